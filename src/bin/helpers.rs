@@ -17,11 +17,12 @@
 
 use clap::{Arg, ArgAction, ArgMatches, Command};
 use console::style;
-use crowbook::Book;
+use crowbook::{Book, EpubImport, PandocConversion};
 
 use std::env;
 use std::fs;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 static BIRD: &str = "🐦 ";
@@ -120,10 +121,9 @@ pub fn set_book_options(book: &mut Book, matches: &ArgMatches) -> String {
     output
 }
 
-/// create a book file with the command line arguments
-/// and exit the process at the end
-pub fn create_book(matches: &ArgMatches) -> ! {
-    let mut f: Box<dyn Write> = if let Some(book) = matches.get_one::<String>("BOOK") {
+/// Opens the book file that will be created, or stdout if it wasn't specified
+fn create_book_file(matches: &ArgMatches) -> Box<dyn Write> {
+    if let Some(book) = matches.get_one::<String>("BOOK") {
         if fs::metadata(book).is_ok() {
             print_error_and_exit(
                 &lformat!("Could not create file {}: it already exists!", book),
@@ -133,7 +133,13 @@ pub fn create_book(matches: &ArgMatches) -> ! {
         Box::new(fs::File::create(book).unwrap())
     } else {
         Box::new(io::stdout())
-    };
+    }
+}
+
+/// create a book file with the command line arguments
+/// and exit the process at the end
+pub fn create_book(matches: &ArgMatches) -> ! {
+    let mut f = create_book_file(matches);
 
     if let Some(values) = matches.get_many::<String>("files") {
         if matches.get_many::<String>("set").is_some() {
@@ -181,6 +187,81 @@ lang: en
     }
 }
 
+/// create a book file from a pandoc metadata or defaults file
+/// and exit the process at the end
+pub fn import_pandoc(matches: &ArgMatches, emoji: bool) -> ! {
+    // ok to unwrap since clap requires at least one value
+    let mut values = matches.get_many::<String>("import-pandoc").unwrap();
+    let pandoc_file = values.next().unwrap();
+    let files: Vec<String> = values.cloned().collect();
+
+    let yaml = fs::read_to_string(pandoc_file).unwrap_or_else(|_| {
+        print_error_and_exit(&lformat!("Could not read file {}", pandoc_file), emoji)
+    });
+    let root = Path::new(pandoc_file)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let conversion = PandocConversion::from_pandoc(&yaml, root, &files)
+        .unwrap_or_else(|err| print_error_and_exit(&format!("{err}"), emoji));
+    for key in &conversion.unmapped {
+        print_warning(
+            &lformat!("pandoc option '{}' has no equivalent and was ignored", key),
+            emoji,
+        );
+    }
+
+    let mut f = create_book_file(matches);
+    f.write_all(conversion.content.as_bytes()).unwrap();
+    if let Some(s) = matches.get_one::<String>("BOOK") {
+        println!("{}", lformat!("Created {} from {}", s, pandoc_file));
+    }
+    exit(0);
+}
+
+/// Print a pandoc defaults file equivalent to the book configuration
+/// and exit the process at the end
+pub fn export_pandoc(book: &Book, emoji: bool) -> ! {
+    match PandocConversion::to_pandoc(book) {
+        Ok(conversion) => {
+            for key in &conversion.unmapped {
+                print_warning(
+                    &lformat!("option '{}' has no pandoc equivalent and was ignored", key),
+                    emoji,
+                );
+            }
+            print!("{}", conversion.content);
+            exit(0);
+        }
+        Err(err) => print_error_and_exit(&format!("{err}"), emoji),
+    }
+}
+
+/// create a new book from an EPUB file
+/// and exit the process at the end
+pub fn import_epub(matches: &ArgMatches, emoji: bool) -> ! {
+    // ok to unwrap since clap requires a value
+    let epub = matches.get_one::<String>("import").unwrap();
+    let book_file = if let Some(book) = matches.get_one::<String>("BOOK") {
+        PathBuf::from(book)
+    } else {
+        // By default, create foo/foo.book for foo.epub
+        let stem = Path::new(epub).file_stem().unwrap_or_default();
+        Path::new(stem).join(stem).with_extension("book")
+    };
+
+    let mut book = Book::new();
+    book.set_options(&get_book_options(matches));
+    let result = EpubImport::new(epub, &book).and_then(|import| import.write(&book_file));
+    if let Err(err) = result {
+        print_error_and_exit(&format!("{err}"), emoji);
+    }
+    println!(
+        "{}",
+        lformat!("Created {} from {}", book_file.display(), epub)
+    );
+    exit(0);
+}
+
 pub fn create_matches() -> ArgMatches {
     app().get_matches()
 }
@@ -195,6 +276,9 @@ fn app() -> clap::Command {
         static ref QUIET: String = lformat!("Don't print info/error messages");
         static ref PROOFREAD: String = lformat!("Enable proofreading");
         static ref CREATE: String = lformat!("Create a new book with existing Markdown files");
+        static ref IMPORT_PANDOC: String = lformat!("Create a new book from a pandoc metadata or defaults file, and optional Markdown files");
+        static ref EXPORT_PANDOC: String = lformat!("Print a pandoc defaults file equivalent to the book configuration");
+        static ref IMPORT: String = lformat!("Create a new book from an existing EPUB file");
         static ref AUTOGRAPH: String = lformat!("Prompts for an autograph for this book");
         static ref OUTPUT: String = lformat!("Specify output file");
         static ref LANG: String = lformat!("Set the runtime language used by Crowbook");
@@ -284,6 +368,28 @@ ARGS:
                 .num_args(1..)
                 .help(CREATE.as_str()),
         )
+        .arg(
+            Arg::new("import-pandoc")
+                .long("import-pandoc")
+                .action(ArgAction::Set)
+                .num_args(1..)
+                .conflicts_with("files")
+                .help(IMPORT_PANDOC.as_str()),
+        )
+        .arg(
+            Arg::new("export-pandoc")
+                .long("export-pandoc")
+                .action(ArgAction::SetTrue)
+                .help(EXPORT_PANDOC.as_str()),
+        )
+        .arg(
+            Arg::new("import")
+                .long("import")
+                .action(ArgAction::Set)
+                .num_args(1)
+                .conflicts_with_all(["files", "import-pandoc"])
+                .help(IMPORT.as_str()),
+        )
         .arg(
             Arg::new("output")
                 .short('o')
@@ -300,10 +406,17 @@ ARGS:
                 .action(ArgAction::Set)
                 .value_parser([
                     "epub",
+                    "kindle.epub",
                     "pdf",
                     "html",
                     "tex",
                     "odt",
+                    "md",
+                    "txt",
+                    "typ",
+                    "manuscript.tex",
+                    "manuscript.pdf",
+                    "cover.print",
                     "html.dir",
                     "proofread.html",
                     "proofread.html.dir",
@@ -91,7 +91,10 @@ pub fn try_main() -> Result<()> {
         exit(0);
     }
 
-    if matches.get_flag("no-fancy") || matches.get_flag("stats") {
+    if matches.get_flag("no-fancy")
+        || matches.get_flag("stats")
+        || matches.get_flag("export-pandoc")
+    {
         fancy_ui = false;
         emoji = false;
     }
@@ -120,6 +123,12 @@ pub fn try_main() -> Result<()> {
     if matches.get_many::<String>("files").is_some() {
         create_book(&matches);
     }
+    if matches.get_many::<String>("import-pandoc").is_some() {
+        import_pandoc(&matches, emoji);
+    }
+    if matches.get_one::<String>("import").is_some() {
+        import_epub(&matches, emoji);
+    }
     let book = matches.get_one::<String>("BOOK");
     if book.is_none() {
         print_error_and_exit(
@@ -219,6 +228,10 @@ pub fn try_main() -> Result<()> {
 
         set_book_options(&mut book, &matches);
 
+        if matches.get_flag("export-pandoc") {
+            export_pandoc(&book, emoji);
+        }
+
         if matches.get_flag("stats") {
             let stats = Stats::new(&book, matches.get_flag("verbose"));
             println!("{stats}");
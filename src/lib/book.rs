@@ -18,15 +18,17 @@
 use crate::book_bars::Bars;
 use crate::book_renderer::BookRenderer;
 use crate::bookoptions::BookOptions;
-use crate::chapter::Chapter;
+use crate::chapter::{Chapter, Matter};
 use crate::cleaner::{Cleaner, CleanerParams, Default, French, Off};
-use crate::epub::Epub;
+use crate::cover::CoverPrint;
+use crate::epub::{Epub, KindleEpub};
 use crate::error::{Error, Result, Source};
 use crate::html_dir::{HtmlDir, ProofHtmlDir};
 use crate::html_if::HtmlIf;
 use crate::html_single::{HtmlSingle, ProofHtmlSingle};
 use crate::lang;
-use crate::latex::{Latex, Pdf, ProofLatex, ProofPdf};
+use crate::latex::{Latex, Manuscript, ManuscriptPdf, Pdf, ProofLatex, ProofPdf};
+use crate::markdown::Markdown;
 use crate::misc;
 use crate::number::Number;
 #[cfg(feature = "odt")]
@@ -34,9 +36,13 @@ use crate::odt::Odt;
 use crate::parser::Features;
 use crate::parser::Parser;
 use crate::resource_handler::ResourceHandler;
-use crate::templates::{epub, epub3, highlight, html, html_dir, html_if, html_single, latex};
+use crate::templates::{
+    epub, epub3, highlight, html, html_dir, html_if, html_single, latex, typst,
+};
 use crate::text_view::view_as_text;
-use crate::token::Token;
+use crate::token::{Condition, Token};
+use crate::txt::Txt;
+use crate::typst::Typst;
 
 #[cfg(feature = "proofread")]
 use crate::grammalecte::GrammalecteChecker;
@@ -75,8 +81,9 @@ impl RepetitionDetector {
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::env;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::iter::IntoIterator;
 use std::path::{Path, PathBuf};
@@ -86,13 +93,15 @@ use numerals::roman::Roman;
 use rayon::prelude::*;
 use yaml_rust::{Yaml, YamlLoader};
 
-/// Type of header (part or chapter)
+/// Type of header (part, chapter or appendix)
 #[derive(Copy, Clone, Debug)]
 pub enum Header {
     /// Chapter (default)
     Chapter,
     /// Part (or "book" or "episode" or whatever)
     Part,
+    /// Appendix (a chapter numbered with letters)
+    Appendix,
 }
 
 /// Header data (for chapter or part)
@@ -183,6 +192,10 @@ pub struct Book {
     grammalecte: Option<GrammalecteChecker>,
     detector: Option<RepetitionDetector>,
     formats: HashMap<&'static str, (String, Box<dyn BookRenderer>)>,
+    /// Canonical paths of this book file and of the books including it as a volume
+    book_files: Vec<PathBuf>,
+    /// Division of the book new chapters are added to
+    matter: Matter,
 
     #[doc(hidden)]
     pub bars: Bars,
@@ -203,6 +216,8 @@ impl Book {
             grammalecte: None,
             detector: None,
             formats: HashMap::new(),
+            book_files: vec![],
+            matter: Matter::Main,
             features: Features::new(),
             bars: Bars::new(),
         };
@@ -239,6 +254,29 @@ impl Book {
             Box::new(ProofPdf {}),
         )
         .add_format("epub", lformat!("EPUB"), Box::new(Epub {}))
+        .add_format(
+            "kindle.epub",
+            lformat!("EPUB (for Kindle)"),
+            Box::new(KindleEpub {}),
+        )
+        .add_format("md", lformat!("Markdown"), Box::new(Markdown {}))
+        .add_format("txt", lformat!("Plain text"), Box::new(Txt {}))
+        .add_format("typ", lformat!("Typst"), Box::new(Typst {}))
+        .add_format(
+            "manuscript.tex",
+            lformat!("LaTeX (standard manuscript format)"),
+            Box::new(Manuscript {}),
+        )
+        .add_format(
+            "manuscript.pdf",
+            lformat!("PDF (standard manuscript format)"),
+            Box::new(ManuscriptPdf {}),
+        )
+        .add_format(
+            "cover.print",
+            lformat!("PDF (print cover)"),
+            Box::new(CoverPrint {}),
+        )
         .add_format(
             "html.if",
             lformat!("HTML (interactive fiction)"),
@@ -352,6 +390,11 @@ impl Book {
         let f = File::open(path.as_ref()).map_err(|_| {
             Error::file_not_found(Source::empty(), lformat!("book"), filename.clone())
         })?;
+        self.book_files.push(
+            path.as_ref()
+                .canonicalize()
+                .unwrap_or_else(|_| path.as_ref().to_owned()),
+        );
         // Set book path to book's directory
         if let Some(parent) = path.as_ref().parent() {
             self.root = parent.to_owned();
@@ -515,13 +558,23 @@ impl Book {
     /// ```
     pub fn read_config<R: Read>(&mut self, mut source: R) -> Result<&mut Book> {
         fn get_filename<'a>(source: &Source, s: &'a str) -> Result<&'a str> {
-            let words: Vec<&str> = (s[1..]).split_whitespace().collect();
+            let s = s[1..].trim();
+            if let Some(quoted) = s.strip_prefix('"') {
+                return match quoted.split_once('"') {
+                    Some((name, rest)) if !name.is_empty() && rest.trim().is_empty() => Ok(name),
+                    _ => Err(Error::config_parser(
+                        source,
+                        lformat!("ill-formatted quoted chapter filename"),
+                    )),
+                };
+            }
+            let words: Vec<&str> = s.split_whitespace().collect();
             if words.len() > 1 {
                 return Err(Error::config_parser(
                     source,
                     lformat!(
-                        "chapter filenames must not contain \
-                                                          whitespace"
+                        "chapter filenames containing whitespace \
+                                                          must be quoted"
                     ),
                 ));
             } else if words.is_empty() {
@@ -553,7 +606,7 @@ impl Book {
 
         while let Some(next_line) = lines.peek() {
             if next_line.starts_with(|c| match c {
-                '-' | '+' | '!' | '@' => true,
+                '-' | '+' | '!' | '@' | '[' => true,
                 _ => c.is_ascii_digit(),
             }) {
                 break;
@@ -642,19 +695,27 @@ impl Book {
                 assert!(level > 1);
                 level -= 1;
                 let file = get_filename(&self.source, &line[level..])?;
-                self.add_subchapter(level as i32, file)?;
+                for file in expand_chapter_files(&self.root, &self.source, file)? {
+                    self.add_subchapter(level as i32, &file)?;
+                }
             } else if line.starts_with('-') {
                 // unnumbered chapter
                 let file = get_filename(&self.source, line)?;
-                self.add_chapter(Number::Unnumbered, file, false)?;
+                for file in expand_chapter_files(&self.root, &self.source, file)? {
+                    self.add_chapter(Number::Unnumbered, &file, false)?;
+                }
             } else if line.starts_with('+') {
                 // numbered chapter
                 let file = get_filename(&self.source, line)?;
-                self.add_chapter(Number::Default, file, true)?;
+                for file in expand_chapter_files(&self.root, &self.source, file)? {
+                    self.add_chapter(Number::Default, &file, true)?;
+                }
             } else if line.starts_with('!') {
                 // hidden chapter
                 let file = get_filename(&self.source, line)?;
-                self.add_chapter(Number::Hidden, file, false)?;
+                for file in expand_chapter_files(&self.root, &self.source, file)? {
+                    self.add_chapter(Number::Hidden, &file, false)?;
+                }
             } else if line.starts_with(|c: char| c.is_ascii_digit()) {
                 // chapter with specific number
                 let parts: Vec<_> = line
@@ -676,23 +737,54 @@ impl Book {
                         lformat!("error parsing chapter number: {error}", error = err),
                     )
                 })?;
-                self.add_chapter(Number::Specified(number), file, true)?;
+                // Following chapters are numbered from this one
+                for (i, file) in expand_chapter_files(&self.root, &self.source, file)?
+                    .iter()
+                    .enumerate()
+                {
+                    let number = if i == 0 {
+                        Number::Specified(number)
+                    } else {
+                        Number::Default
+                    };
+                    self.add_chapter(number, file, true)?;
+                }
+            } else if line.starts_with('[') {
+                // Division of the book
+                let matter = match line {
+                    "[frontmatter]" => Matter::Front,
+                    "[mainmatter]" => Matter::Main,
+                    "[appendix]" => Matter::Appendix,
+                    "[backmatter]" => Matter::Back,
+                    _ => {
+                        return Err(Error::config_parser(
+                            &self.source,
+                            lformat!(
+                                "found invalid marker {marker} in the chapter list, expected \
+                                 [frontmatter], [mainmatter], [appendix] or [backmatter]",
+                                marker = line
+                            ),
+                        ))
+                    }
+                };
+                self.set_matter(matter);
             } else if let Some(subline) = line.strip_prefix('@') {
                 /* Part */
                 if subline.starts_with(|c: char| c.is_whitespace()) {
                     let subline = subline.trim();
                     let ast = Parser::from(self).parse_inline(subline)?;
                     let ast = vec![Token::Header(1, ast)];
-                    self.chapters
-                        .push(Chapter::new(Number::DefaultPart, String::new(), ast));
+                    let mut chapter = Chapter::new(Number::DefaultPart, String::new(), ast);
+                    chapter.matter = self.matter;
+                    self.chapters.push(chapter);
                 } else if subline.starts_with('-') {
                     /* Unnumbered part */
                     let file = get_filename(&self.source, subline)?;
-                    self.add_chapter(Number::UnnumberedPart, file, true)?;
+                    self.add_part(Number::UnnumberedPart, file)?;
                 } else if subline.starts_with('+') {
                     /* Numbered part */
                     let file = get_filename(&self.source, subline)?;
-                    self.add_chapter(Number::DefaultPart, file, true)?;
+                    self.add_part(Number::DefaultPart, file)?;
                 } else if subline.starts_with(|c: char| c.is_ascii_digit()) {
                     /* Specified  part*/
                     let parts: Vec<_> = subline
@@ -714,7 +806,7 @@ impl Book {
                             lformat!("error parsing part number: {error}", error = err),
                         )
                     })?;
-                    self.add_chapter(Number::SpecifiedPart(number), file, true)?;
+                    self.add_part(Number::SpecifiedPart(number), file)?;
                 } else {
                     return Err(Error::config_parser(
                         &self.source,
@@ -739,6 +831,112 @@ impl Book {
         Ok(self)
     }
 
+    /// Sets the division of the book (front matter, main matter, appendices or
+    /// back matter) the chapters added after this call belong to.
+    ///
+    /// Numbered chapters of the front and back matter become unnumbered, while
+    /// numbered chapters of the appendices are numbered with letters.
+    pub fn set_matter(&mut self, matter: Matter) -> &mut Self {
+        self.matter = matter;
+        self
+    }
+
+    /// Adds a part from a line of the chapter list.
+    ///
+    /// If `file` is a glob pattern or a directory, the first matching file is the
+    /// part and the other ones are its (numbered) chapters.
+    fn add_part(&mut self, number: Number, file: &str) -> Result<&mut Self> {
+        let files = expand_chapter_files(&self.root, &self.source, file)?;
+        if files[0].ends_with(".book") {
+            // Each book file is a volume
+            for (i, file) in files.iter().enumerate() {
+                let number = match number {
+                    Number::UnnumberedPart => number,
+                    _ if i == 0 => number,
+                    _ => Number::DefaultPart,
+                };
+                self.add_volume(number, file)?;
+            }
+            return Ok(self);
+        }
+        self.add_chapter(number, &files[0], true)?;
+        for file in &files[1..] {
+            self.add_chapter(Number::Default, file, true)?;
+        }
+        Ok(self)
+    }
+
+    /// Adds another book as a volume, that is, a part (with a title page made
+    /// from the volume's metadata) followed by the chapters of this book
+    fn add_volume(&mut self, number: Number, file: &str) -> Result<&mut Self> {
+        let path = self.root.join(file);
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.book_files.contains(&canonical) {
+            return Err(Error::config_parser(
+                &self.source,
+                lformat!(
+                    "book {file} includes itself as a volume (directly or indirectly)",
+                    file = misc::normalize(&path)
+                ),
+            ));
+        }
+        // Images of the volume are looked up relatively to its own directory
+        // within this book's image base path, unless the volume sets its own
+        let offset = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+        let images = Path::new(&self.options.get_path("resources.base_path.images")?).join(offset);
+        let images = env::current_dir()
+            .map(|dir| dir.join(&images))
+            .unwrap_or(images);
+        let mut volume = Book::new();
+        volume.book_files = self.book_files.clone();
+        volume.options.set(
+            "resources.base_path.images",
+            &format!("{}", images.display()),
+        )?;
+        volume.load_file(&path)?;
+        if volume.chapters.iter().any(|c| c.number.is_part()) {
+            return Err(Error::config_parser(
+                &self.source,
+                lformat!(
+                    "volume {file} contains parts, which is not supported",
+                    file = misc::normalize(&path)
+                ),
+            ));
+        }
+
+        // Title page of the volume
+        let mut parser = Parser::from(self);
+        let title = match volume.options.get_str("title") {
+            Ok(title) => title.to_owned(),
+            Err(_) => Path::new(file)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        let mut content = vec![Token::Header(1, parser.parse_inline(&title)?)];
+        if let Ok(subtitle) = volume.options.get_str("subtitle") {
+            content.push(Token::Paragraph(vec![Token::Emphasis(
+                parser.parse_inline(subtitle)?,
+            )]));
+        }
+        if let Ok(author) = volume.options.get_str("author") {
+            content.push(Token::Paragraph(parser.parse_inline(author)?));
+        }
+        let mut chapter = Chapter::new(number, String::new(), content);
+        chapter.matter = self.matter;
+        self.chapters.push(chapter);
+
+        // Chapters of the volume, with local links made relative to this book
+        // (image paths were already resolved when loading the volume)
+        for mut chapter in std::mem::take(&mut volume.chapters) {
+            ResourceHandler::add_offset(offset, Path::new(""), &mut chapter.content);
+            chapter.filename = format!("{}", offset.join(&chapter.filename).display());
+            self.chapters.push(chapter);
+        }
+        self.features = self.features | volume.features;
+        Ok(self)
+    }
+
     /// Determine whether proofreading is activated or not
     fn is_proofread(&self) -> bool {
         self.options.get_bool("proofread").unwrap()
@@ -1068,9 +1266,10 @@ impl Book {
         parser.set_source_file(file);
         let mut yaml_block = String::from("");
         let mut tokens = parser.parse(&content, Option::Some(&mut yaml_block))?;
+        let mut lines = parser.lines().to_vec();
 
         // Parse YAML block
-        self.parse_yaml(&yaml_block);
+        let metadata = self.parse_yaml(&yaml_block);
         self.features = self.features | parser.features();
 
         // transform the AST to make local links and images relative to `book` directory
@@ -1119,7 +1318,11 @@ impl Book {
 
         // Add a title if there is none in the chapter (unless this is subchapter)
         if add_title_if_empty {
+            let len = tokens.len();
             misc::insert_title(&mut tokens);
+            if tokens.len() > len && !lines.is_empty() {
+                lines.insert(0, 1);
+            }
         }
 
         // If one of the renderers requires it, perform grammarcheck
@@ -1194,7 +1397,37 @@ impl Book {
         }
         self.bar_set_message(Crowbar::Second, "");
 
-        self.chapters.push(Chapter::new(number, file, tokens));
+        // A chapter can opt out of numbering in its front matter
+        let number = match (metadata.get("numbered").map(String::as_str), number) {
+            (Some("false"), Number::Default) | (Some("false"), Number::Specified(_)) => {
+                Number::Unnumbered
+            }
+            (Some("false"), Number::DefaultPart) | (Some("false"), Number::SpecifiedPart(_)) => {
+                Number::UnnumberedPart
+            }
+            (_, number) => number,
+        };
+        let number = match (self.matter, number) {
+            (Matter::Front, Number::Default)
+            | (Matter::Front, Number::Specified(_))
+            | (Matter::Back, Number::Default)
+            | (Matter::Back, Number::Specified(_)) => Number::Unnumbered,
+            (Matter::Appendix, Number::Default) => {
+                if self.chapters.iter().any(|c| c.number.is_appendix()) {
+                    Number::DefaultAppendix
+                } else {
+                    // First appendix: restart numbering
+                    Number::SpecifiedAppendix(1)
+                }
+            }
+            (Matter::Appendix, Number::Specified(n)) => Number::SpecifiedAppendix(n),
+            (_, number) => number,
+        };
+        let mut chapter = Chapter::new(number, file, tokens);
+        chapter.metadata = metadata;
+        chapter.matter = self.matter;
+        chapter.lines = lines;
+        self.chapters.push(chapter);
 
         Ok(self)
     }
@@ -1295,6 +1528,15 @@ impl Book {
         self.cleaner.clean(text.into())
     }
 
+    /// Returns true if a conditional block with these conditions must be
+    /// rendered by a renderer producing `format`, according to the
+    /// `rendering.flags` option
+    #[doc(hidden)]
+    pub fn is_rendered(&self, conditions: &[Condition], format: &str) -> bool {
+        let flags = self.options.get_str_vec("rendering.flags").unwrap_or(&[]);
+        conditions.iter().all(|c| c.holds(format, flags))
+    }
+
     /// Returns a template
     ///
     /// Returns the default one if no option was set, or the one set by the user.
@@ -1324,6 +1566,9 @@ impl Book {
             "html.if.js" => html_if::JS,
             "html.if.new_game" => html_if::NEW_GAME,
             "tex.template" => latex::TEMPLATE,
+            "tex.manuscript.template" => latex::MANUSCRIPT,
+            "tex.cover.template" => latex::COVER,
+            "typst.template" => typst::TEMPLATE,
             _ => {
                 return Err(Error::config_parser(
                     &self.source,
@@ -1359,10 +1604,22 @@ impl Book {
         Ok(())
     }
 
-    /// Returns the formatted (roman or arabic) number of chapter
+    /// Returns the formatted (roman, arabic or, for appendices, letter) number of chapter
     #[doc(hidden)]
     pub fn get_header_number(&self, header: Header, n: i32) -> Result<String> {
         let boolean = match header {
+            Header::Appendix => {
+                if n <= 0 {
+                    return Err(Error::render(
+                        Source::empty(),
+                        lformat!(
+                            "can not use letters with zero or negative appendix numbers ({n})",
+                            n = n
+                        ),
+                    ));
+                }
+                return Ok(misc::to_letters(n as u32));
+            }
             Header::Part => self
                 .options
                 .get_bool("rendering.part.roman_numerals")
@@ -1401,9 +1658,11 @@ impl Book {
     where
         F: FnMut(&str) -> Result<String>,
     {
-        let header_type = match header {
-            Header::Part => "part",
-            Header::Chapter => "chapter",
+        // Appendices use the chapter template but are called differently
+        let (header_type, name) = match header {
+            Header::Part => ("part", "part"),
+            Header::Chapter => ("chapter", "chapter"),
+            Header::Appendix => ("chapter", "appendix"),
         };
         let mut data = self.get_metadata(&mut f)?;
         if !title.is_empty() {
@@ -1412,9 +1671,9 @@ impl Book {
         let number = self.get_header_number(header, n)?;
         let header_name = self
             .options
-            .get_str(&format!("rendering.{header_type}"))
+            .get_str(&format!("rendering.{name}"))
             .map(|s| s.to_owned())
-            .unwrap_or_else(|_| lang::get_str(self.options.get_str("lang").unwrap(), header_type));
+            .unwrap_or_else(|_| lang::get_str(self.options.get_str("lang").unwrap(), name));
 
         data = data
             .insert_str(format!("{header_type}_title"), title.clone())
@@ -1425,7 +1684,7 @@ impl Book {
 
         let opt_template = match header {
             Header::Part => &self.part_template,
-            Header::Chapter => &self.chapter_template,
+            Header::Chapter | Header::Appendix => &self.chapter_template,
         };
 
         if let Some(ref template) = *opt_template {
@@ -1541,6 +1800,54 @@ impl Book {
         Ok(mapbuilder)
     }
 
+    /// Completes a `MapBuilder` with the metadata of chapter `i`, set in its YAML front matter.
+    ///
+    /// Each key `xxx` is inserted as `chapter_xxx` (and `has_chapter_xxx`); `class` and
+    /// `numbered` are inserted verbatim while other values are rendered with `f`.
+    /// The division of the book the chapter belongs to is inserted as `chapter_matter`.
+    #[doc(hidden)]
+    pub fn get_chapter_metadata<F>(
+        &self,
+        i: usize,
+        mut mapbuilder: MapBuilder,
+        mut f: F,
+    ) -> Result<MapBuilder>
+    where
+        F: FnMut(&str) -> Result<String>,
+    {
+        for key in &["author", "subtitle", "epigraph", "date", "class"] {
+            mapbuilder = mapbuilder.insert_bool(format!("has_chapter_{key}"), false);
+        }
+        if let Some(chapter) = self.chapters.get(i) {
+            mapbuilder = mapbuilder.insert_str("chapter_matter", chapter.matter.to_string());
+        }
+        let metadata = self
+            .chapters
+            .get(i)
+            .map(|c| &c.metadata)
+            .into_iter()
+            .flatten();
+        for (key, value) in metadata {
+            let content = match key.as_ref() {
+                "class" | "numbered" | "audio" | "audio.timing" => value.clone(),
+                _ => f(value).map_err(|err| {
+                    Error::render(
+                        &self.source,
+                        lformat!(
+                            "could not render `{key}` for chapter metadata:\n{error}",
+                            key = key,
+                            error = err
+                        ),
+                    )
+                })?,
+            };
+            let key = key.replace('.', "_");
+            mapbuilder = mapbuilder.insert_bool(format!("has_chapter_{key}"), !content.is_empty());
+            mapbuilder = mapbuilder.insert_str(format!("chapter_{key}"), content);
+        }
+        Ok(mapbuilder)
+    }
+
     /// Remove YAML blocks from a string and try to parse them to set options
     ///
     /// YAML blocks start with
@@ -1549,7 +1856,11 @@ impl Book {
     /// ---
     /// or
     /// ...
-    fn parse_yaml(&mut self, yaml_block: &String) {
+    ///
+    /// Keys in the `chapter` section (either `chapter.author: ...` or a `chapter:` hash)
+    /// don't set options but are returned as the chapter's metadata.
+    fn parse_yaml(&mut self, yaml_block: &String) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
         // Checks that this is valid YAML
         match YamlLoader::load_from_str(yaml_block) {
             Ok(docs) => {
@@ -1558,6 +1869,42 @@ impl Book {
                 if !docs.is_empty() && docs[0].as_hash().is_some() {
                     let hash = docs[0].as_hash().unwrap();
                     for (key, value) in hash {
+                        let entries = match (key.as_str(), value) {
+                            (Some("chapter"), Yaml::Hash(inner)) => inner
+                                .iter()
+                                .map(|(k, v)| (k.as_str().map(String::from), v))
+                                .collect(),
+                            (Some(key), value) if key.starts_with("chapter.") => {
+                                vec![(Some(key["chapter.".len()..].to_owned()), value)]
+                            }
+                            _ => vec![],
+                        };
+                        if !entries.is_empty() {
+                            for (k, v) in entries {
+                                let s = match v {
+                                    Yaml::String(s) => Some(s.clone()),
+                                    Yaml::Integer(i) => Some(i.to_string()),
+                                    Yaml::Real(r) => Some(r.clone()),
+                                    Yaml::Boolean(b) => Some(b.to_string()),
+                                    _ => None,
+                                };
+                                match (k, s) {
+                                    (Some(k), Some(s)) => {
+                                        metadata.insert(k, s);
+                                    }
+                                    (k, _) => error!(
+                                        "{}",
+                                        lformat!(
+                                            "Inline YAML block: chapter metadata {:?} \
+                                             must be a string, found {:?}",
+                                            k,
+                                            v
+                                        )
+                                    ),
+                                }
+                            }
+                            continue;
+                        }
                         match self
                             .options
                             //todo: remove clone
@@ -1638,6 +1985,7 @@ impl Book {
                 );
             }
         }
+        metadata
     }
 
     // Update the cleaner according to autoclean and lang options
@@ -1692,3 +2040,67 @@ where
         )),
     }
 }
+
+/// Expands a glob pattern or a directory of the chapter list to the list of
+/// matching (Markdown) files, in natural order
+fn expand_chapter_files(root: &Path, source: &Source, name: &str) -> Result<Vec<String>> {
+    let list_dir = |dir: &Path| -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(root.join(dir))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        names.sort_by(|a, b| misc::natural_cmp(a, b));
+        names
+    };
+    let files: Vec<PathBuf> = if misc::is_glob(name) {
+        let mut candidates = vec![PathBuf::new()];
+        for component in Path::new(name).components() {
+            let component = component.as_os_str().to_string_lossy();
+            let mut next = vec![];
+            for prefix in &candidates {
+                if misc::is_glob(&component) {
+                    next.extend(
+                        list_dir(prefix)
+                            .iter()
+                            .filter(|name| misc::glob_match(&component, name))
+                            .map(|name| prefix.join(name)),
+                    );
+                } else {
+                    next.push(prefix.join(component.as_ref()));
+                }
+            }
+            candidates = next;
+        }
+        candidates
+            .into_iter()
+            .filter(|file| root.join(file).is_file())
+            .collect()
+    } else if root.join(name).is_dir() {
+        list_dir(Path::new(name))
+            .iter()
+            .map(|file| Path::new(name).join(file))
+            .filter(|file| {
+                root.join(file).is_file()
+                    && matches!(
+                        file.extension().and_then(|e| e.to_str()),
+                        Some("md") | Some("markdown")
+                    )
+            })
+            .collect()
+    } else {
+        return Ok(vec![name.to_owned()]);
+    };
+    if files.is_empty() {
+        return Err(Error::config_parser(
+            source,
+            lformat!("no chapter file matches '{pattern}'", pattern = name),
+        ));
+    }
+    Ok(files
+        .iter()
+        .map(|file| format!("{}", file.display()))
+        .collect())
+}
@@ -3,7 +3,7 @@ use crate::bookoption::BookOption;
 use crate::error::{Error, Result, Source};
 use crate::style;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::mem;
 use std::path::{Path, PathBuf};
@@ -29,29 +29,43 @@ autograph:meta                      # {autograph}
 # {output_opt}
 output:strvec                       # {output}
 output.epub:path                    # {output_epub}
+output.kindle.epub:path             # {output_kindle_epub}
 output.html:path                    # {output_html}
 output.html.dir:path                # {output_html_dir}
 output.tex:path                     # {output_tex}
 output.pdf:path                     # {output_pdf}
 output.odt:path                     # {output_odt}
 output.html.if:path                 # {output_if}
+output.md:path                      # {output_md}
+output.txt:path                     # {output_txt}
+output.typ:path                     # {output_typ}
+output.manuscript.tex:path          # {output_manuscript_tex}
+output.manuscript.pdf:path          # {output_manuscript_pdf}
+output.cover.print:path             # {output_cover_print}
 output.base_path:path:\"\"            # {output_base_path}
 
 # {render_opt}
 rendering.highlight:str:syntect                                      # {rendering_highlight}
 rendering.highlight.theme:str:InspiredGitHub                         # {rendering_highlight_theme}
+rendering.highlight.syntaxes:path                                    # {rendering_highlight_syntaxes}
+rendering.highlight.themes:path                                      # {rendering_highlight_themes}
+rendering.code.line_numbers:bool:false                               # {rendering_code_line_numbers}
 rendering.initials:bool:false                                        # {rendering_initials}
 rendering.inline_toc:bool:false                                      # {inline_toc}
 rendering.inline_toc.name:str:\"{{{{{{loc_toc}}}}}}\"                        # {toc_name}
 rendering.num_depth:int:1                                            # {num_depth}
 rendering.chapter:str                                                # {chapter}
 rendering.part:str                                                   # {part}
+rendering.appendix:str                                               # {appendix}
 rendering.chapter.roman_numerals:bool:false                                  # {roman_numerals_chapters}
 rendering.part.roman_numerals:bool:true                                      # {roman_numerals_parts}
 rendering.part.reset_counter:bool:true                                      # {reset_counter}
 rendering.chapter.template:str:\"{{{{{{number}}}}}}. {{{{{{chapter_title}}}}}}\" # {chapter_template}
 
 rendering.part.template:str:\"{{{{{{number}}}}}}. {{{{{{part_title}}}}}}\" # {part_template}
+rendering.flags:strvec                                               # {rendering_flags}
+rendering.scene_break:str:\"* * *\"                                    # {scene_break}
+rendering.scene_break.image:path                                     # {scene_break_image}
 
 
 
@@ -73,7 +87,9 @@ html.highlight.js:tpl               # {highlight_js}
 html.highlight.css:tpl              # {highlight_css}
 html.side_notes:bool:false          # {side_notes}
 html.escape_nb_spaces:bool:true     # {nb_spaces}
-html.chapter.template:str:\"<h1 id = 'link-{{{{{{link}}}}}}'>{{{{#has_number}}}}<span class = 'chapter-header'>{{{{{{header}}}}}} {{{{{{number}}}}}}</span>{{{{#has_title}}}}<br />{{{{/has_title}}}}{{{{/has_number}}}}{{{{{{title}}}}}}</h1>\" # {html_chapter_template}
+html.fonts:strvec                   # {html_fonts}
+html.fonts.subset:bool:false        # {fonts_subset}
+html.chapter.template:str:\"<h1 id = 'link-{{{{{{link}}}}}}'>{{{{#has_number}}}}<span class = 'chapter-header'>{{{{{{header}}}}}} {{{{{{number}}}}}}</span>{{{{#has_title}}}}<br />{{{{/has_title}}}}{{{{/has_number}}}}{{{{{{title}}}}}}</h1>{{{{#has_chapter_subtitle}}}}<p class = 'chapter-subtitle'>{{{{{{chapter_subtitle}}}}}}</p>{{{{/has_chapter_subtitle}}}}{{{{#has_chapter_author}}}}<p class = 'chapter-author'>{{{{{{chapter_author}}}}}}</p>{{{{/has_chapter_author}}}}{{{{#has_chapter_epigraph}}}}<p class = 'chapter-epigraph'>{{{{{{chapter_epigraph}}}}}}</p>{{{{/has_chapter_epigraph}}}}\" # {html_chapter_template}
 html.part.template:str:\"<h2 class = 'part'>{{{{{{header}}}}}} {{{{{{number}}}}}}</h2> <h1 id = 'link-{{{{{{link}}}}}}' class = 'part'>{{{{{{title}}}}}}</h1>\" # {html_part_template}
 
 # {html_single_opt}
@@ -93,15 +109,29 @@ html.if.new_game:tpl                # {if_new_game}
 
 # {epub_opt}
 epub.version:int:2                  # {epub_ver}
+epub.profile:str:generic            # {epub_profile}
 epub.highlight.theme:str            # {epub_theme}
 epub.css:tpl                        # {epub_css}
 epub.css.add:str                    # {epub_css_add}
 epub.chapter.xhtml:tpl              # {chapter_xhtml}
 epub.toc.extras:bool:true           # {epub_toc}
 epub.escape_nb_spaces:bool:true     # {nb_spaces}
+epub.accessibility.mode:strvec      # {epub_access_mode}
+epub.accessibility.features:strvec  # {epub_access_features}
+epub.accessibility.summary:str      # {epub_access_summary}
+epub.identifier:str                 # {epub_identifier}
+epub.fonts:strvec                   # {epub_fonts}
+epub.fonts.subset:bool:false        # {fonts_subset}
+epub.fonts.obfuscate:bool:false     # {epub_fonts_obfuscate}
+epub.layout:str:reflowable          # {epub_layout}
+epub.layout.pages:str:chapter       # {epub_layout_pages}
+epub.layout.width:int:1200          # {epub_layout_width}
+epub.layout.height:int:1600         # {epub_layout_height}
+epub.layout.spread:str:auto         # {epub_layout_spread}
 
 # {tex_opt}
 tex.highlight.theme:str             # {tex_theme}
+tex.code.wrap:bool:true             # {tex_code_wrap}
 tex.links_as_footnotes:bool:true    # {tex_links}
 tex.command:str:xelatex             # {tex_command}
 tex.escape_nb_spaces:bool:true      # {nb_spaces_tex}
@@ -115,8 +145,47 @@ tex.margin.top:str:\"2cm\"          # {tex_margin_top}
 tex.margin.bottom:str:\"1.5cm\"     # {tex_margin_bottom}
 tex.title:bool:true                 # {tex_title}
 tex.font.size:int                   # {tex_font_size}
+tex.font.main:str                   # {tex_font_main}
+tex.font.sans:str                   # {tex_font_sans}
+tex.font.mono:str                   # {tex_font_mono}
+tex.line_spacing:float              # {tex_line_spacing}
+tex.paragraph.style:str:indent      # {tex_paragraph_style}
+tex.paragraph.indent:str            # {tex_paragraph_indent}
+tex.penalty.widow:int               # {tex_penalty_widow}
+tex.penalty.orphan:int              # {tex_penalty_orphan}
+tex.chapter.style:str:default       # {tex_chapter_style}
+tex.headers:str:page                # {tex_headers}
+tex.initials.lines:int:2            # {tex_initials_lines}
+tex.initials.oversize:float         # {tex_initials_oversize}
+tex.microtype:bool:false            # {tex_microtype}
+tex.microtype.options:str           # {tex_microtype_options}
+tex.classes:strvec                  # {tex_classes}
 tex.hyperref:bool:true              # {tex_hyperref}
 tex.stdpage:bool:false              # {tex_stdpage}
+tex.manuscript.template:tpl         # {tex_manuscript_tmpl}
+tex.manuscript.paper.size:str:letterpaper # {tex_manuscript_paper}
+tex.trim.size:str                   # {tex_trim_size}
+tex.bleed:str                       # {tex_bleed}
+tex.crop_marks:bool:false           # {tex_crop_marks}
+tex.margin.gutter:str               # {tex_margin_gutter}
+tex.pdf.standard:str                # {tex_pdf_standard}
+tex.fonts.check:bool:false          # {tex_fonts_check}
+tex.cover.template:tpl              # {tex_cover_tmpl}
+cover.paper:str:white               # {cover_paper}
+cover.pages:int                     # {cover_pages}
+
+# {typst_opt}
+pdf.engine:str:latex                # {pdf_engine}
+typst.command:str:typst             # {typst_command}
+typst.template:tpl                  # {typst_tmpl}
+typst.template.add:str              # {typst_tmpl_add}
+typst.paper.size:str:a5             # {typst_paper_size}
+typst.font.size:int                 # {typst_font_size}
+typst.title:bool:true               # {typst_title}
+
+# {txt_opt}
+txt.width:int:72                    # {txt_width}
+txt.footnotes:str:end               # {txt_footnotes}
 
 
 # {rs_opt}
@@ -134,6 +203,7 @@ input.clean.smart_quotes:bool:true  # {smart_quotes}
 input.clean.ligature.dashes:bool:false # {ligature_dashes}
 input.clean.ligature.guillemets:bool:false # {ligature_guillemets}
 input.yaml_blocks:bool:false        # {yaml}
+input.scene_breaks:bool:false       # {scene_breaks}
 
 
 # {crowbook_opt}
@@ -142,6 +212,7 @@ crowbook.files_mean_chapters:bool   # {files_mean_chapters}
 crowbook.markdown.superscript:bool:false  # {superscript}
 crowbook.temp_dir:path:             # {tmp_dir}
 crowbook.zip.command:str:zip        # {zip}
+crowbook.font_subset.command:str:pyftsubset # {font_subset}
 
 # {prf_opt}
 output.proofread.html:path          # {prf_html}
@@ -219,6 +290,8 @@ crowbook.verbose:alias                              # {removed}
                                          html_if_opt = lformat!("Interactive fiction HTML options"),
                                          epub_opt = lformat!("EPUB options"),
                                          tex_opt = lformat!("LaTeX options"),
+                                         typst_opt = lformat!("Typst options"),
+                                         txt_opt = lformat!("Plain text options"),
                                          rs_opt = lformat!("Resources option"),
                                          input_opt = lformat!("Input options"),
                                          crowbook_opt = lformat!("Crowbook options"),
@@ -240,27 +313,41 @@ crowbook.verbose:alias                              # {removed}
                                          autograph = lformat!("An autograph"),
 
                                          output_epub = lformat!("Output file name for EPUB rendering"),
+                                         output_kindle_epub = lformat!("Output file name for EPUB rendering with the Kindle profile (ready for KF8/AZW3 conversion)"),
                                          output_html = lformat!("Output file name for HTML rendering"),
                                          output_tex = lformat!("Output file name for LaTeX rendering"),
                                          output_pdf = lformat!("Output file name for PDF rendering"),
                                          output_odt = lformat!("Output file name for ODT rendering"),
                                          output_if = lformat!("Output file name for HTML (interactive fiction) rendering"),
+                                         output_md = lformat!("Output file name for Markdown rendering"),
+                                         output_txt = lformat!("Output file name for plain text rendering"),
+                                         output_typ = lformat!("Output file name for Typst rendering"),
+                                         output_manuscript_tex = lformat!("Output file name for LaTeX rendering in standard manuscript format"),
+                                         output_manuscript_pdf = lformat!("Output file name for PDF rendering in standard manuscript format"),
+                                         output_cover_print = lformat!("Output file name for the print cover (back cover, spine and front cover), in PDF"),
                                          output_html_dir = lformat!("Output directory name for HTML rendering"),
                                          output_base_path = lformat!("Directory where those output files will we written"),
 
                                          rendering_highlight = lformat!("If/how highligh code blocks. Possible values: \"syntect\" (default, performed at runtime), \"highlight.js\" (HTML-only, uses Javascript), \"none\""),
                                          rendering_highlight_theme = lformat!("Theme for syntax highlighting (if rendering.highlight is set to 'syntect')"),
+                                         rendering_highlight_syntaxes = lformat!("Directory containing additional syntax definitions (.sublime-syntax files) for syntect"),
+                                         rendering_highlight_themes = lformat!("Directory containing additional themes (.tmTheme files) for syntect"),
+                                         rendering_code_line_numbers = lformat!("Number the lines of all code blocks, as if their info string contained 'linenos'"),
                                          rendering_initials = lformat!("Use initials ('lettrines') for first letter of a chapter"),
                                          inline_toc = lformat!("Display a table of content in the document"),
                                          toc_name = lformat!("Name of the table of contents if it is displayed in document"),
                                          num_depth = lformat!("The  maximum heading levels that should be numbered (0: no numbering, 1: only chapters, ..., 6: all)"),
                                          part = lformat!("How to call parts (or 'books', 'episodes', ...)"),
                                          chapter = lformat!("How to call chapters"),
+                                         appendix = lformat!("How to call appendices"),
                                          chapter_template = lformat!("Naming scheme of chapters, for TOC"),
                                          part_template = lformat!("Naming scheme of parts, for TOC"),
                                          roman_numerals_parts = lformat!("If set to true, display part number with roman numerals"),
                                          roman_numerals_chapters = lformat!("If set to true, display chapter number with roman numerals"),
                                          reset_counter = lformat!("If set to true, reset chapter number at each part"),
+                                         rendering_flags = lformat!("List of flags enabling conditional blocks (e.g. 'if=teacher_edition')"),
+                                         scene_break = lformat!("Text or symbol displayed for scene breaks (see input.scene_breaks), e.g. '⁂' or '❦', or an empty string for a blank line"),
+                                         scene_break_image = lformat!("Path of an image (e.g. a fleuron) displayed for scene breaks instead of rendering.scene_break"),
 
                                          import_config = lformat!("Import another book configuration file"),
 
@@ -275,6 +362,8 @@ crowbook.verbose:alias                              # {removed}
                                          highlight_js = lformat!("Set another highlight.js version than the bundled one"),
                                          highlight_css = lformat!("Set another highlight.js CSS theme than the default one"),
                                          side_notes = lformat!("Display footnotes as side notes in HTML/Epub (experimental)"),
+                                         html_fonts = lformat!("List of font files to embed in HTML, with generated @font-face rules"),
+                                         fonts_subset = lformat!("Only embed the glyphs of fonts that are used in the book"),
                                          nb_spaces = lformat!("Replace unicode non breaking spaces with HTML entities and CSS"),
                                          nb_spaces_tex = lformat!("Replace unicode non breaking spaces with TeX code"),
 
@@ -291,11 +380,24 @@ crowbook.verbose:alias                              # {removed}
                                          html_dir_template = lformat!("Path of a HTML template for multifile HTML"),
 
                                          epub_ver = lformat!("EPUB version to generate (2 or 3)"),
+                                         epub_profile = lformat!("Store or reader the EPUB is generated for: 'generic', 'kindle', 'apple' or 'kobo'"),
                                          epub_css = lformat!("Path of a stylesheet for EPUB"),
                                          epub_css_add = lformat!("Inline CSS added to the EPUB stylesheet template"),
                                          chapter_xhtml = lformat!("Path of an xhtml template for each chapter"),
                                          epub_toc = lformat!("Add 'Title' and (if set) 'Cover' in the EPUB table of contents"),
-
+                                         epub_access_mode = lformat!("Access modes of the EPUB (default: 'textual', and 'visual' if it contains images)"),
+                                         epub_access_features = lformat!("Accessibility features of the EPUB (default: 'structuralNavigation', 'tableOfContents', and page navigation if there are page breaks)"),
+                                         epub_access_summary = lformat!("Human-readable summary of the accessibility of the EPUB"),
+                                         epub_identifier = lformat!("Unique identifier of the EPUB (e.g. 'urn:isbn:...'), a random UUID if not set"),
+                                         epub_fonts = lformat!("List of font files to embed in the EPUB, with generated @font-face rules"),
+                                         epub_fonts_obfuscate = lformat!("Obfuscate embedded fonts, using the IDPF algorithm"),
+                                         epub_layout = lformat!("Layout of the EPUB: 'reflowable' or 'pre-paginated' (fixed layout, EPUB 3 only)"),
+                                         epub_layout_pages = lformat!("In a fixed layout, make a page of each 'chapter' or of each standalone 'image'"),
+                                         epub_layout_width = lformat!("Width of the pages of a fixed layout, in pixels"),
+                                         epub_layout_height = lformat!("Height of the pages of a fixed layout, in pixels"),
+                                         epub_layout_spread = lformat!("How pages of a fixed layout are displayed side by side: 'none', 'landscape', 'both' or 'auto'"),
+
+                                         tex_code_wrap = lformat!("Wrap long lines of code blocks (else they may overflow the page)"),
                                          tex_links = lformat!("Add foontotes to URL of links so they are readable when printed"),
                                          tex_command = lformat!("LaTeX command to use for generating PDF"),
                                          tex_tmpl = lformat!("Path of a LaTeX template file"),
@@ -308,8 +410,45 @@ crowbook.verbose:alias                              # {removed}
                                          tex_margin_top = lformat!("Specifies top margin"),
                                          tex_margin_bottom = lformat!("Specifies left margin"),
                                          tex_font_size = lformat!("Specify latex font size (in pt, 10 (default), 11, or 12 are accepted)"),
+                                         tex_font_main = lformat!("Main font of the document, loaded with fontspec (requires tex.command set to xelatex or tectonic)"),
+                                         tex_font_sans = lformat!("Sans-serif font of the document, loaded with fontspec (requires tex.command set to xelatex or tectonic)"),
+                                         tex_font_mono = lformat!("Monospace font of the document, used for code, loaded with fontspec (requires tex.command set to xelatex or tectonic)"),
+                                         tex_line_spacing = lformat!("Line spacing, relative to the default one (e.g. 1.2)"),
+                                         tex_paragraph_style = lformat!("Separation of paragraphs: 'indent' (first line indented) or 'spacing' (vertical space, no indent)"),
+                                         tex_paragraph_indent = lformat!("Indent of the first line of paragraphs (e.g. '1.5em')"),
+                                         tex_penalty_widow = lformat!("Penalty for a widow, the last line of a paragraph alone at the top of a page (from 0 to 10000, which forbids them)"),
+                                         tex_penalty_orphan = lformat!("Penalty for an orphan, the first line of a paragraph alone at the bottom of a page (from 0 to 10000, which forbids them)"),
+                                         tex_chapter_style = lformat!("Design of chapter headings: 'default', 'centered', 'hang', 'rule' or 'smallcaps'"),
+                                         tex_headers = lformat!("Running headers: 'page' (page number only), 'chapter' (page number, book title on left pages and chapter title on right pages) or 'footer' (page number in the footer, no header)"),
+                                         tex_initials_lines = lformat!("Number of lines the initials (see rendering.initials) drop down"),
+                                         tex_initials_oversize = lformat!("Enlargement of initials, relative to their height (e.g. 0.1)"),
+                                         tex_microtype = lformat!("If set to true, use the microtype package for character protrusion and font expansion"),
+                                         tex_microtype_options = lformat!("Options passed to the microtype package (e.g. 'protrusion=true,expansion=false,tracking=true')"),
+                                         tex_classes = lformat!("LaTeX environments (for blocks) or commands (for spans) used for the classes set with attributes, e.g. [\"epigraph=flushright\", \"smallcaps=textsc\"]"),
                                          tex_hyperref = lformat!("If disabled, don't try to find references inside the document"),
                                          tex_stdpage = lformat!("If set to true, use 'stdpage' package to format a manuscript according to standards"),
+                                         tex_manuscript_tmpl = lformat!("Path of a LaTeX template file for standard manuscript format"),
+                                         tex_manuscript_paper = lformat!("Specifies the size of the page for standard manuscript format"),
+                                         tex_trim_size = lformat!("Trim size of a print-ready PDF, either a width and a height (e.g. '6x9', in inches, or '148mmx210mm') or one of 'pocket', 'digest', 'us-trade', 'royal', 'crown-quarto', 'executive', 'letter', 'a5', 'b5' or 'a4'. Overrides tex.paper.size"),
+                                         tex_bleed = lformat!("Bleed added around the pages of a print-ready PDF (e.g. '0.125in')"),
+                                         tex_crop_marks = lformat!("If set to true, print crop marks around the pages of a print-ready PDF"),
+                                         tex_margin_gutter = lformat!("Minimal inner margin of a print-ready PDF, or 'auto' to compute it from the page count"),
+                                         tex_pdf_standard = lformat!("Make the PDF comply with PDF/X or PDF/A: 'x-1a', 'x-3', 'x-4', 'a-1b', 'a-2b', 'a-2u', 'a-3b' or 'a-3u'"),
+                                         tex_cover_tmpl = lformat!("Path of a LaTeX template file for the print cover"),
+                                         cover_paper = lformat!("Paper of the printed book, used to compute the spine width of the print cover: 'white', 'cream', 'color' or the thickness of a page (e.g. '0.0025in')"),
+                                         cover_pages = lformat!("Page count used to compute the spine width of the print cover (by default, the book is rendered to PDF to get it)"),
+                                         tex_fonts_check = lformat!("If set to true, check with pdffonts that all fonts are embedded in the PDF (always done if tex.pdf.standard is set)"),
+
+                                         pdf_engine = lformat!("Engine used for PDF rendering: 'latex' or 'typst' (falls back to LaTeX if the Typst command can't be run)"),
+                                         typst_command = lformat!("Typst command to use for generating PDF"),
+                                         typst_tmpl = lformat!("Path of a Typst template file"),
+                                         typst_tmpl_add = lformat!("Inline code added in the Typst template"),
+                                         typst_paper_size = lformat!("Size of the page, as a Typst paper name (e.g. 'a5', 'us-letter')"),
+                                         typst_font_size = lformat!("Size of the font, in pt"),
+                                         typst_title = lformat!("If true, generate a title page"),
+
+                                         txt_width = lformat!("Maximum width of lines in plain text output (0: no wrapping)"),
+                                         txt_footnotes = lformat!("How to display footnotes in plain text output: \"end\" (at the end of each chapter), \"inline\" or \"none\""),
 
                                          rs_files = lformat!("Whitespace-separated list of files to embed in e.g. EPUB file; useful for including e.g. fonts"),
                                          rs_out = lformat!("Paths where additional resources should be copied in the EPUB file or HTML directory"),
@@ -325,10 +464,12 @@ crowbook.verbose:alias                              # {removed}
                                          ligature_guillemets = lformat!("If enabled, replaces '<<' and '>>' to french \"guillemets\" ('«' and '»')"),
                                          superscript = lformat!("If enabled, allow support for superscript and subscript using respectively foo^up^  and bar~down~ syntax."),
                                          yaml = lformat!("Enable inline YAML blocks to override options set in config file"),
+                                         scene_breaks = lformat!("Parse `***` and `* * *` paragraphs as scene breaks instead of horizontal rules"),
                                          html_as_text = lformat!("Consider HTML blocks as text. This avoids having <foo> being considered as HTML and thus ignored."),
                                          files_mean_chapters = lformat!("Consider that a new file is always a new chapter, even if it does not include heading (default: only for numbered chapters)"),
                                          tmp_dir = lformat!("Path where to create a temporary directory (default: uses result from Rust's std::env::temp_dir())"),
                                          zip = lformat!("Command to use to zip files (for EPUB/ODT)"),
+                                         font_subset = lformat!("Command to use to subset fonts (must be compatible with fontTools' pyftsubset)"),
 
                                          prf_html = lformat!("Output file name for HTML rendering with proofread features"),
                                          prf_html_dir = lformat!("Output directory name for HTML rendering with proofread features"),
@@ -381,6 +522,8 @@ crowbook.verbose:alias                              # {removed}
 pub struct BookOptions {
     options: HashMap<String, BookOption>,
     defaults: HashMap<String, BookOption>,
+    /// Keys of the options that have been set explicitly, even to their default value
+    set_keys: HashSet<String>,
     deprecated: HashMap<String, Option<String>>,
     valid_tpls: Vec<&'static str>,
     valid_bools: Vec<&'static str>,
@@ -408,6 +551,7 @@ impl BookOptions {
             options: HashMap::new(),
             deprecated: HashMap::new(),
             defaults: HashMap::new(),
+            set_keys: HashSet::new(),
             valid_bools: vec![],
             valid_chars: vec![],
             valid_ints: vec![],
@@ -462,9 +606,11 @@ impl BookOptions {
             }
             if key == "crowbook.temp_dir" {
                 // "temp_dir" has a special default value that depends on the environment
+                let temp_dir = env::temp_dir().to_string_lossy().into_owned();
+                options.set(key, &temp_dir).unwrap();
                 options
-                    .set(key, &env::temp_dir().to_string_lossy())
-                    .unwrap();
+                    .defaults
+                    .insert(key.to_owned(), BookOption::Path(temp_dir));
                 continue;
             }
             if let Some(value) = default_value {
@@ -474,6 +620,8 @@ impl BookOptions {
                 options.defaults.insert(key.to_owned(), option.unwrap());
             }
         }
+        // Default values don't count as set
+        options.set_keys.clear();
         options
     }
 
@@ -498,7 +646,13 @@ impl BookOptions {
                 lformat!("Expected a String as a key, found {:?}", key),
             ));
         };
+        let previous = self.set_value(key.clone(), value)?;
+        self.set_keys.insert(key);
+        Ok(previous)
+    }
 
+    /// Sets an option after checking that the value has the right type
+    fn set_value(&mut self, key: String, value: Yaml) -> Result<Option<BookOption>> {
         if self.valid_str_vecs.contains(&key.as_ref()) {
             // Value is a list of string
             if let Yaml::Array(array) = value {
@@ -786,6 +940,27 @@ impl BookOptions {
         &self.metadata
     }
 
+    /// Returns the (sorted) list of keys of options that have been set to
+    /// a value other than their default
+    #[doc(hidden)]
+    pub fn get_set_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .options
+            .iter()
+            .filter(|(key, value)| self.defaults.get(key.as_str()) != Some(*value))
+            .map(|(key, _)| key.as_str())
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Returns true if an option has been set explicitly, in the book configuration
+    /// or with `set`, even if it was set to its default value
+    #[doc(hidden)]
+    pub fn is_set(&self, key: &str) -> bool {
+        self.set_keys.contains(key)
+    }
+
     /// Gets an option
     #[doc(hidden)]
     pub fn get(&self, key: &str) -> Result<&BookOption> {
@@ -852,11 +1027,18 @@ impl BookOptions {
             }
 
             "output.epub"
+            | "output.kindle.epub"
             | "output.html"
             | "output.html.dir"
             | "output.pdf"
             | "output.tex"
             | "output.odt"
+            | "output.md"
+            | "output.txt"
+            | "output.typ"
+            | "output.manuscript.tex"
+            | "output.manuscript.pdf"
+            | "output.cover.print"
             | "output.proofread.html"
             | "output.proofread.html.dir"
             | "output.proofread.pdf"
@@ -940,6 +1122,9 @@ impl BookOptions {
                 let default = &self.defaults[key];
                 // If new value is equal to default, don't insert it
                 if value == default {
+                    if other.set_keys.contains(key) {
+                        self.set_keys.insert(key.clone());
+                    }
                     continue;
                 }
                 if let Some(previous_opt) = previous_opt {
@@ -979,6 +1164,7 @@ impl BookOptions {
             } else {
                 self.options.insert(key.clone(), value.clone());
             }
+            self.set_keys.insert(key.clone());
         }
         Ok(())
     }
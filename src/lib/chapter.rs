@@ -18,6 +18,37 @@
 use crate::number::Number;
 use crate::token::Token;
 
+use std::collections::HashMap;
+use std::fmt;
+
+/// The division of the book a chapter belongs to.
+///
+/// This is set by the `[frontmatter]`, `[mainmatter]`, `[appendix]` and `[backmatter]`
+/// markers of the chapter list.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum Matter {
+    /// Dedication, foreword, ...: chapters are not numbered
+    Front,
+    /// Main content of the book (the default)
+    Main,
+    /// Appendices: chapters are numbered with letters
+    Appendix,
+    /// Acknowledgements, colophon, ...: chapters are not numbered
+    Back,
+}
+
+impl fmt::Display for Matter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Matter::Front => write!(f, "frontmatter"),
+            Matter::Main => write!(f, "mainmatter"),
+            Matter::Appendix => write!(f, "appendix"),
+            Matter::Back => write!(f, "backmatter"),
+        }
+    }
+}
+
 /// Represents the content of a chapter.
 #[derive(Debug)]
 pub struct Chapter {
@@ -27,6 +58,12 @@ pub struct Chapter {
     pub filename: String,
     /// The (already parsed) content of this chapter
     pub content: Vec<Token>,
+    /// Metadata set in the `chapter` section of this chapter's YAML front matter
+    pub(crate) metadata: HashMap<String, String>,
+    /// The division of the book (front matter, main matter, ...) this chapter belongs to
+    pub matter: Matter,
+    /// The line in the source file of each top-level token of `content`, if known
+    pub lines: Vec<u32>,
 }
 
 impl Chapter {
@@ -41,6 +78,15 @@ impl Chapter {
             number,
             filename: filename.into(),
             content,
+            metadata: HashMap::new(),
+            matter: Matter::Main,
+            lines: vec![],
         }
     }
+
+    /// Returns the metadata set in the `chapter` section of this chapter's YAML front matter
+    /// (e.g. `author`, `subtitle`, `epigraph`, `date`, `class`)
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        &self.metadata
+    }
 }
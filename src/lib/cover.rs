@@ -0,0 +1,232 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Print cover: back cover, spine and front cover on a single page, whose width
+//! depends on the page count of the book
+
+use crate::book::{compile_str, Book};
+use crate::book_renderer::BookRenderer;
+use crate::error::{Error, Result};
+use crate::latex::LatexRenderer;
+use crate::parser::Parser;
+use crate::print;
+use crate::renderer::Renderer;
+use crate::zipper::Zipper;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Thickness of a page for the paper stocks of print-on-demand services, in inches
+const PAPER_STOCKS: &[(&str, f64)] = &[("white", 0.002252), ("cream", 0.0025), ("color", 0.002347)];
+
+/// Minimal page count for text on the spine
+const SPINE_TEXT_PAGES: u32 = 80;
+
+/// Distance between the trimmed edges and text, in big points
+const SAFE_MARGIN: f64 = 0.25 * 72.0;
+
+/// Layout of a print cover, in big points
+#[derive(Debug, PartialEq)]
+pub struct CoverLayout {
+    /// Size of the whole cover, including bleed
+    pub size: (f64, f64),
+    /// Left edge of the spine
+    pub spine_x: f64,
+    /// Width of the spine
+    pub spine_width: f64,
+    /// Left edge of the front cover
+    pub front_x: f64,
+    /// Left edge of the text of the back cover
+    pub back_text_x: f64,
+    /// Top edge of text, from the bottom of the cover
+    pub text_top: f64,
+    /// Width of text on the back and front covers
+    pub text_width: f64,
+}
+
+impl CoverLayout {
+    /// Computes the layout of the cover of a book of `pages` pages of size `trim`, with
+    /// `bleed` around the cover and pages of the given `thickness`
+    pub fn new(trim: (f64, f64), bleed: f64, pages: u32, thickness: f64) -> CoverLayout {
+        let (width, height) = trim;
+        let spine_width = pages as f64 * thickness;
+        let spine_x = bleed + width;
+        CoverLayout {
+            size: (2.0 * (width + bleed) + spine_width, height + 2.0 * bleed),
+            spine_x,
+            spine_width,
+            front_x: spine_x + spine_width,
+            back_text_x: bleed + SAFE_MARGIN,
+            text_top: bleed + height - SAFE_MARGIN,
+            text_width: width - 2.0 * SAFE_MARGIN,
+        }
+    }
+}
+
+/// Parses the thickness of a page, either one of the paper stocks or a dimension
+///
+/// Returns it in big points.
+pub fn paper_thickness(s: &str) -> Option<f64> {
+    match PAPER_STOCKS.iter().find(|(name, _)| *name == s) {
+        Some((_, inches)) => Some(inches * 72.0),
+        None => print::parse_dimension(s, "in"),
+    }
+}
+
+/// Renders the print cover of a book
+///
+/// Unless `cover.pages` is set, the book is first rendered to PDF to get its page count.
+pub fn render_cover(book: &Book, to: &mut dyn io::Write) -> Result<String> {
+    let options = &book.options;
+    let trim = options
+        .get_str("tex.trim.size")
+        .ok()
+        .and_then(print::trim_size)
+        .ok_or_else(|| {
+            Error::book_option(
+                &book.source,
+                lformat!("the print cover requires a valid tex.trim.size"),
+            )
+        })?;
+    let bleed = match options.get_str("tex.bleed") {
+        Ok(bleed) => print::parse_dimension(bleed, "pt").ok_or_else(|| {
+            Error::book_option(
+                &book.source,
+                lformat!(
+                    "tex.bleed set to '{value}', which is not a valid dimension",
+                    value = bleed
+                ),
+            )
+        })?,
+        Err(_) => 0.0,
+    };
+    let thickness = options.get_str("cover.paper").unwrap();
+    let thickness = paper_thickness(thickness).ok_or_else(|| {
+        Error::book_option(
+            &book.source,
+            lformat!(
+                "cover.paper set to '{value}', expected 'white', 'cream', 'color' or the \
+                 thickness of a page (e.g. '0.0025in')",
+                value = thickness
+            ),
+        )
+    })?;
+
+    let mut latex = LatexRenderer::new(book)?;
+    let pages = match options.get_i32("cover.pages") {
+        Ok(pages) if pages > 0 => pages as u32,
+        _ => {
+            debug!(
+                "{}",
+                lformat!("Rendering the book to PDF to get its page count")
+            );
+            latex.render_pdf(&mut io::sink())?;
+            latex.page_count().ok_or_else(|| {
+                Error::render(
+                    &book.source,
+                    lformat!("could not find the page count of the PDF in the LaTeX log"),
+                )
+            })?
+        }
+    };
+    let layout = CoverLayout::new(trim, bleed, pages, thickness);
+    debug!(
+        "{}",
+        lformat!(
+            "Print cover: {pages} pages, spine width {width}",
+            pages = pages,
+            width = print::bp(layout.spine_width)
+        )
+    );
+
+    let mut zipper = Zipper::new(&options.get_path("crowbook.temp_dir").unwrap())?;
+    let template = compile_str(
+        book.get_template("tex.cover.template")?.as_ref(),
+        &book.source,
+        "tex.cover.template",
+    )?;
+    let number = |x: f64| format!("{x:.2}");
+    let mut data = book
+        .get_metadata(|s| latex.render_vec(&Parser::new().parse_inline(s)?))?
+        .insert_str("paper_width", print::bp(layout.size.0))
+        .insert_str("paper_height", print::bp(layout.size.1))
+        .insert_str("width", number(layout.size.0))
+        .insert_str("height", number(layout.size.1))
+        .insert_str("spine_x", number(layout.spine_x))
+        .insert_str("spine_width", number(layout.spine_width))
+        .insert_str(
+            "spine_center",
+            number(layout.spine_x + layout.spine_width / 2.0),
+        )
+        .insert_str("front_x", number(layout.front_x))
+        .insert_str("front_width", number(layout.size.0 - layout.front_x))
+        .insert_str("front_text_x", number(layout.front_x + SAFE_MARGIN))
+        .insert_str("back_text_x", number(layout.back_text_x))
+        .insert_str("text_top", number(layout.text_top))
+        .insert_str("text_width", number(layout.text_width))
+        .insert_bool("has_spine_text", pages >= SPINE_TEXT_PAGES);
+    if let Ok(cover) = options.get_path("cover") {
+        let extension = Path::new(&cover)
+            .extension()
+            .map(|e| e.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let content = fs::read(&cover)
+            .map_err(|_| Error::file_not_found(&book.source, lformat!("cover"), cover.clone()))?;
+        let file = format!("cover.{extension}");
+        zipper.write(&file, &content, false)?;
+        data = data.insert_str("cover", file);
+    }
+    // Insert xelatex if tex.command is set to xelatex or tectonic
+    if matches!(
+        options.get_str("tex.command"),
+        Ok("xelatex") | Ok("tectonic")
+    ) {
+        data = data.insert_bool("xelatex", true);
+    }
+    let mut res: Vec<u8> = vec![];
+    template.render_data(&mut res, &data.build())?;
+    zipper.write("result.tex", &res, false)?;
+    zipper.generate_pdf(options.get_str("tex.command").unwrap(), "result.tex", to)
+}
+
+pub struct CoverPrint;
+
+impl BookRenderer for CoverPrint {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.cover.pdf"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
+        render_cover(book, to)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn cover_layout() {
+    assert_eq!(paper_thickness("cream"), Some(0.18));
+    assert_eq!(paper_thickness("0.5in"), Some(36.0));
+    assert_eq!(paper_thickness("thick"), None);
+
+    let layout = CoverLayout::new((432.0, 648.0), 9.0, 200, 0.18);
+    assert_eq!(layout.spine_width, 36.0);
+    assert_eq!(layout.size, (918.0, 666.0));
+    assert_eq!(layout.spine_x, 441.0);
+    assert_eq!(layout.front_x, 477.0);
+    assert_eq!(layout.text_width, 396.0);
+}
@@ -18,9 +18,12 @@
 use crate::book::Header;
 use crate::book::{compile_str, Book};
 use crate::book_renderer::BookRenderer;
+use crate::chapter::Matter;
 use crate::error::{Error, Result, Source};
+use crate::font::{self, Font};
 use crate::html::HtmlRenderer;
 use crate::lang;
+use crate::media_overlay::{self, Overlay};
 use crate::parser::Parser;
 use crate::renderer::Renderer;
 use crate::resource_handler;
@@ -34,16 +37,55 @@ use epub_builder::{
     EpubBuilder, EpubContent, EpubVersion, ReferenceType, ZipCommand, ZipCommandOrLibrary,
     ZipLibrary,
 };
-use mustache::Template;
+use mustache::{MapBuilder, Template};
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
 
 use std::borrow::Cow;
 use std::convert::{AsMut, AsRef};
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Cursor, Read, Write};
 
 use std::path::Path;
 
+/// Source and destination paths of an audio file narrating a chapter
+type AudioFile = (String, String);
+
+/// Store or reading system an EPUB is generated for
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Profile {
+    /// Standard EPUB, following the options
+    Generic,
+    /// EPUB meant to be converted to KF8/AZW3 for Kindle
+    Kindle,
+    /// Apple Books
+    Apple,
+    /// Kobo readers
+    Kobo,
+}
+
+impl Profile {
+    /// Returns the name of the profile, as used in `epub.profile`
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Generic => "generic",
+            Profile::Kindle => "kindle",
+            Profile::Apple => "apple",
+            Profile::Kobo => "kobo",
+        }
+    }
+}
+
+/// How pages are made in a fixed-layout EPUB
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Pages {
+    /// Each chapter is a page
+    Chapter,
+    /// Each standalone image starts a new page, the text following it being overlaid
+    Image,
+}
+
 /// Renderer for Epub
 ///
 /// Uses part of the HTML renderer
@@ -52,6 +94,15 @@ pub struct EpubRenderer<'a> {
     html: HtmlRenderer<'a>,
     chapter_title: String,
     chapter_title_raw: String,
+    /// Page breaks of the print edition (link and page number)
+    page_list: Vec<(String, String)>,
+    profile: Profile,
+    /// Whether an EPUB 3 file is generated, according to `epub.version` and the profile
+    epub3: bool,
+    /// How pages are made, if `epub.layout` is `pre-paginated`
+    fixed_layout: Option<Pages>,
+    /// Page of the current chapter, if it is split in several pages
+    current_page: usize,
 }
 
 impl<'a> EpubRenderer<'a> {
@@ -65,16 +116,173 @@ impl<'a> EpubRenderer<'a> {
         )?;
         html.handler.set_images_mapping(true);
         html.handler.set_base64(false);
+        html.format = "epub";
+        let profile = match book.options.get_str("epub.profile").unwrap() {
+            "generic" => Profile::Generic,
+            "kindle" => Profile::Kindle,
+            "apple" => Profile::Apple,
+            "kobo" => Profile::Kobo,
+            value => {
+                return Err(Error::book_option(
+                    &book.source,
+                    lformat!(
+                        "epub.profile set to '{value}', valid values are 'generic', 'kindle', 'apple' or 'kobo'",
+                        value = value
+                    ),
+                ))
+            }
+        };
+        let fixed_layout = match book.options.get_str("epub.layout").unwrap() {
+            "reflowable" => None,
+            "pre-paginated" => match book.options.get_str("epub.layout.pages").unwrap() {
+                "chapter" => Some(Pages::Chapter),
+                "image" => Some(Pages::Image),
+                value => {
+                    return Err(Error::book_option(
+                        &book.source,
+                        lformat!(
+                            "epub.layout.pages set to '{value}', valid values are 'chapter' or 'image'",
+                            value = value
+                        ),
+                    ))
+                }
+            },
+            value => {
+                return Err(Error::book_option(
+                    &book.source,
+                    lformat!(
+                        "epub.layout set to '{value}', valid values are 'reflowable' or 'pre-paginated'",
+                        value = value
+                    ),
+                ))
+            }
+        };
         Ok(EpubRenderer {
             html,
             toc: vec![],
             chapter_title: String::new(),
             chapter_title_raw: String::new(),
-        })
+            page_list: vec![],
+            profile: Profile::Generic,
+            epub3: false,
+            fixed_layout,
+            current_page: 0,
+        }
+        .profile(profile))
+    }
+
+    /// Generate the EPUB for a given profile, regardless of `epub.profile`
+    pub fn profile(mut self, profile: Profile) -> Self {
+        let book = self.html.book;
+        let options = &book.options;
+        self.profile = profile;
+        // Apple Books and Kobo handle EPUB 3 best, unless the version is set explicitly
+        self.epub3 = if self.fixed_layout.is_some() {
+            // Fixed layout is only supported by EPUB 3
+            true
+        } else if options.is_set("epub.version") {
+            options.get_i32("epub.version").unwrap() == 3
+        } else {
+            matches!(profile, Profile::Apple | Profile::Kobo)
+        };
+        self
+    }
+
+    /// Returns true if an inline table of contents is added
+    ///
+    /// Kindle builds its own from the NCX.
+    fn inline_toc(&self) -> bool {
+        self.profile != Profile::Kindle
+            && self
+                .html
+                .book
+                .options
+                .get_bool("rendering.inline_toc")
+                .unwrap()
     }
 
     /// Render a book
     pub fn render_book(&mut self, to: &mut dyn Write) -> Result<String> {
+        let lang = self.html.book.options.get_str("lang").unwrap();
+        let toc_extras = self.html.book.options.get_bool("epub.toc.extras").unwrap();
+        let epub3 = self.epub3;
+
+        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            self.html
+                .handler
+                .add_link(chapter.filename.as_str(), filenamer(i));
+            self.html
+                .handler
+                .add_anchors(&chapter.filename, &chapter.content, |id| {
+                    format!("{}#{id}", filenamer(i))
+                });
+        }
+
+        // Render cover.xhtml (if needs be) and titlepage
+        let cover = if self.html.book.options.get_path("cover").is_ok() {
+            Some(self.render_cover()?)
+        } else {
+            None
+        };
+        // Kindle displays the cover image itself, so a cover page would only duplicate it
+        let cover = cover.filter(|_| self.profile != Profile::Kindle);
+        let title_page = self.render_titlepage()?;
+
+        // Render chapters
+        let template_chapter = if self
+            .html
+            .book
+            .options
+            .get_path("epub.chapter.xhtml")
+            .is_ok()
+        {
+            self.html.book.get_template("epub.chapter.xhtml")?
+        } else if epub3 {
+            Cow::Borrowed(epub3::TEMPLATE)
+        } else {
+            Cow::Borrowed(TEMPLATE)
+        };
+        let template_chapter = compile_str(
+            template_chapter.as_ref(),
+            &self.html.book.source,
+            "epub.chapter.xhtml",
+        )?;
+        let mut rendered = vec![];
+        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+            let n = chapter.number;
+            let v = &chapter.content;
+            self.html.chapter_config(i, n, filenamer(i));
+            let this_chapter = self.render_chapter_pages(v, &template_chapter)?;
+            rendered.push(this_chapter);
+        }
+
+        // The "start of content" landmark is the first chapter of the main matter
+        let start = self
+            .html
+            .book
+            .chapters
+            .iter()
+            .position(|c| c.matter == Matter::Main)
+            .unwrap_or(0);
+
+        // Fonts must be obfuscated with the unique identifier before being added
+        let identifier = match self.html.book.options.get_str("epub.identifier") {
+            Ok(identifier) => identifier.to_owned(),
+            Err(_) => format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+        };
+        let mut fonts = font::load_fonts(self.html.book, "epub")?;
+        let obfuscate = self
+            .html
+            .book
+            .options
+            .get_bool("epub.fonts.obfuscate")
+            .unwrap();
+        if obfuscate {
+            for font in &mut fonts {
+                font.obfuscate(&identifier);
+            }
+        }
+
         // Initialize the EPUB builder
         let mut zip = ZipCommand::new_in(self.html.book.options.get_path("crowbook.temp_dir")?)?;
         zip.command(
@@ -93,13 +301,40 @@ impl<'a> EpubRenderer<'a> {
             );
             ZipCommandOrLibrary::Library(ZipLibrary::new()?)
         };
+        // Synchronize chapters with their narration, if any
+        let (overlays, audio) = self.render_media_overlays(&rendered)?;
+        let durations = render_durations(&overlays);
+
+        let mut files = vec![];
+        if obfuscate && !fonts.is_empty() {
+            files.push((
+                String::from("META-INF/encryption.xml"),
+                render_encryption(&fonts),
+            ));
+        }
+        if self.profile == Profile::Apple && !fonts.is_empty() {
+            // Otherwise Apple Books ignores embedded fonts
+            files.push((
+                String::from("META-INF/com.apple.ibooks.display-options.xml"),
+                String::from(APPLE_DISPLAY_OPTIONS),
+            ));
+        }
+        let patch = EpubPatch {
+            identifier,
+            files,
+            overlays,
+            opf_metadata: self.render_accessibility(epub3) + &self.render_rendition()? + &durations,
+            navigation: if epub3 {
+                self.render_navigation(cover.is_some(), start)
+            } else {
+                String::new()
+            },
+        };
         let mut maker = EpubBuilder::new(wrapper)?;
-        if self.html.book.options.get_i32("epub.version").unwrap() == 3 {
+        if epub3 {
             maker.epub_version(EpubVersion::V30);
         }
 
-        let lang = self.html.book.options.get_str("lang").unwrap();
-        let toc_extras = self.html.book.options.get_bool("epub.toc.extras").unwrap();
         maker.metadata("lang", lang)?;
         maker.metadata(
             "author",
@@ -121,35 +356,8 @@ impl<'a> EpubRenderer<'a> {
             maker.metadata("license", license)?;
         }
 
-        // if self.html.book.options.get_bool("epub.toc.extras").unwrap() == true {
-        //     if self.html.book.options.get("cover").is_ok() {
-        //         self.html.toc.add(1,
-        //                           String::from("cover.xhtml"),
-        //                           lang::get_str(lang, "cover"));
-        //     }
-        //     self.html.toc.add(1,
-        //                       String::from("title_page.xhtml"),
-        //                       lang::get_str(lang, "title"));
-
-        // }
-
-        // /* If toc will be rendered inline, add it... to the toc (yeah it's meta) */
-        // if self.html.book.options.get_bool("rendering.inline_toc").unwrap() == true {
-        //     self.html.toc.add(1,
-        //                       String::from("toc.xhtml"),
-        //                       lang::get_str(self.html.book.options.get_str("lang").unwrap(),
-        //                                     "toc"));
-        // }
-
-        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
-            self.html
-                .handler
-                .add_link(chapter.filename.as_str(), filenamer(i));
-        }
-
         // Write cover.xhtml (if needs be)
-        if self.html.book.options.get_path("cover").is_ok() {
-            let cover = self.render_cover()?;
+        if let Some(cover) = cover {
             let mut content =
                 EpubContent::new("cover.xhtml", cover.as_bytes()).reftype(ReferenceType::Cover);
             if toc_extras {
@@ -160,7 +368,6 @@ impl<'a> EpubRenderer<'a> {
 
         // Write titlepage
         {
-            let title_page = self.render_titlepage()?;
             let mut content = EpubContent::new("title_page.xhtml", title_page.as_bytes())
                 .reftype(ReferenceType::TitlePage);
             if toc_extras {
@@ -169,47 +376,34 @@ impl<'a> EpubRenderer<'a> {
             maker.add_content(content)?;
         }
 
-        if self
-            .html
-            .book
-            .options
-            .get_bool("rendering.inline_toc")
-            .unwrap()
-        {
+        if self.inline_toc() {
             maker.inline_toc();
         }
 
-        // Write chapters
-        let template_chapter = compile_str(
-            self.html.book.get_template("epub.chapter.xhtml")?.as_ref(),
-            &self.html.book.source,
-            "epub.chapter.xhtml",
-        )?;
-        let mut rendered = vec![];
-        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
-            let n = chapter.number;
-            let v = &chapter.content;
-            self.html.chapter_config(i, n, filenamer(i));
-            let this_chapter = self.render_chapter(v, &template_chapter)?;
-            rendered.push(this_chapter);
-        }
-
-        for (i, (rendered_chapter, raw_title)) in rendered.into_iter().enumerate() {
-            let mut content = EpubContent::new(filenamer(i), rendered_chapter.as_bytes());
-            if i == 0 {
-                content = content.reftype(ReferenceType::Text);
-            }
+        // Write chapters, with one file per page for fixed-layout books
+        for (i, (pages, raw_title)) in rendered.into_iter().enumerate() {
+            for (page, rendered_page) in pages.into_iter().enumerate() {
+                let mut content =
+                    EpubContent::new(page_filenamer(i, page), rendered_page.as_bytes());
+                if page > 0 {
+                    maker.add_content(content)?;
+                    continue;
+                }
+                if i == start {
+                    content = content.reftype(ReferenceType::Text);
+                }
 
-            // horrible hack to add subtoc of this chapter to epub's toc
-            // todo: find cleaner way
-            for element in &self.html.toc.elements {
-                if element.url.contains(&filenamer(i)) {
-                    content = content.title(escape::html(raw_title));
-                    content.toc.children = element.children.clone();
-                    break;
+                // horrible hack to add subtoc of this chapter to epub's toc
+                // todo: find cleaner way
+                for element in &self.html.toc.elements {
+                    if element.url.contains(&filenamer(i)) {
+                        content = content.title(escape::html(&raw_title));
+                        content.toc.children = element.children.clone();
+                        break;
+                    }
                 }
+                maker.add_content(content)?;
             }
-            maker.add_content(content)?;
         }
         self.html.source = Source::empty();
 
@@ -227,6 +421,16 @@ impl<'a> EpubRenderer<'a> {
         if let Ok(epub_css_add) = self.html.book.options.get_str("epub.css.add") {
             data = data.insert_str("additional_code", epub_css_add);
         }
+        let mut font_faces = String::new();
+        for font in &fonts {
+            let path = format!("fonts/{}", font.file_name());
+            font_faces.push_str(&font.font_face(&path));
+            maker.add_resource(&path, font.content.as_slice(), font.media_type())?;
+        }
+        data = data.insert_str("font_faces", font_faces);
+        data = data.insert_bool(format!("profile_{}", self.profile.name()), true);
+        data = data.insert_bool("fixed_layout", self.fixed_layout.is_some());
+        data = data.insert_bool("media_overlays", !audio.is_empty());
         let data = data.build();
         let mut res: Vec<u8> = vec![];
         template_css.render_data(&mut res, &data)?;
@@ -251,6 +455,18 @@ impl<'a> EpubRenderer<'a> {
             }
         }
 
+        // Write narrations
+        for (source, dest) in &audio {
+            let f = fs::canonicalize(source).and_then(File::open).map_err(|_| {
+                Error::file_not_found(
+                    &self.html.book.source,
+                    lformat!("audio file of a media overlay"),
+                    source.to_owned(),
+                )
+            })?;
+            maker.add_resource(dest, &f, self.get_format(dest))?;
+        }
+
         // Write additional resources
         if let Ok(list) = self.html.book.options.get_str_vec("resources.files") {
             let base_path_files = self
@@ -281,24 +497,237 @@ impl<'a> EpubRenderer<'a> {
             }
         }
 
-        maker.generate(to)?;
+        // Add what the EPUB builder doesn't generate itself
+        let mut epub = vec![];
+        maker.generate(&mut epub)?;
+        patch.apply(&epub, to)?;
 
         Ok(String::new())
     }
 
+    /// Render the accessibility metadata that must be added to the OPF file
+    fn render_accessibility(&self, epub3: bool) -> String {
+        let options = &self.html.book.options;
+        let cover = options.get_path("cover");
+        let modes = match options.get_str_vec("epub.accessibility.mode") {
+            Ok(modes) => modes.to_vec(),
+            Err(_) => {
+                let mut modes = vec![String::from("textual")];
+                if self
+                    .html
+                    .handler
+                    .images_mapping()
+                    .keys()
+                    .any(|source| cover.as_ref() != Ok(source))
+                {
+                    modes.push(String::from("visual"));
+                }
+                modes
+            }
+        };
+        let features = match options.get_str_vec("epub.accessibility.features") {
+            Ok(features) => features.to_vec(),
+            Err(_) => {
+                let mut features = vec![
+                    String::from("structuralNavigation"),
+                    String::from("tableOfContents"),
+                ];
+                if !self.page_list.is_empty() {
+                    features.push(String::from("printPageNumbers"));
+                    if epub3 {
+                        features.push(String::from("pageNavigation"));
+                    }
+                }
+                features
+            }
+        };
+        let mut properties = vec![];
+        for mode in &modes {
+            properties.push(("schema:accessMode", mode.as_str()));
+        }
+        for feature in &features {
+            properties.push(("schema:accessibilityFeature", feature.as_str()));
+        }
+        if let Ok(summary) = options.get_str("epub.accessibility.summary") {
+            properties.push(("schema:accessibilitySummary", summary));
+        }
+
+        let mut res = String::new();
+        for (property, value) in properties {
+            let value = escape::html(value);
+            if epub3 {
+                res.push_str(&format!(
+                    "    <meta property = \"{property}\">{value}</meta>\n"
+                ));
+            } else {
+                res.push_str(&format!(
+                    "    <meta name = \"{property}\" content = \"{value}\" />\n"
+                ));
+            }
+        }
+        res
+    }
+
+    /// Render the landmarks and page list that must be added to the navigation document
+    fn render_navigation(&self, has_cover: bool, start: usize) -> String {
+        let lang = self.html.book.options.get_str("lang").unwrap();
+        let mut landmarks = vec![];
+        if has_cover {
+            landmarks.push(("cover", String::from("cover.xhtml"), "cover"));
+        }
+        landmarks.push(("titlepage", String::from("title_page.xhtml"), "title"));
+        if self.inline_toc() {
+            landmarks.push(("toc", String::from("toc.xhtml"), "toc"));
+        }
+        if !self.html.book.chapters.is_empty() {
+            landmarks.push(("bodymatter", filenamer(start), "start"));
+        }
+
+        let mut res = String::from("<nav epub:type = \"landmarks\" hidden = \"\">\n  <ol>\n");
+        for (epub_type, href, key) in landmarks {
+            res.push_str(&format!(
+                "    <li><a epub:type = \"{epub_type}\" href = \"{href}\">{}</a></li>\n",
+                escape::html(lang::get_str(lang, key))
+            ));
+        }
+        res.push_str("  </ol>\n</nav>\n");
+
+        if !self.page_list.is_empty() {
+            res.push_str("<nav epub:type = \"page-list\" hidden = \"\">\n  <ol>\n");
+            for (href, label) in &self.page_list {
+                res.push_str(&format!(
+                    "    <li><a href = \"{href}\">{}</a></li>\n",
+                    escape::html(label.as_str())
+                ));
+            }
+            res.push_str("  </ol>\n</nav>\n");
+        }
+        res
+    }
+
+    /// Render the media overlays of the chapters that set an `audio` file and its `audio.timing`
+    ///
+    /// Returns the overlays, and the source and destination paths of the audio files
+    fn render_media_overlays(
+        &self,
+        rendered: &[(Vec<String>, String)],
+    ) -> Result<(Vec<Overlay>, Vec<AudioFile>)> {
+        let book = self.html.book;
+        let mut overlays = vec![];
+        let mut audio_files = vec![];
+        for (i, (pages, _)) in rendered.iter().enumerate() {
+            let chapter = &book.chapters[i];
+            let audio = match chapter.metadata.get("audio") {
+                Some(audio) => audio,
+                None => continue,
+            };
+            if !self.epub3 {
+                warn!(
+                    "{}",
+                    lformat!(
+                        "EPUB ({source}): media overlays require EPUB 3, ignoring audio",
+                        source = chapter.filename
+                    )
+                );
+                continue;
+            }
+            let source = Source::new(chapter.filename.as_str());
+            let timing = chapter.metadata.get("audio.timing").ok_or_else(|| {
+                Error::book_option(
+                    &source,
+                    lformat!("chapter sets an audio file but no audio.timing file"),
+                )
+            })?;
+            let base_path = book.options.get_path("resources.base_path.files").unwrap();
+            let timing = Path::new(&base_path).join(timing);
+            let text = fs::read_to_string(&timing).map_err(|_| {
+                Error::file_not_found(
+                    &source,
+                    lformat!("timing file of a media overlay"),
+                    timing.to_string_lossy().into_owned(),
+                )
+            })?;
+            let clips = media_overlay::parse_timings(&text, timing.to_string_lossy().as_ref())?;
+
+            let extension = Path::new(audio)
+                .extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("mp3");
+            let dest = format!("audio/chapter_{i:03}.{extension}");
+            let pages: Vec<(String, String)> = pages
+                .iter()
+                .enumerate()
+                .map(|(j, page)| (page_filenamer(i, j), page.clone()))
+                .collect();
+            overlays.extend(media_overlay::render_overlays(
+                &pages, &dest, &clips, &source,
+            )?);
+            audio_files.push((
+                Path::new(&base_path)
+                    .join(audio)
+                    .to_string_lossy()
+                    .into_owned(),
+                dest,
+            ));
+        }
+        Ok((overlays, audio_files))
+    }
+
+    /// Insert the viewport of the pages in template data, if the layout is fixed
+    fn insert_viewport(&self, data: MapBuilder) -> MapBuilder {
+        if self.fixed_layout.is_none() {
+            return data;
+        }
+        let options = &self.html.book.options;
+        data.insert_bool("has_viewport", true).insert_str(
+            "viewport",
+            format!(
+                "width={}, height={}",
+                options.get_i32("epub.layout.width").unwrap(),
+                options.get_i32("epub.layout.height").unwrap()
+            ),
+        )
+    }
+
+    /// Render the rendition properties of a fixed-layout book that must be added to the OPF file
+    fn render_rendition(&self) -> Result<String> {
+        if self.fixed_layout.is_none() {
+            return Ok(String::new());
+        }
+        let spread = self
+            .html
+            .book
+            .options
+            .get_str("epub.layout.spread")
+            .unwrap();
+        if !["none", "landscape", "both", "auto"].contains(&spread) {
+            return Err(Error::book_option(
+                &self.html.book.source,
+                lformat!(
+                    "epub.layout.spread set to '{value}', valid values are 'none', 'landscape', 'both' or 'auto'",
+                    value = spread
+                ),
+            ));
+        }
+        Ok(format!(
+            "    <meta property=\"rendition:layout\">pre-paginated</meta>
+    <meta property=\"rendition:orientation\">auto</meta>
+    <meta property=\"rendition:spread\">{spread}</meta>\n"
+        ))
+    }
+
     /// Render the titlepgae
     fn render_titlepage(&mut self) -> Result<String> {
-        let epub3 = self.html.book.options.get_i32("epub.version").unwrap() == 3;
         let template = compile_str(
-            if epub3 { epub3::TITLE } else { TITLE },
+            if self.epub3 { epub3::TITLE } else { TITLE },
             &self.html.book.source,
             "title page",
         )?;
         let data = self
             .html
             .book
-            .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
-            .build();
+            .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
+        let data = self.insert_viewport(data).build();
         let mut res: Vec<u8> = vec![];
         template.render_data(&mut res, &data)?;
         match String::from_utf8(res) {
@@ -318,9 +747,8 @@ impl<'a> EpubRenderer<'a> {
                     cover,
                 ));
             }
-            let epub3 = self.html.book.options.get_i32("epub.version").unwrap() == 3;
             let template = compile_str(
-                if epub3 { epub3::COVER } else { COVER },
+                if self.epub3 { epub3::COVER } else { COVER },
                 &self.html.book.source,
                 "cover.xhtml",
             )?;
@@ -334,8 +762,8 @@ impl<'a> EpubRenderer<'a> {
                         .handler
                         .map_image(&self.html.source, Cow::Owned(cover))?
                         .into_owned(),
-                )
-                .build();
+                );
+            let data = self.insert_viewport(data).build();
             let mut res: Vec<u8> = vec![];
             template.render_data(&mut res, &data)?;
             match String::from_utf8(res) {
@@ -355,15 +783,45 @@ impl<'a> EpubRenderer<'a> {
 
     /// Render a chapter
     ///
-    /// Return chapter content and raw title
+    /// Return chapter content and raw title. For fixed-layout books that split the chapter
+    /// in several pages, only the first page is returned.
     pub fn render_chapter(&mut self, v: &[Token], template: &Template) -> Result<(String, String)> {
-        let mut content = String::new();
+        let (mut pages, title) = self.render_chapter_pages(v, template)?;
+        Ok((pages.remove(0), title))
+    }
+
+    /// Render a chapter, with one XHTML file per page for fixed-layout books
+    ///
+    /// Return the content of each page and raw title
+    fn render_chapter_pages(
+        &mut self,
+        v: &[Token],
+        template: &Template,
+    ) -> Result<(Vec<String>, String)> {
+        // Each page is an image and the text overlaid on it; a reflowable
+        // chapter is a single "page" with only text
+        let mut pages = vec![(String::new(), String::new())];
+        self.current_page = 0;
 
         for token in v {
+            if self.fixed_layout == Some(Pages::Image) {
+                if let Token::StandaloneImage(..) = *token {
+                    if !pages.last().unwrap().0.is_empty() {
+                        pages.push((String::new(), String::new()));
+                        self.current_page += 1;
+                    }
+                    pages.last_mut().unwrap().0 = self.render_token(token)?;
+                    continue;
+                }
+            }
+            let content = &mut pages.last_mut().unwrap().1;
             content.push_str(&self.render_token(token)?);
-            self.html.render_side_notes(&mut content);
+            // Floating side notes break on Kindle, use end notes instead
+            if self.profile != Profile::Kindle {
+                self.html.render_side_notes(content);
+            }
         }
-        self.html.render_end_notes(&mut content);
+        self.html.render_end_notes(&mut pages.last_mut().unwrap().1);
 
         if self.chapter_title.is_empty() && self.html.current_numbering >= 1 {
             let number;
@@ -371,6 +829,9 @@ impl<'a> EpubRenderer<'a> {
             if self.html.current_part {
                 number = self.html.current_chapter[0] + 1;
                 header = Header::Part;
+            } else if self.html.current_appendix {
+                number = self.html.current_chapter[1] + 1;
+                header = Header::Appendix;
             } else {
                 number = self.html.current_chapter[1] + 1;
                 header = Header::Chapter;
@@ -393,21 +854,47 @@ impl<'a> EpubRenderer<'a> {
         }
         self.toc.push(self.chapter_title.clone());
 
-        let data = self
-            .html
-            .book
-            .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
-            .insert_str("content", content)
-            .insert_str("chapter_title_raw", self.chapter_title_raw.clone())
-            .insert_str("chapter_title", std::mem::take(&mut self.chapter_title))
-            .build();
-        self.chapter_title = String::new();
-        let mut res: Vec<u8> = vec![];
-        template.render_data(&mut res, &data)?;
-        match String::from_utf8(res) {
-            Err(_) => panic!("{}", lformat!("generated HTML was not utf-8 valid")),
-            Ok(res) => Ok((res, std::mem::take(&mut self.chapter_title_raw))),
+        let book = self.html.book;
+        let (epub_matter, epub_type) = match book.chapters[self.html.current_index].matter {
+            Matter::Front => ("frontmatter", "chapter"),
+            Matter::Appendix => ("backmatter", "appendix"),
+            Matter::Back => ("backmatter", "chapter"),
+            Matter::Main => ("bodymatter", "chapter"),
+        };
+        let epub_type = if self.html.current_part {
+            "part"
+        } else {
+            epub_type
+        };
+        let chapter_title = std::mem::take(&mut self.chapter_title);
+        let mut rendered = vec![];
+        for (image, content) in pages {
+            let content = if self.fixed_layout.is_none() {
+                content
+            } else if content.trim().is_empty() {
+                image
+            } else {
+                format!("{image}<div class = \"overlay\">\n{content}</div>\n")
+            };
+            let data = book.get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
+            let data = book
+                .get_chapter_metadata(self.html.current_index, data, |s| {
+                    self.render_vec(&Parser::new().parse_inline(s)?)
+                })?
+                .insert_str("epub_matter", epub_matter)
+                .insert_str("epub_type", epub_type)
+                .insert_str("content", content)
+                .insert_str("chapter_title_raw", self.chapter_title_raw.clone())
+                .insert_str("chapter_title", chapter_title.clone());
+            let data = self.insert_viewport(data).build();
+            let mut res: Vec<u8> = vec![];
+            template.render_data(&mut res, &data)?;
+            match String::from_utf8(res) {
+                Err(_) => panic!("{}", lformat!("generated HTML was not utf-8 valid")),
+                Ok(res) => rendered.push(res),
+            }
         }
+        Ok((rendered, std::mem::take(&mut self.chapter_title_raw)))
     }
 
     /// Renders the header section of the book, finding the title of the chapter
@@ -433,6 +920,9 @@ impl<'a> EpubRenderer<'a> {
             if self.html.current_part {
                 header = Header::Part;
                 number = self.html.current_chapter[0] + 1;
+            } else if self.html.current_appendix {
+                header = Header::Appendix;
+                number = self.html.current_chapter[1] + 1;
             } else {
                 header = Header::Chapter;
                 number = self.html.current_chapter[1] + 1;
@@ -561,12 +1051,7 @@ impl<'a> EpubRenderer<'a> {
                 HtmlRenderer::static_render_token(this, token)
             }
             Token::FootnoteReference(ref reference) => {
-                let epub3 = (this.as_ref() as &HtmlRenderer)
-                    .book
-                    .options
-                    .get_i32("epub.version")
-                    .unwrap()
-                    == 3;
+                let epub3 = (this.as_ref() as &EpubRenderer).epub3;
 
                 Ok(format!(
                     "<a {} href = \"#note-dest-{reference}\"><sup id = \
@@ -575,12 +1060,9 @@ impl<'a> EpubRenderer<'a> {
                 ))
             }
             Token::FootnoteDefinition(ref reference, ref vec) => {
-                let epub3 = (this.as_ref() as &HtmlRenderer)
-                    .book
-                    .options
-                    .get_i32("epub.version")
-                    .unwrap()
-                    == 3;
+                let epub: &EpubRenderer = this.as_ref();
+                // Kindle hides asides, so notes are displayed as regular end notes
+                let aside = epub.epub3 && epub.profile != Profile::Kindle;
                 let inner_content = this.render_vec(vec)?;
                 let html: &mut HtmlRenderer = this.as_mut();
                 let note_number = format!(
@@ -588,7 +1070,7 @@ impl<'a> EpubRenderer<'a> {
   <a href = \"#note-source-{reference}\">[{reference}]</a>
 </p>\n",
                 );
-                let inner = if epub3 {
+                let inner = if aside {
                     format!(
                         "<aside epub:type = \"footnote\" id = \"note-dest-{reference}\">{inner_content}</aside>"
                     )
@@ -599,6 +1081,25 @@ impl<'a> EpubRenderer<'a> {
 
                 Ok(String::new())
             }
+            Token::PageBreak(ref label) => {
+                let epub3 = (this.as_ref() as &EpubRenderer).epub3;
+                let page = (this.as_ref() as &EpubRenderer).current_page;
+                let html: &HtmlRenderer = this.as_ref();
+                let id = HtmlRenderer::page_break_id(label);
+                let href = format!("{}#{id}", page_filenamer(html.current_index, page));
+                let epub: &mut EpubRenderer = this.as_mut();
+                epub.page_list.push((href, label.clone()));
+
+                let label = escape::html(label.as_str());
+                if epub3 {
+                    Ok(format!(
+                        "<span epub:type = \"pagebreak\" id = \"{id}\" role = \"doc-pagebreak\" \
+                         aria-label = \"{label}\" title = \"{label}\"></span>"
+                    ))
+                } else {
+                    Ok(format!("<span id = \"{id}\" title = \"{label}\"></span>"))
+                }
+            }
             _ => HtmlRenderer::static_render_token(this, token),
         }
     }
@@ -609,8 +1110,210 @@ fn filenamer(i: usize) -> String {
     format!("chapter_{i:03}.xhtml")
 }
 
+/// Generate the file name of a page of a chapter, for fixed-layout books
+fn page_filenamer(i: usize, page: usize) -> String {
+    if page == 0 {
+        filenamer(i)
+    } else {
+        format!("chapter_{i:03}_{page:03}.xhtml")
+    }
+}
+
 derive_html! {EpubRenderer<'a>, EpubRenderer::static_render_token}
 
+/// What the EPUB builder doesn't generate itself, added to the EPUB once it is generated:
+/// metadata in the OPF file, landmarks and page list in the navigation document,
+/// the encryption file listing obfuscated fonts and media overlays
+struct EpubPatch {
+    /// Unique identifier of the book, replacing the one generated by the EPUB builder
+    identifier: String,
+    /// Additional files (path and content), replacing generated files with the same path
+    files: Vec<(String, String)>,
+    /// Metadata added at the end of the OPF `<metadata>` element
+    opf_metadata: String,
+    /// Navigation elements added at the end of the navigation document
+    navigation: String,
+    /// Media overlays, added to the manifest and written as additional files
+    overlays: Vec<Overlay>,
+}
+
+impl EpubPatch {
+    /// Rewrites the EPUB generated by the EPUB builder to `to`, patching the package
+    /// and navigation documents and adding the additional files
+    ///
+    /// Other files are copied as they are, so the `mimetype` file stays first and stored.
+    fn apply(&self, epub: &[u8], to: &mut dyn Write) -> Result<()> {
+        let error = |err: &dyn std::fmt::Display| {
+            Error::render(
+                Source::empty(),
+                lformat!("error during EPUB generation: {error}", error = err),
+            )
+        };
+        let mut archive = ZipArchive::new(Cursor::new(epub)).map_err(|e| error(&e))?;
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for i in 0..archive.len() {
+            let file = archive.by_index_raw(i).map_err(|e| error(&e))?;
+            let name = file.name().to_owned();
+            let file_name = name.rsplit('/').next().unwrap_or("");
+            if self.files.iter().any(|(path, _)| *path == name) {
+                continue;
+            }
+            if !matches!(file_name, "content.opf" | "toc.ncx" | "nav.xhtml") {
+                writer.raw_copy_file(file).map_err(|e| error(&e))?;
+                continue;
+            }
+            drop(file);
+
+            let mut text = String::new();
+            archive
+                .by_index(i)
+                .map_err(|e| error(&e))?
+                .read_to_string(&mut text)
+                .map_err(|e| error(&e))?;
+            match file_name {
+                "content.opf" => self.patch_opf(&mut text),
+                "toc.ncx" => {
+                    replace_between(&mut text, "dtb:uid", "content=\"", "\"", &self.identifier)
+                }
+                _ => self.patch_navigation(&mut text),
+            }
+            writer
+                .start_file(name, FileOptions::default())
+                .map_err(|e| error(&e))?;
+            writer.write_all(text.as_bytes()).map_err(|e| error(&e))?;
+        }
+
+        let overlays = self
+            .overlays
+            .iter()
+            .map(|overlay| (format!("OEBPS/{}", overlay.path), &overlay.smil));
+        let files = self
+            .files
+            .iter()
+            .map(|(path, content)| (path.clone(), content));
+        for (path, content) in files.chain(overlays) {
+            writer
+                .start_file(path, FileOptions::default())
+                .map_err(|e| error(&e))?;
+            writer
+                .write_all(content.as_bytes())
+                .map_err(|e| error(&e))?;
+        }
+        let epub = writer.finish().map_err(|e| error(&e))?.into_inner();
+        to.write_all(&epub).map_err(|e| error(&e))
+    }
+
+    /// Sets the identifier, adds metadata and media overlays to the package document
+    fn patch_opf(&self, text: &mut String) {
+        replace_between(text, "<dc:identifier", ">", "<", &self.identifier);
+        if let Some(pos) = text.find("</metadata>") {
+            text.insert_str(pos, &self.opf_metadata);
+        }
+        for overlay in &self.overlays {
+            let href = format!("\"{}\"", overlay.page);
+            let manifest = text.find("<manifest").unwrap_or(0);
+            if let Some(pos) = text[manifest..].find(&href) {
+                let pos = manifest + pos + href.len();
+                text.insert_str(pos, &format!(" media-overlay=\"{}\"", overlay.id));
+            }
+            if let Some(pos) = text.find("</manifest>") {
+                text.insert_str(
+                    pos,
+                    &format!(
+                        "  <item id=\"{}\" href=\"{}\" media-type=\"application/smil+xml\"/>\n",
+                        overlay.id, overlay.path
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Adds landmarks and page list to the navigation document
+    fn patch_navigation(&self, text: &mut String) {
+        let mut navigation = self.navigation.as_str();
+        // Don't add landmarks twice if the EPUB builder already generated them
+        if text.contains("\"landmarks\"") {
+            navigation = navigation
+                .find("<nav epub:type = \"page-list\"")
+                .map(|pos| &navigation[pos..])
+                .unwrap_or("");
+        }
+        if let Some(pos) = text.rfind("</body>") {
+            text.insert_str(pos, navigation);
+        }
+    }
+}
+
+/// Render the durations of media overlays that must be added to the OPF file
+fn render_durations(overlays: &[Overlay]) -> String {
+    if overlays.is_empty() {
+        return String::new();
+    }
+    let mut res = String::new();
+    let mut total = 0.0;
+    for overlay in overlays {
+        res.push_str(&format!(
+            "    <meta property=\"media:duration\" refines=\"#{}\">{}</meta>\n",
+            overlay.id,
+            media_overlay::clock_value(overlay.duration)
+        ));
+        total += overlay.duration;
+    }
+    res.push_str(&format!(
+        "    <meta property=\"media:duration\">{}</meta>
+    <meta property=\"media:active-class\">-epub-media-overlay-active</meta>\n",
+        media_overlay::clock_value(total)
+    ));
+    res
+}
+
+/// Option file telling Apple Books to use the embedded fonts
+const APPLE_DISPLAY_OPTIONS: &str = "<?xml version = \"1.0\" encoding = \"UTF-8\"?>
+<display_options>
+  <platform name = \"*\">
+    <option name = \"specified-fonts\">true</option>
+  </platform>
+</display_options>
+";
+
+/// Render the encryption file listing obfuscated fonts
+fn render_encryption(fonts: &[Font]) -> String {
+    let mut res = String::from(
+        "<?xml version = \"1.0\" encoding = \"UTF-8\"?>
+<encryption xmlns = \"urn:oasis:names:tc:opendocument:xmlns:container\"
+            xmlns:enc = \"http://www.w3.org/2001/04/xmlenc#\">\n",
+    );
+    for font in fonts {
+        res.push_str(&format!(
+            "  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm = \"http://www.idpf.org/2008/embedding\" />
+    <enc:CipherData>
+      <enc:CipherReference URI = \"OEBPS/fonts/{}\" />
+    </enc:CipherData>
+  </enc:EncryptedData>\n",
+            escape::html(font.file_name())
+        ));
+    }
+    res.push_str("</encryption>\n");
+    res
+}
+
+/// Replaces the text between `end` and `close`, after the first occurence of `start`
+/// that is followed by `end`
+fn replace_between(text: &mut String, start: &str, end: &str, close: &str, replacement: &str) {
+    let begin = match text.find(start) {
+        Some(pos) => pos,
+        None => return,
+    };
+    let begin = match text[begin..].find(end) {
+        Some(pos) => begin + pos + end.len(),
+        None => return,
+    };
+    if let Some(len) = text[begin..].find(close) {
+        text.replace_range(begin..begin + len, &escape::html(replacement));
+    }
+}
+
 pub struct Epub {}
 
 impl BookRenderer for Epub {
@@ -623,3 +1326,19 @@ impl BookRenderer for Epub {
         Ok(())
     }
 }
+
+/// EPUB using the Kindle profile, ready to be converted to KF8/AZW3
+pub struct KindleEpub {}
+
+impl BookRenderer for KindleEpub {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.kindle.epub"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn Write) -> Result<()> {
+        EpubRenderer::new(book)?
+            .profile(Profile::Kindle)
+            .render_book(to)?;
+        Ok(())
+    }
+}
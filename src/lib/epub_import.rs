@@ -0,0 +1,938 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::book::Book;
+use crate::error::{Error, Result, Source};
+use crate::markdown::MarkdownRenderer;
+use crate::pandoc::emit_yaml;
+use crate::token::Token;
+use crate::xml::{self, XmlElement, XmlNode};
+use crate::zipper::Zipper;
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, DirBuilder};
+use std::path::{Path, PathBuf};
+
+use yaml_rust::yaml::Hash;
+use yaml_rust::Yaml;
+
+/// Directory (relative to the book file) where images are extracted
+static RESOURCES_DIR: &str = "resources";
+
+/// Elements that are rendered as blocks, and thus interrupt paragraphs
+static BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "center",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Elements whose content is ignored
+static IGNORED_ELEMENTS: &[&str] = &["head", "script", "style", "title"];
+
+/// A chapter converted from an XHTML document of the spine
+struct ImportedChapter {
+    /// File name of the Markdown file, relative to the book file
+    filename: String,
+    /// Markdown content of the chapter
+    content: String,
+}
+
+/// Imports an EPUB file as a Crowbook project.
+///
+/// Each XHTML document of the spine is converted to a Markdown chapter,
+/// images are extracted to a `resources` directory, and a book configuration
+/// file lists the chapters in spine order.
+///
+/// The EPUB is extracted with the `unzip` command, which must be installed.
+///
+/// # Example
+///
+/// ```no_run
+/// use crowbook::{Book, EpubImport};
+/// let import = EpubImport::new("some.epub", &Book::new()).unwrap();
+/// import.write("some/dir/some.book").unwrap();
+/// ```
+pub struct EpubImport {
+    /// Temporary directory where the EPUB is unzipped
+    zipper: Zipper,
+    /// Metadata, as Crowbook options
+    metadata: Hash,
+    /// Chapters, in spine order
+    chapters: Vec<ImportedChapter>,
+    /// Images, as (path in the EPUB, path relative to the book file)
+    images: Vec<(String, String)>,
+}
+
+impl EpubImport {
+    /// Unzips an EPUB file and converts its content
+    ///
+    /// Only the options of `book` are used, e.g. `crowbook.temp_dir`.
+    pub fn new<P: AsRef<Path>>(epub: P, book: &Book) -> Result<EpubImport> {
+        let epub = epub.as_ref();
+        let source = Source::new(format!("{}", epub.display()).as_str());
+        let bytes = fs::read(epub).map_err(|_| {
+            Error::file_not_found(
+                Source::empty(),
+                lformat!("EPUB file"),
+                format!("{}", epub.display()),
+            )
+        })?;
+        let mut zipper = Zipper::new(&book.options.get_path("crowbook.temp_dir")?)?;
+        zipper.write("import.epub", &bytes, false)?;
+        zipper.unzip("import.epub")?;
+
+        let read = |path: &str| {
+            fs::read_to_string(zipper.path().join(path)).map_err(|_| {
+                Error::file_not_found(&source, lformat!("file in EPUB"), path.to_owned())
+            })
+        };
+
+        // Find the package document
+        let container = xml::parse(&read("META-INF/container.xml")?);
+        let opf_path = container
+            .find("rootfile")
+            .and_then(|e| e.attribute("full-path"))
+            .map(|path| resolve("", &percent_decode(path)))
+            .ok_or_else(|| {
+                Error::default(
+                    &source,
+                    lformat!("could not find the package document of this EPUB file"),
+                )
+            })?;
+        let package = xml::parse(&read(&opf_path)?);
+        let opf_dir = parent_dir(&opf_path);
+
+        // Manifest: id -> (path, properties)
+        let mut manifest = HashMap::new();
+        for item in package.find_all("item") {
+            if let (Some(id), Some(href)) = (item.attribute("id"), item.attribute("href")) {
+                let properties = item.attribute("properties").unwrap_or("");
+                manifest.insert(
+                    id.to_owned(),
+                    (
+                        resolve(opf_dir, &percent_decode(href)),
+                        properties.to_owned(),
+                    ),
+                );
+            }
+        }
+
+        let mut metadata = Hash::new();
+        let meta = package.find("metadata");
+        let dc = |name: &str| -> Vec<String> {
+            meta.map(|meta| meta.find_all(name))
+                .unwrap_or_default()
+                .iter()
+                .map(|e| collapse_whitespace(&e.text()).trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        for (key, values) in [
+            ("author", dc("creator")),
+            ("title", dc("title")),
+            ("lang", dc("language")),
+        ] {
+            if !values.is_empty() {
+                let value = if key == "author" {
+                    values.join(", ")
+                } else {
+                    values[0].clone()
+                };
+                metadata.insert(Yaml::String(key.to_owned()), Yaml::String(value));
+            }
+        }
+
+        // Cover: EPUB 3 property, or EPUB 2 <meta name="cover">
+        let cover = manifest
+            .values()
+            .find(|(_, properties)| properties.split_whitespace().any(|p| p == "cover-image"))
+            .or_else(|| {
+                package
+                    .find_all("meta")
+                    .into_iter()
+                    .find(|meta| meta.attribute("name") == Some("cover"))
+                    .and_then(|meta| meta.attribute("content"))
+                    .and_then(|id| manifest.get(id))
+            })
+            .map(|(path, _)| path.clone());
+
+        // Spine documents, except navigation documents (EPUB 3) and tables of contents (EPUB 2)
+        let tocs: Vec<String> = package
+            .find_all("reference")
+            .iter()
+            .filter(|reference| reference.attribute("type") == Some("toc"))
+            .filter_map(|reference| reference.attribute("href"))
+            .map(|href| resolve_link(&opf_path, href))
+            .map(|target| target.split('#').next().unwrap_or("").to_owned())
+            .collect();
+        let spine: Vec<String> = package
+            .find("spine")
+            .map(|spine| spine.find_all("itemref"))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|itemref| manifest.get(itemref.attribute("idref")?))
+            .filter(|(path, properties)| {
+                !properties.split_whitespace().any(|p| p == "nav") && !tocs.contains(path)
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        let mut converter = Converter::new(&spine);
+        if let Some(cover) = cover {
+            let file = converter.image(&cover);
+            metadata.insert(Yaml::String("cover".to_owned()), Yaml::String(file));
+        }
+        let documents = spine
+            .iter()
+            .map(|path| Ok((path.clone(), xml::parse(&read(path)?))))
+            .collect::<Result<Vec<_>>>()?;
+        let by_path: HashMap<&str, &XmlElement> = documents
+            .iter()
+            .map(|(path, document)| (path.as_str(), document))
+            .collect();
+        for (path, document) in &documents {
+            converter.collect_notes(&by_path, path, document, false);
+        }
+
+        let mut chapters = vec![];
+        for (path, document) in &documents {
+            let body = document.find("body").unwrap_or(document);
+            let tokens = converter.chapter(path, body);
+            let cover = metadata.get(&Yaml::String("cover".to_owned()));
+            let is_cover = |token: &Token| match token {
+                Token::StandaloneImage(url, ..) | Token::Image(url, ..) => {
+                    cover.and_then(|c| c.as_str()) == Some(url.as_str())
+                }
+                _ => false,
+            };
+            if tokens.iter().all(is_cover) {
+                // Empty document, or cover page
+                continue;
+            }
+            let content = MarkdownRenderer::new(book).render_markdown(&tokens)?;
+            let content = format!("{}\n", content.trim_end());
+            chapters.push(ImportedChapter {
+                filename: converter.chapters[path].clone(),
+                content,
+            });
+        }
+        if converter.superscript {
+            metadata.insert(
+                Yaml::String("crowbook.markdown.superscript".to_owned()),
+                Yaml::Boolean(true),
+            );
+        }
+
+        Ok(EpubImport {
+            zipper,
+            metadata,
+            chapters,
+            images: converter.images,
+        })
+    }
+
+    /// Writes the book configuration file, and the chapters and images
+    /// in the same directory.
+    ///
+    /// Returns an error, without writing anything, if one of these files already exists.
+    pub fn write<P: AsRef<Path>>(&self, book_file: P) -> Result<()> {
+        let book_file = book_file.as_ref();
+        let dir = book_file.parent().unwrap_or_else(|| Path::new(""));
+        let mut files: Vec<PathBuf> = vec![book_file.to_owned()];
+        files.extend(self.chapters.iter().map(|c| dir.join(&c.filename)));
+        files.extend(self.images.iter().map(|(_, file)| dir.join(file)));
+        if let Some(file) = files.iter().find(|file| file.exists()) {
+            return Err(Error::default(
+                Source::empty(),
+                lformat!(
+                    "Could not create file {}: it already exists!",
+                    file.display()
+                ),
+            ));
+        }
+
+        let write = |file: &Path, content: &[u8]| {
+            if let Some(parent) = file.parent() {
+                DirBuilder::new()
+                    .recursive(true)
+                    .create(parent)
+                    .map_err(|_| {
+                        Error::default(
+                            Source::empty(),
+                            lformat!("could not create directory {}", parent.display()),
+                        )
+                    })?;
+            }
+            fs::write(file, content).map_err(|_| {
+                Error::default(
+                    Source::empty(),
+                    lformat!("could not write file {}", file.display()),
+                )
+            })
+        };
+        for chapter in &self.chapters {
+            write(&dir.join(&chapter.filename), chapter.content.as_bytes())?;
+        }
+        for (path, file) in &self.images {
+            let content = fs::read(self.zipper.path().join(path)).map_err(|_| {
+                Error::file_not_found(Source::empty(), lformat!("image in EPUB"), path.clone())
+            })?;
+            write(&dir.join(file), &content)?;
+        }
+
+        let mut config = emit_yaml(self.metadata.clone())?;
+        config.push_str(&lformat!("\n## List of chapters\n"));
+        for chapter in &self.chapters {
+            config.push_str(&format!("- {}\n", chapter.filename));
+        }
+        write(book_file, config.as_bytes())
+    }
+}
+
+/// Converts the XHTML documents of an EPUB to tokens
+struct Converter {
+    /// Markdown file name of each spine document
+    chapters: HashMap<String, String>,
+    /// Extracted images, as (path in the EPUB, path relative to the book file)
+    images: Vec<(String, String)>,
+    /// Notes, indexed by their path in the EPUB (`file#id`)
+    notes: HashMap<String, XmlElement>,
+    /// Targets of the links back from notes to their references
+    backlinks: HashSet<String>,
+    /// Whether superscript or subscript is used
+    superscript: bool,
+
+    /// Path of the document being converted
+    path: String,
+    /// Footnote definitions of the chapter being converted
+    footnotes: Vec<Token>,
+    /// Names of the footnotes already defined in this chapter
+    footnote_names: HashMap<String, String>,
+}
+
+impl Converter {
+    fn new(spine: &[String]) -> Converter {
+        let mut chapters = HashMap::new();
+        let mut used = HashSet::new();
+        for path in spine {
+            let stem = Path::new(path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let filename = unique_name(&mut used, &sanitize(&stem), ".md");
+            chapters.insert(path.clone(), filename);
+        }
+        Converter {
+            chapters,
+            images: vec![],
+            notes: HashMap::new(),
+            backlinks: HashSet::new(),
+            superscript: false,
+            path: String::new(),
+            footnotes: vec![],
+            footnote_names: HashMap::new(),
+        }
+    }
+
+    /// Finds the note references of a document, and records the notes they point to.
+    fn collect_notes(
+        &mut self,
+        documents: &HashMap<&str, &XmlElement>,
+        path: &str,
+        element: &XmlElement,
+        in_sup: bool,
+    ) {
+        for child in element.elements() {
+            if let Some(href) = child.attribute("href") {
+                if child.local_name() == "a" && is_noteref(child, in_sup) {
+                    let target = resolve_link(path, href);
+                    if let Some(id) = child.attribute("id") {
+                        self.backlinks.insert(format!("{path}#{id}"));
+                    }
+                    if let Entry::Vacant(entry) = self.notes.entry(target) {
+                        if let Some((file, id)) = entry.key().split_once('#') {
+                            let note = documents.get(file).and_then(|d| find_by_id(d, id));
+                            if let Some(note) = note {
+                                entry.insert(note.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            let in_sup = in_sup || child.local_name() == "sup";
+            self.collect_notes(documents, path, child, in_sup);
+        }
+    }
+
+    /// Converts the body of a document
+    fn chapter(&mut self, path: &str, body: &XmlElement) -> Vec<Token> {
+        self.path = path.to_owned();
+        self.footnotes.clear();
+        self.footnote_names.clear();
+        let mut tokens = self.blocks(&body.children);
+        tokens.append(&mut self.footnotes);
+        tokens
+    }
+
+    /// Converts a list of nodes in a block context
+    fn blocks(&mut self, nodes: &[XmlNode]) -> Vec<Token> {
+        let mut blocks = vec![];
+        let mut inline = vec![];
+        for node in nodes {
+            match node {
+                XmlNode::Element(e) if BLOCK_ELEMENTS.contains(&e.local_name()) => {
+                    push_paragraph(&mut blocks, std::mem::take(&mut inline));
+                    let mut converted = self.block(e);
+                    blocks.append(&mut converted);
+                }
+                node => inline.extend(self.inline(node, false)),
+            }
+        }
+        push_paragraph(&mut blocks, inline);
+        blocks
+    }
+
+    /// Converts a block element
+    fn block(&mut self, element: &XmlElement) -> Vec<Token> {
+        if self.is_skipped(element) {
+            return vec![];
+        }
+        match element.local_name() {
+            "p" => {
+                let mut blocks = vec![];
+                push_paragraph(&mut blocks, self.inlines(&element.children, false));
+                blocks
+            }
+            name @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                let level = name[1..].parse().unwrap();
+                let inline = self
+                    .inlines(&element.children, false)
+                    .into_iter()
+                    .map(|token| match token {
+                        Token::HardBreak | Token::SoftBreak => Token::Str(String::from(" ")),
+                        token => token,
+                    })
+                    .collect();
+                let inline = trim_inlines(inline);
+                if inline.is_empty() {
+                    vec![]
+                } else {
+                    vec![Token::Header(level, inline)]
+                }
+            }
+            "hr" => vec![Token::Rule],
+            "blockquote" => vec![Token::BlockQuote(self.blocks(&element.children))],
+            "pre" => {
+                let lang = element
+                    .find("code")
+                    .unwrap_or(element)
+                    .attribute("class")
+                    .and_then(|class| {
+                        class
+                            .split_whitespace()
+                            .find_map(|c| c.strip_prefix("language-"))
+                    })
+                    .unwrap_or("");
+                let mut code = element.text();
+                if !code.ends_with('\n') {
+                    code.push('\n');
+                }
+                vec![Token::CodeBlock(lang.to_owned(), code)]
+            }
+            list @ ("ul" | "ol") => {
+                let items: Vec<&XmlElement> = element
+                    .elements()
+                    .filter(|e| e.local_name() == "li" && !self.is_skipped(e))
+                    .collect();
+                let items: Vec<Token> = items
+                    .into_iter()
+                    .map(|e| Token::Item(self.blocks(&e.children)))
+                    .collect();
+                if items.is_empty() {
+                    vec![]
+                } else if list == "ul" {
+                    vec![Token::List(items)]
+                } else {
+                    let start = element
+                        .attribute("start")
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(1);
+                    vec![Token::OrderedList(start, items)]
+                }
+            }
+            "table" => self.table(element),
+            _ => self.blocks(&element.children),
+        }
+    }
+
+    /// Converts a table
+    fn table(&mut self, table: &XmlElement) -> Vec<Token> {
+        let mut rows = vec![];
+        let mut columns = 0;
+        for row in table.find_all("tr") {
+            let cells: Vec<Token> = row
+                .elements()
+                .filter(|e| matches!(e.local_name(), "td" | "th"))
+                .map(|e| Token::TableCell(trim_inlines(self.inlines(&e.children, false))))
+                .collect();
+            columns = columns.max(cells.len());
+            let is_head = rows.is_empty() && row.elements().all(|e| e.local_name() == "th");
+            rows.push(if is_head {
+                Token::TableHead(cells)
+            } else {
+                Token::TableRow(cells)
+            });
+        }
+        if rows.is_empty() {
+            return vec![];
+        }
+        // Markdown tables need a header and rows of the same length
+        if !matches!(rows[0], Token::TableHead(_)) {
+            rows.insert(0, Token::TableHead(vec![]));
+        }
+        for row in &mut rows {
+            if let Token::TableHead(cells) | Token::TableRow(cells) = row {
+                cells.resize(columns, Token::TableCell(vec![]));
+            }
+        }
+        vec![Token::Table(columns as i32, rows)]
+    }
+
+    /// Converts a list of nodes in an inline context
+    fn inlines(&mut self, nodes: &[XmlNode], in_sup: bool) -> Vec<Token> {
+        nodes
+            .iter()
+            .flat_map(|node| self.inline(node, in_sup))
+            .collect()
+    }
+
+    /// Converts a node in an inline context
+    fn inline(&mut self, node: &XmlNode, in_sup: bool) -> Vec<Token> {
+        let element = match node {
+            XmlNode::Text(text) => return vec![Token::Str(collapse_whitespace(text))],
+            XmlNode::Element(element) => element,
+        };
+        if self.is_skipped(element) {
+            return vec![];
+        }
+        if element.has_token("type", "pagebreak") || element.has_token("role", "doc-pagebreak") {
+            let label = element
+                .attribute("aria-label")
+                .or_else(|| element.attribute("title"))
+                .map(|s| s.to_owned())
+                .unwrap_or_else(|| element.text());
+            let label = label.trim();
+            return if label.is_empty() {
+                vec![]
+            } else {
+                vec![Token::PageBreak(label.to_owned())]
+            };
+        }
+        let children = &element.children;
+        match element.local_name() {
+            "em" | "i" | "cite" | "var" | "dfn" => {
+                wrap(self.inlines(children, in_sup), Token::Emphasis)
+            }
+            "strong" | "b" => wrap(self.inlines(children, in_sup), Token::Strong),
+            "s" | "del" | "strike" => wrap(self.inlines(children, in_sup), Token::Strikethrough),
+            "sup" => {
+                let inner = self.inlines(children, true);
+                if inner
+                    .iter()
+                    .all(|t| matches!(t, Token::FootnoteReference(_)))
+                {
+                    // Superscript only used for a note reference
+                    inner
+                } else {
+                    self.superscript = true;
+                    wrap(inner, Token::Superscript)
+                }
+            }
+            "sub" => {
+                self.superscript = true;
+                wrap(self.inlines(children, in_sup), Token::Subscript)
+            }
+            "code" | "kbd" | "samp" | "tt" => vec![Token::Code(element.text())],
+            "br" => vec![Token::HardBreak],
+            "img" | "image" => {
+                let src = element
+                    .attribute("src")
+                    .or_else(|| element.attribute("href"))
+                    .unwrap_or("");
+                let url = if src.is_empty() || src.starts_with("data:") {
+                    return vec![];
+                } else if src.contains(':') {
+                    // Remote image, keep it as is
+                    src.to_owned()
+                } else {
+                    let path = resolve(parent_dir(&self.path), &percent_decode(src));
+                    self.image(&path)
+                };
+                let title = element.attribute("title").unwrap_or("").to_owned();
+                let alt = element.attribute("alt").unwrap_or("");
+                vec![Token::Image(url, title, vec![Token::Str(alt.to_owned())])]
+            }
+            "a" => {
+                let href = element.attribute("href").unwrap_or("");
+                if is_noteref(element, in_sup) {
+                    let target = resolve_link(&self.path, href);
+                    if let Some(name) = self.footnote(&target) {
+                        return vec![Token::FootnoteReference(name)];
+                    }
+                }
+                let inner = self.inlines(children, in_sup);
+                if href.is_empty() {
+                    return inner;
+                }
+                let title = element.attribute("title").unwrap_or("").to_owned();
+                vec![Token::Link(self.link(href), title, inner)]
+            }
+            name if BLOCK_ELEMENTS.contains(&name) => {
+                // Block inside an inline element: keep its content
+                self.inlines(children, in_sup)
+            }
+            name if IGNORED_ELEMENTS.contains(&name) => vec![],
+            _ => self.inlines(children, in_sup),
+        }
+    }
+
+    /// Returns true if an element is not rendered where it appears
+    fn is_skipped(&self, element: &XmlElement) -> bool {
+        if IGNORED_ELEMENTS.contains(&element.local_name()) {
+            return true;
+        }
+        // Notes are rendered as footnotes of the chapters referencing them
+        if let Some(id) = element.attribute("id") {
+            if self.notes.contains_key(&format!("{}#{id}", self.path)) {
+                return true;
+            }
+        }
+        // Links back to the note references
+        if element.local_name() == "a" {
+            if let Some(href) = element.attribute("href") {
+                if self.backlinks.contains(&resolve_link(&self.path, href)) {
+                    return true;
+                }
+            }
+        }
+        // Sections that only contain notes
+        ["footnotes", "endnotes", "rearnotes", "doc-endnotes"]
+            .iter()
+            .any(|t| element.has_token("type", t) || element.has_token("role", t))
+            || (element.local_name() != "a"
+                && (element.has_token("class", "footnotes") || element.has_token("class", "notes")))
+    }
+
+    /// Returns the name of the footnote for a note, adding its definition to the
+    /// current chapter if needed
+    fn footnote(&mut self, target: &str) -> Option<String> {
+        if let Some(name) = self.footnote_names.get(target) {
+            return Some(name.clone());
+        }
+        let note = self.notes.get(target)?.clone();
+        let id = target.rsplit('#').next().unwrap_or("note");
+        let mut used: HashSet<String> = self.footnote_names.values().cloned().collect();
+        let name = unique_name(&mut used, &sanitize(id), "");
+        self.footnote_names.insert(target.to_owned(), name.clone());
+
+        // The note itself is skipped by `blocks`, so convert its content
+        let content = if BLOCK_ELEMENTS.contains(&note.local_name())
+            && !matches!(
+                note.local_name(),
+                "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+            ) {
+            self.blocks(&note.children)
+        } else {
+            let mut blocks = vec![];
+            push_paragraph(&mut blocks, self.inlines(&note.children, false));
+            blocks
+        };
+        self.footnotes
+            .push(Token::FootnoteDefinition(name.clone(), content));
+        Some(name)
+    }
+
+    /// Returns the path of an image relative to the book file, extracting it if needed
+    fn image(&mut self, path: &str) -> String {
+        if let Some((_, file)) = self.images.iter().find(|(p, _)| p == path) {
+            return file.clone();
+        }
+        let name = Path::new(path)
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) => (stem.to_owned(), format!(".{extension}")),
+            None => (name.clone(), String::new()),
+        };
+        let mut used: HashSet<String> = self
+            .images
+            .iter()
+            .map(|(_, file)| file[RESOURCES_DIR.len() + 1..].to_owned())
+            .collect();
+        let file = format!(
+            "{RESOURCES_DIR}/{}",
+            unique_name(&mut used, &sanitize(&stem), &extension)
+        );
+        self.images.push((path.to_owned(), file.clone()));
+        file
+    }
+
+    /// Converts the target of a link
+    fn link(&self, href: &str) -> String {
+        if href.starts_with('#') || href.contains(':') {
+            return href.to_owned();
+        }
+        let target = resolve_link(&self.path, href);
+        let (file, fragment) = match target.split_once('#') {
+            Some((file, fragment)) => (file, Some(fragment)),
+            None => (target.as_str(), None),
+        };
+        match (self.chapters.get(file), fragment) {
+            (Some(chapter), Some(fragment)) => format!("{chapter}#{fragment}"),
+            (Some(chapter), None) => chapter.clone(),
+            (None, _) => href.to_owned(),
+        }
+    }
+}
+
+/// Finds the element with a given id
+fn find_by_id<'e>(element: &'e XmlElement, id: &str) -> Option<&'e XmlElement> {
+    for child in element.elements() {
+        if child.attribute("id") == Some(id) {
+            return Some(child);
+        }
+        if let Some(found) = find_by_id(child, id) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+/// Returns true if a link is a reference to a note
+fn is_noteref(element: &XmlElement, in_sup: bool) -> bool {
+    element.has_token("type", "noteref")
+        || element.has_token("role", "doc-noteref")
+        || element.has_token("class", "footnote-ref")
+        || element.has_token("class", "noteref")
+        || (element.attribute("href").map_or(false, |h| h.contains('#'))
+            && (in_sup || only_sup(element)))
+}
+
+/// Returns true if the content of an element is a single `sup` element
+fn only_sup(element: &XmlElement) -> bool {
+    let mut elements = element.elements();
+    matches!(elements.next(), Some(e) if e.local_name() == "sup")
+        && elements.next().is_none()
+        && element.children.iter().all(|node| match node {
+            XmlNode::Text(text) => text.trim().is_empty(),
+            XmlNode::Element(_) => true,
+        })
+}
+
+/// Adds a paragraph containing some inline tokens, if they are not only whitespace
+fn push_paragraph(blocks: &mut Vec<Token>, inline: Vec<Token>) {
+    let inline = trim_inlines(inline);
+    if inline.is_empty() {
+        return;
+    }
+    if let [Token::Image(..)] = inline.as_slice() {
+        if let Some(Token::Image(url, title, alt)) = inline.into_iter().next() {
+            blocks.push(Token::StandaloneImage(url, title, alt));
+        }
+    } else {
+        blocks.push(Token::Paragraph(inline));
+    }
+}
+
+/// Wraps inline tokens in e.g. `Token::Emphasis`, moving leading and
+/// trailing whitespace outside (as Markdown requires)
+fn wrap(inner: Vec<Token>, f: fn(Vec<Token>) -> Token) -> Vec<Token> {
+    let starts = matches!(inner.first(), Some(Token::Str(s)) if s.starts_with(' '));
+    let ends = matches!(inner.last(), Some(Token::Str(s)) if s.ends_with(' '));
+    let inner = trim_inlines(inner);
+    let mut result = vec![];
+    if starts {
+        result.push(Token::Str(String::from(" ")));
+    }
+    if !inner.is_empty() {
+        result.push(f(inner));
+    }
+    if ends && !(starts && result.len() == 1) {
+        result.push(Token::Str(String::from(" ")));
+    }
+    result
+}
+
+/// Merges consecutive strings, and removes leading and trailing whitespace
+fn trim_inlines(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = vec![];
+    for token in tokens {
+        if let (Some(Token::Str(previous)), Token::Str(s)) = (result.last_mut(), &token) {
+            if previous.ends_with(' ') {
+                previous.push_str(s.trim_start_matches(' '));
+            } else {
+                previous.push_str(s);
+            }
+            continue;
+        }
+        result.push(token);
+    }
+    if let Some(Token::Str(s)) = result.first_mut() {
+        *s = s.trim_start_matches(' ').to_owned();
+    }
+    if let Some(Token::Str(s)) = result.last_mut() {
+        s.truncate(s.trim_end_matches(' ').len());
+    }
+    result.retain(|t| !matches!(t, Token::Str(s) if s.is_empty()));
+    result
+}
+
+/// Replaces sequences of (non-breaking) whitespace by a single space
+fn collapse_whitespace(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut space = false;
+    for c in s.chars() {
+        if c.is_ascii_whitespace() {
+            if !space {
+                result.push(' ');
+            }
+            space = true;
+        } else {
+            result.push(c);
+            space = false;
+        }
+    }
+    result
+}
+
+/// Returns the directory part of a path inside the EPUB (with a trailing '/')
+fn parent_dir(path: &str) -> &str {
+    path.rfind('/').map_or("", |i| &path[..=i])
+}
+
+/// Resolves a relative path inside the EPUB, removing `.` and `..` components
+pub(crate) fn resolve(dir: &str, href: &str) -> String {
+    let mut components: Vec<&str> = vec![];
+    for component in dir.split('/').chain(href.split('/')) {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+    components.join("/")
+}
+
+/// Resolves a link (possibly only a fragment) found in document `path`, as `file#id`
+pub(crate) fn resolve_link(path: &str, href: &str) -> String {
+    let (file, fragment) = href.split_once('#').unwrap_or((href, ""));
+    let file = if file.is_empty() {
+        path.to_owned()
+    } else {
+        resolve(parent_dir(path), &percent_decode(file))
+    };
+    if fragment.is_empty() {
+        file
+    } else {
+        format!("{file}#{fragment}")
+    }
+}
+
+/// Decodes `%XX` sequences of an URL
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                result.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                result.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Keeps only characters that are safe in file names and footnote names
+fn sanitize(s: &str) -> String {
+    let s: String = s
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if s.is_empty() {
+        String::from("_")
+    } else {
+        s
+    }
+}
+
+/// Returns `stem` + `suffix`, adding a number to the stem if it is already used
+fn unique_name(used: &mut HashSet<String>, stem: &str, suffix: &str) -> String {
+    let mut name = format!("{stem}{suffix}");
+    let mut i = 1;
+    while used.contains(&name) {
+        i += 1;
+        name = format!("{stem}_{i}{suffix}");
+    }
+    used.insert(name.clone());
+    name
+}
@@ -0,0 +1,446 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fonts embedded in EPUB and HTML outputs
+
+use crate::book::Book;
+use crate::error::{Error, Result};
+use crate::lang;
+use crate::resource_handler;
+use crate::text_view::view_as_text;
+use crate::zipper::Zipper;
+
+use rustc_serialize::base64::{self, ToBase64};
+use yaml_rust::Yaml;
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Number of bytes of a font that are obfuscated, according to the IDPF algorithm
+const OBFUSCATED_LENGTH: usize = 1040;
+
+/// A font file embedded in a document
+pub struct Font {
+    /// Path of the font, relative to `resources.base_path.files`
+    pub path: String,
+    /// Family name, used in the generated `@font-face` rule
+    pub family: String,
+    /// Weight, from 100 to 900
+    pub weight: u16,
+    /// Whether the font is italic
+    pub italic: bool,
+    /// Content of the font file
+    pub content: Vec<u8>,
+}
+
+impl Font {
+    /// Reads a font file
+    ///
+    /// Family name, weight and style are read from the `name` and `OS/2` tables
+    /// of TrueType and OpenType fonts, or guessed from the file name
+    /// (e.g. `Lora-BoldItalic.woff2`) for other formats.
+    pub fn new(path: String, content: Vec<u8>) -> Font {
+        let (family, weight, italic) = read_tables(&content).unwrap_or_else(|| {
+            let stem = Path::new(&path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            guess_from_name(&stem)
+        });
+        Font {
+            path,
+            family,
+            weight,
+            italic,
+            content,
+        }
+    }
+
+    /// Returns the extension of the font file, in lowercase
+    fn extension(&self) -> String {
+        Path::new(&self.path)
+            .extension()
+            .map(|s| s.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Returns the name of the font file
+    pub fn file_name(&self) -> String {
+        Path::new(&self.path)
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Returns the content of the font as a data URL
+    pub fn data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.media_type(),
+            self.content.to_base64(base64::STANDARD)
+        )
+    }
+
+    /// Returns the media type of the font
+    pub fn media_type(&self) -> &'static str {
+        match self.extension().as_str() {
+            "otf" => "font/otf",
+            "woff" => "font/woff",
+            "woff2" => "font/woff2",
+            _ => "font/ttf",
+        }
+    }
+
+    /// Returns the format of the font, as used in `@font-face` rules
+    fn format(&self) -> &'static str {
+        match self.extension().as_str() {
+            "otf" => "opentype",
+            "woff" => "woff",
+            "woff2" => "woff2",
+            _ => "truetype",
+        }
+    }
+
+    /// Returns the `@font-face` rule for this font, located at `url`
+    pub fn font_face(&self, url: &str) -> String {
+        format!(
+            "@font-face {{
+    font-family: \"{family}\";
+    font-weight: {weight};
+    font-style: {style};
+    src: url(\"{url}\") format(\"{format}\");
+}}\n",
+            family = self.family.replace('"', "\\\""),
+            weight = self.weight,
+            style = if self.italic { "italic" } else { "normal" },
+            format = self.format(),
+        )
+    }
+
+    /// Only keeps the glyphs needed for the given characters, using an external command
+    ///
+    /// Keeps the font unchanged (with a warning) if it fails.
+    pub fn subset(&mut self, book: &Book, chars: &str) {
+        if let Err(err) = self.try_subset(book, chars) {
+            warn!(
+                "{}",
+                lformat!(
+                    "could not subset font {font}, embedding it entirely: {error}",
+                    font = &self.path,
+                    error = err
+                )
+            );
+        }
+    }
+
+    fn try_subset(&mut self, book: &Book, chars: &str) -> Result<()> {
+        let command_name = book
+            .options
+            .get_str("crowbook.font_subset.command")
+            .unwrap();
+        let mut zipper = Zipper::new(&book.options.get_path("crowbook.temp_dir").unwrap())?;
+        let extension = self.extension();
+        let in_file = format!("font.{extension}");
+        let out_file = format!("subset.{extension}");
+        zipper.write(&in_file, &self.content, false)?;
+        zipper.write("chars.txt", chars.as_bytes(), false)?;
+
+        let mut command = Command::new(command_name);
+        command
+            .current_dir(zipper.path())
+            .arg(&in_file)
+            .arg("--text-file=chars.txt")
+            .arg(format!("--output-file={out_file}"))
+            .arg("--layout-features=*");
+        if extension == "woff" || extension == "woff2" {
+            command.arg(format!("--flavor={extension}"));
+        }
+        let mut content = vec![];
+        zipper.run_command(command, command_name, &out_file, &mut content)?;
+        self.content = content;
+        Ok(())
+    }
+
+    /// Obfuscates the font according to the IDPF algorithm, using the unique identifier of the EPUB
+    ///
+    /// See https://www.w3.org/publishing/epub3/epub-ocf.html#sec-font-obfuscation
+    pub fn obfuscate(&mut self, identifier: &str) {
+        let identifier: String = identifier
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+            .collect();
+        let key = sha1(identifier.as_bytes());
+        for (i, byte) in self.content.iter_mut().take(OBFUSCATED_LENGTH).enumerate() {
+            *byte ^= key[i % key.len()];
+        }
+    }
+}
+
+/// Reads the fonts listed in the `fonts` option of a format (e.g. `epub.fonts`),
+/// and subsets them if `fonts.subset` is set for this format
+pub fn load_fonts(book: &Book, format: &str) -> Result<Vec<Font>> {
+    let key = format!("{format}.fonts");
+    let list = match book.options.get_str_vec(&key) {
+        Ok(list) => list,
+        Err(_) => return Ok(vec![]),
+    };
+    let base_path = book.options.get_path("resources.base_path.files").unwrap();
+    let mut fonts = vec![];
+    for path in resource_handler::get_files(list, &base_path)? {
+        let abs_path = Path::new(&base_path).join(&path);
+        let content = fs::read(&abs_path).map_err(|_| {
+            Error::file_not_found(
+                &book.source,
+                lformat!("font from {key}", key = &key),
+                abs_path.to_string_lossy().into_owned(),
+            )
+        })?;
+        fonts.push(Font::new(path, content));
+    }
+
+    if !fonts.is_empty()
+        && book
+            .options
+            .get_bool(&format!("{format}.fonts.subset"))
+            .unwrap()
+    {
+        let chars = used_chars(book);
+        for font in &mut fonts {
+            font.subset(book, &chars);
+        }
+    }
+    Ok(fonts)
+}
+
+/// Returns all the characters that might be displayed in the book, in order to subset fonts
+fn used_chars(book: &Book) -> String {
+    let mut chars: BTreeSet<char> = (' '..='~').collect();
+    chars.extend("\u{a0}\u{2009}\u{202f}‘’“”«»–—…•".chars());
+    let mut add = |s: &str| {
+        chars.extend(s.chars());
+        chars.extend(book.clean(s).chars());
+    };
+    for key in book.options.get_metadata() {
+        if let Ok(s) = book.options.get_str(key) {
+            add(s);
+        }
+    }
+    for (_, value) in lang::get_hash(book.options.get_str("lang").unwrap()) {
+        if let Yaml::String(s) = value {
+            add(&s);
+        }
+    }
+    for chapter in &book.chapters {
+        add(&view_as_text(&chapter.content));
+        for value in chapter.metadata.values() {
+            add(value);
+        }
+    }
+    chars.into_iter().filter(|c| !c.is_control()).collect()
+}
+
+/// Reads the family name, weight and style of a TrueType or OpenType font
+fn read_tables(content: &[u8]) -> Option<(String, u16, bool)> {
+    let version = read_u32(content, 0)?;
+    if version != 0x0001_0000 && &content[0..4] != b"OTTO" && &content[0..4] != b"true" {
+        return None;
+    }
+    let num_tables = read_u16(content, 4)? as usize;
+    let mut name = None;
+    let mut os2 = None;
+    for i in 0..num_tables {
+        let record = 12 + 16 * i;
+        let tag = content.get(record..record + 4)?;
+        let offset = read_u32(content, record + 8)? as usize;
+        match tag {
+            b"name" => name = Some(offset),
+            b"OS/2" => os2 = Some(offset),
+            _ => {}
+        }
+    }
+
+    let family = read_family(content, name?)?;
+    let (weight, italic) = match os2 {
+        Some(offset) => {
+            let weight = read_u16(content, offset + 4)?;
+            let selection = read_u16(content, offset + 62)?;
+            (weight, selection & 0b10_0000_0001 != 0)
+        }
+        None => (400, false),
+    };
+    Some((family, weight, italic))
+}
+
+/// Reads the family name in the `name` table, preferring the typographic family name
+fn read_family(content: &[u8], table: usize) -> Option<String> {
+    let count = read_u16(content, table + 2)? as usize;
+    let strings = table + read_u16(content, table + 4)? as usize;
+    let mut best: Option<(u16, String)> = None;
+    for i in 0..count {
+        let record = table + 6 + 12 * i;
+        let platform = read_u16(content, record)?;
+        let encoding = read_u16(content, record + 2)?;
+        let language = read_u16(content, record + 4)?;
+        let name_id = read_u16(content, record + 6)?;
+        if name_id != 1 && name_id != 16 {
+            continue;
+        }
+        let length = read_u16(content, record + 8)? as usize;
+        let offset = strings + read_u16(content, record + 10)? as usize;
+        let bytes = content.get(offset..offset + length)?;
+        let name = match (platform, encoding) {
+            (0, _) | (3, 1) | (3, 10) => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            (1, 0) => bytes.iter().map(|&b| b as char).collect(),
+            _ => continue,
+        };
+        // Typographic family first, then english names
+        let score = if name_id == 16 { 2 } else { 0 }
+            + if platform == 3 && language == 0x409 {
+                1
+            } else {
+                0
+            };
+        if best.as_ref().map_or(true, |(s, _)| score > *s) {
+            best = Some((score, name));
+        }
+    }
+    best.map(|(_, name)| name).filter(|name| !name.is_empty())
+}
+
+/// Guesses the family name, weight and style of a font from its file name
+fn guess_from_name(stem: &str) -> (String, u16, bool) {
+    let (family, style) = match stem.rfind('-') {
+        Some(pos) => (&stem[..pos], stem[pos + 1..].to_lowercase()),
+        None => (stem, String::new()),
+    };
+    let weights = [
+        ("thin", 100),
+        ("extralight", 200),
+        ("light", 300),
+        ("medium", 500),
+        ("semibold", 600),
+        ("extrabold", 800),
+        ("bold", 700),
+        ("black", 900),
+    ];
+    let weight = weights
+        .iter()
+        .find(|(name, _)| style.contains(name))
+        .map_or(400, |&(_, weight)| weight);
+    let italic = style.contains("italic") || style.contains("oblique");
+    (family.replace('_', " "), weight, italic)
+}
+
+fn read_u16(content: &[u8], offset: usize) -> Option<u16> {
+    let bytes = content.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(content: &[u8], offset: usize) -> Option<u32> {
+    let bytes = content.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Computes the SHA-1 digest of some data, used for font obfuscation
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha1_digest() {
+        let hex =
+            |digest: [u8; 20]| -> String { digest.iter().map(|b| format!("{b:02x}")).collect() };
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+
+    #[test]
+    fn font_names() {
+        assert_eq!(
+            guess_from_name("Lora-BoldItalic"),
+            (String::from("Lora"), 700, true)
+        );
+        assert_eq!(
+            guess_from_name("Open_Sans-SemiBold"),
+            (String::from("Open Sans"), 600, false)
+        );
+        assert_eq!(
+            guess_from_name("Garamond"),
+            (String::from("Garamond"), 400, false)
+        );
+    }
+}
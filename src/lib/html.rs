@@ -20,11 +20,13 @@ use crate::book::HeaderData;
 use crate::book::{compile_str, Book};
 use crate::error::{Error, Result, Source};
 use crate::lang;
+use crate::misc;
 use crate::number::Number;
 use crate::parser::Parser;
 use crate::renderer::Renderer;
 use crate::resource_handler::ResourceHandler;
-use crate::syntax::Syntax;
+use crate::syntax::{self, CodeInfo, Syntax};
+use crate::token::Attributes;
 use crate::token::Data;
 use crate::token::Token;
 
@@ -67,6 +69,9 @@ pub struct HtmlRenderer<'a> {
     /// Proofread or not
     pub proofread: bool,
 
+    /// Kind of document that is rendered (`html` or `epub`), for conditional blocks
+    pub format: &'static str,
+
     /// Current part, chapter (and subsection, subsubsection and so on)
     #[doc(hidden)]
     pub current_chapter: [i32; 7],
@@ -83,6 +88,14 @@ pub struct HtmlRenderer<'a> {
     #[doc(hidden)]
     pub current_part: bool,
 
+    /// Whether current chapter is an appendix (numbered with letters)
+    #[doc(hidden)]
+    pub current_appendix: bool,
+
+    /// Index of current chapter in the book
+    #[doc(hidden)]
+    pub current_index: usize,
+
     /// Resource handler
     #[doc(hidden)]
     pub handler: ResourceHandler,
@@ -113,12 +126,12 @@ pub struct HtmlRenderer<'a> {
 }
 
 impl<'a> HtmlRenderer<'a> {
-    fn get_highlight(book: &Book, theme: &str) -> (Highlight, Option<Syntax>) {
-        match book.options.get_str("rendering.highlight").unwrap() {
+    fn get_highlight(book: &Book, theme: &str) -> Result<(Highlight, Option<Syntax>)> {
+        let res = match book.options.get_str("rendering.highlight").unwrap() {
             "syntect" => {
                 // Don't init syntect if codeblocks are not used
                 if book.features.codeblock {
-                    (Highlight::Syntect, Some(Syntax::new(theme)))
+                    (Highlight::Syntect, Some(Syntax::new(book, theme)?))
                 } else {
                     (Highlight::None, None)
                 }
@@ -132,12 +145,13 @@ impl<'a> HtmlRenderer<'a> {
                 );
                 (Highlight::None, None)
             }
-        }
+        };
+        Ok(res)
     }
 
     /// Creates a new HTML renderer
     pub fn new(book: &'a Book, theme: &str) -> Result<HtmlRenderer<'a>> {
-        let (highlight, syntax) = Self::get_highlight(book, theme);
+        let (highlight, syntax) = Self::get_highlight(book, theme)?;
 
         let mut html = HtmlRenderer {
             book,
@@ -146,6 +160,8 @@ impl<'a> HtmlRenderer<'a> {
             current_chapter: [0, 0, 0, 0, 0, 0, 0],
             current_numbering: book.options.get_i32("rendering.num_depth").unwrap(),
             current_part: false,
+            current_index: 0,
+            current_appendix: false,
             current_par: 0,
             current_hide: false,
             table_head: false,
@@ -158,6 +174,7 @@ impl<'a> HtmlRenderer<'a> {
             first_letter: false,
             first_paragraph: true,
             proofread: false,
+            format: "html",
             syntax,
             highlight,
             part_template_html: compile_str(
@@ -191,8 +208,10 @@ impl<'a> HtmlRenderer<'a> {
         let book_numbering = self.book.options.get_i32("rendering.num_depth").unwrap();
         match n {
             Number::Unnumbered | Number::UnnumberedPart => self.current_numbering = 0,
-            Number::Default | Number::DefaultPart => self.current_numbering = book_numbering,
-            Number::Specified(n) => {
+            Number::Default | Number::DefaultPart | Number::DefaultAppendix => {
+                self.current_numbering = book_numbering
+            }
+            Number::Specified(n) | Number::SpecifiedAppendix(n) => {
                 self.current_numbering = book_numbering;
                 self.current_chapter[1] = n - 1;
             }
@@ -206,6 +225,8 @@ impl<'a> HtmlRenderer<'a> {
             }
         } //          _ => panic!("Parts are not supported yet"),
         self.current_part = n.is_part();
+        self.current_appendix = n.is_appendix();
+        self.current_index = i;
 
         self.filename = filename;
     }
@@ -238,6 +259,8 @@ impl<'a> HtmlRenderer<'a> {
         if n <= 1 && self.current_numbering >= 1 {
             let header = if n == 0 {
                 Header::Part
+            } else if self.current_appendix {
+                Header::Appendix
             } else {
                 Header::Chapter
             };
@@ -270,11 +293,6 @@ impl<'a> HtmlRenderer<'a> {
             if self.current_hide {
                 Ok(format!("<h1 id = \"link-{}\"></h1>", self.link_number))
             } else {
-                let template = if self.current_part {
-                    &self.part_template_html
-                } else {
-                    &self.chapter_template_html
-                };
                 let has_number = !data.header.is_empty();
                 let has_title = !data.title.is_empty();
                 let data = MapBuilder::new()
@@ -283,8 +301,18 @@ impl<'a> HtmlRenderer<'a> {
                     .insert_str("header", data.header)
                     .insert_str("number", data.number)
                     .insert_str("link", format!("{}", self.link_number))
-                    .insert_str("title", data.title)
+                    .insert_str("title", data.title);
+                let book = self.book;
+                let data = book
+                    .get_chapter_metadata(self.current_index, data, |s| {
+                        self.render_vec(&Parser::from(book).parse_inline(s)?)
+                    })?
                     .build();
+                let template = if self.current_part {
+                    &self.part_template_html
+                } else {
+                    &self.chapter_template_html
+                };
                 let mut res = vec![];
                 template.render_data(&mut res, &data)?;
                 Ok(String::from_utf8(res)?)
@@ -343,7 +371,14 @@ impl<'a> HtmlRenderer<'a> {
                     break;
                 }
             }
-            if i != 1
+            if i == 1 && self.current_appendix && self.current_chapter[i] >= 1 {
+                write!(
+                    output,
+                    "{}.",
+                    misc::to_letters(self.current_chapter[i] as u32)
+                )
+                .unwrap();
+            } else if i != 1
                 || !self
                     .book
                     .options
@@ -420,6 +455,16 @@ impl<'a> HtmlRenderer<'a> {
         }
     }
 
+    /// Returns the id of the anchor for a page break of the print edition
+    #[doc(hidden)]
+    pub fn page_break_id(label: &str) -> String {
+        let label: String = label
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect();
+        format!("page-{label}")
+    }
+
     /// Renders a token
     ///
     /// Used by render_token implementation of Renderer trait. Separate function
@@ -458,6 +503,14 @@ impl<'a> HtmlRenderer<'a> {
                     Ok(content)
                 }
             }
+            Token::Conditional(ref conditions, ref v) => {
+                let html = this.as_ref();
+                if html.book.is_rendered(conditions, html.format) {
+                    this.as_mut().render_vec(v)
+                } else {
+                    Ok(String::new())
+                }
+            }
             Token::Str(ref text) => {
                 let mut content = if this.as_ref().verbatim {
                     Cow::Borrowed(text.as_ref())
@@ -482,12 +535,13 @@ impl<'a> HtmlRenderer<'a> {
             Token::Paragraph(ref vec) => {
                 if this.as_ref().first_paragraph {
                     this.as_mut().first_paragraph = false;
-                    if !vec.is_empty() && vec[0].is_str() {
+                    let first = vec.iter().find(|t| !matches!(t, Token::Attributes(_)));
+                    if first.map_or(false, |t| t.is_str()) {
                         // Only use initials if first element is a Token::str
                         this.as_mut().first_letter = true;
                     }
                 }
-                let class = if this.as_ref().first_letter
+                let class: &[&str] = if this.as_ref().first_letter
                     && this
                         .as_ref()
                         .book
@@ -495,14 +549,16 @@ impl<'a> HtmlRenderer<'a> {
                         .get_bool("rendering.initials")
                         .unwrap()
                 {
-                    " class = \"first-para\""
+                    &["first-para"]
                 } else {
-                    ""
+                    &[]
                 };
                 let content = this.render_vec(vec)?;
                 this.as_mut().current_par += 1;
                 let par = this.as_ref().current_par;
-                Ok(format!("<p id = \"para-{par}\"{class}>{content}</p>\n"))
+                let attributes =
+                    render_attributes(token.attributes(), Some(format!("para-{par}")), class);
+                Ok(format!("<p{attributes}>{content}</p>\n"))
             }
             Token::Header(n, ref vec) => {
                 let data = this.as_mut().render_title(n, vec)?;
@@ -528,7 +584,14 @@ impl<'a> HtmlRenderer<'a> {
                             .add(TocElement::new(url, data.text.clone()).level(n - 1));
                     }
                 }
-                Ok(this.as_mut().render_title_full(n, data)?)
+                let title = this.as_mut().render_title_full(n, data)?;
+                match token.attributes() {
+                    Some(attributes) => Ok(format!(
+                        "<div{}>\n{title}</div>\n",
+                        render_attributes(Some(attributes), None, &[])
+                    )),
+                    None => Ok(title),
+                }
             }
             Token::TaskItem(checked, ref vec) => Ok(format!(
                 "<input type = \"checkbox\" disabled = \"\" {}/>{}",
@@ -545,20 +608,42 @@ impl<'a> HtmlRenderer<'a> {
                 "<blockquote>{}</blockquote>\n",
                 this.render_vec(vec)?
             )),
-            Token::CodeBlock(ref language, ref s) => {
-                let output = if let Some(ref syntax) = this.as_ref().syntax {
-                    syntax.to_html(s, language)?
-                } else if language.is_empty() {
-                    format!("<pre><code>{s}</code></pre>\n")
+            Token::CodeBlock(ref info, ref s) => {
+                let html: &HtmlRenderer = this.as_ref();
+                let mut info = CodeInfo::new(info);
+                info.line_numbers |= html
+                    .book
+                    .options
+                    .get_bool("rendering.code.line_numbers")
+                    .unwrap();
+                let output = if let Some(ref syntax) = html.syntax {
+                    syntax.to_html(s, &info)?
                 } else {
-                    format!(
-                        "<pre><code class = \"language-{language}\">{}</code></pre>\n",
-                        escape::html(s)
-                    )
+                    syntax::plain_html(s, &info)
                 };
-                Ok(output)
+                Ok(info.html_block(output))
             }
             Token::Rule => Ok(String::from("<p class = \"rule\">***</p>\n")),
+            Token::SceneBreak => {
+                let html: &mut HtmlRenderer = this.as_mut();
+                let options = &html.book.options;
+                let text = escape::html(options.get_str("rendering.scene_break").unwrap());
+                if let Ok(image) = options.get_path("rendering.scene_break.image") {
+                    let url = html.handler.map_image(&html.source, image)?;
+                    Ok(format!(
+                        "<p class = \"scene-break\"><img src = \"{url}\" alt = \"{text}\" /></p>\n"
+                    ))
+                } else if text.trim().is_empty() {
+                    Ok(String::from("<p class = \"scene-break\">&#160;</p>\n"))
+                } else {
+                    Ok(format!("<p class = \"scene-break\">{text}</p>\n"))
+                }
+            }
+            Token::PageBreak(ref label) => Ok(format!(
+                "<span id = \"{}\" role = \"doc-pagebreak\" aria-label = \"{}\"></span>",
+                HtmlRenderer::page_break_id(label),
+                escape::html(label.as_str())
+            )),
             Token::SoftBreak => Ok(String::from(" ")),
             Token::HardBreak => Ok(String::from("<br />\n")),
             Token::List(ref vec) => Ok(format!("<ul>\n{}</ul>\n", this.render_vec(vec)?)),
@@ -590,12 +675,13 @@ impl<'a> HtmlRenderer<'a> {
                 };
 
                 Ok(format!(
-                    "<a href = \"{url}\"{}>{}</a>",
+                    "<a href = \"{url}\"{}{}>{}</a>",
                     if title.is_empty() {
                         String::new()
                     } else {
                         format!(" title = \"{title}\"")
                     },
+                    render_attributes(token.attributes(), None, &[]),
                     this.render_vec(vec)?
                 ))
             }
@@ -606,18 +692,49 @@ impl<'a> HtmlRenderer<'a> {
                 let url = html.handler.map_image(&html.source, url.as_str())?;
 
                 if token.is_image() {
+                    let attributes = render_attributes(token.attributes(), None, &[]);
                     Ok(format!(
-                        "<img src = \"{url}\" title = \"{title}\" alt = \"{content}\" />",
+                        "<img src = \"{url}\" title = \"{title}\" alt = \"{content}\"{attributes} />",
                     ))
                 } else {
+                    let attributes = render_attributes(token.attributes(), None, &["image"]);
                     Ok(format!(
-                        "<div class = \"image\">
+                        "<div{attributes}>
   <img src = \"{url}\" title = \"{title}\" alt = \
                                 \"{content}\" />
 </div>",
                     ))
                 }
             }
+            Token::Attributes(_) => Ok(String::new()),
+            Token::Div(ref attributes, ref vec) => Ok(format!(
+                "<div{}>\n{}</div>\n",
+                render_attributes(Some(attributes), None, &[]),
+                this.render_vec(vec)?
+            )),
+            Token::Callout(ref kind, ref vec) => {
+                let html = this.as_ref();
+                let title = lang::get_str(html.book.options.get_str("lang").unwrap(), kind);
+                // Kindle hides asides, so EPUB files use divs
+                let tag = if html.format == "epub" {
+                    "div"
+                } else {
+                    "aside"
+                };
+                let class = format!("callout-{kind}");
+                let attributes =
+                    render_attributes(token.attributes(), None, &["callout", class.as_str()]);
+                Ok(format!(
+                    "<{tag}{attributes}>\n<p class = \"callout-title\">{}</p>\n{}</{tag}>\n",
+                    escape::html(title),
+                    this.render_vec(vec)?
+                ))
+            }
+            Token::Span(ref attributes, ref vec) => Ok(format!(
+                "<span{}>{}</span>",
+                render_attributes(Some(attributes), None, &[]),
+                this.render_vec(vec)?
+            )),
             Token::Table(_, ref vec) => Ok(format!(
                 "<div class = \"table\">
     <table>\n{}
@@ -769,6 +886,45 @@ impl<'a> HtmlRenderer<'a> {
     }
 }
 
+/// Keys of generic attributes that are rendered as is; other ones are rendered as
+/// `data-` attributes
+const HTML_ATTRIBUTES: &[&str] = &["lang", "title", "style", "width", "height", "dir", "role"];
+
+/// Renders generic attributes as HTML attributes (e.g. ` id = "intro" class = "centered"`),
+/// with a default id and some additional classes
+fn render_attributes(
+    attributes: Option<&Attributes>,
+    id: Option<String>,
+    classes: &[&str],
+) -> String {
+    let mut res = String::new();
+    let id = attributes.and_then(|a| a.id.clone()).or(id);
+    if let Some(id) = id {
+        write!(res, " id = \"{}\"", escape::html(id)).unwrap();
+    }
+    let classes: Vec<&str> = classes
+        .iter()
+        .copied()
+        .chain(
+            attributes
+                .into_iter()
+                .flat_map(|a| a.classes.iter().map(|c| c.as_str())),
+        )
+        .collect();
+    if !classes.is_empty() {
+        write!(res, " class = \"{}\"", escape::html(classes.join(" "))).unwrap();
+    }
+    for (key, value) in attributes.into_iter().flat_map(|a| a.pairs.iter()) {
+        let value = escape::html(value.as_str());
+        if HTML_ATTRIBUTES.contains(&key.as_str()) {
+            write!(res, " {key} = \"{value}\"").unwrap();
+        } else {
+            write!(res, " data-{key} = \"{value}\"").unwrap();
+        }
+    }
+    res
+}
+
 impl<'a> AsMut<HtmlRenderer<'a>> for HtmlRenderer<'a> {
     fn as_mut(&mut self) -> &mut HtmlRenderer<'a> {
         self
@@ -15,9 +15,10 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::book::{compile_str, Book};
+use crate::book::{compile_str, Book, Header};
 use crate::book_renderer::BookRenderer;
 use crate::error::{Error, Result, Source};
+use crate::font;
 use crate::html::Highlight;
 use crate::html::HtmlRenderer;
 use crate::parser::Parser;
@@ -71,6 +72,11 @@ impl<'a> HtmlDirRenderer<'a> {
             self.html
                 .handler
                 .add_link(chapter.filename.as_str(), filenamer(i));
+            self.html
+                .handler
+                .add_anchors(&chapter.filename, &chapter.content, |id| {
+                    format!("{}#{id}", filenamer(i))
+                });
         }
 
         if let Ok(metadata) = fs::metadata(dest_path) {
@@ -240,7 +246,12 @@ impl<'a> HtmlDirRenderer<'a> {
                             title = self
                                 .html
                                 .book
-                                .get_chapter_header(
+                                .get_header(
+                                    if n.is_appendix() {
+                                        Header::Appendix
+                                    } else {
+                                        Header::Chapter
+                                    },
                                     self.html.current_chapter[1] + 1,
                                     self.html.render_vec(vec)?,
                                     |s| self.render_vec(&Parser::new().parse_inline(s)?),
@@ -249,7 +260,12 @@ impl<'a> HtmlDirRenderer<'a> {
                             title_raw = self
                                 .html
                                 .book
-                                .get_chapter_header(
+                                .get_header(
+                                    if n.is_appendix() {
+                                        Header::Appendix
+                                    } else {
+                                        Header::Chapter
+                                    },
                                     self.html.current_chapter[1] + 1,
                                     view_as_text(vec),
                                     |s| Ok(view_as_text(&Parser::new().parse_inline(s)?)),
@@ -308,10 +324,13 @@ impl<'a> HtmlDirRenderer<'a> {
             };
 
             // Render each HTML document
-            let mut mapbuilder = self
-                .html
-                .book
-                .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
+            let book = self.html.book;
+            let mapbuilder =
+                book.get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?;
+            let mut mapbuilder = book
+                .get_chapter_metadata(i, mapbuilder, |s| {
+                    self.render_vec(&Parser::new().parse_inline(s)?)
+                })?
                 .insert_str("content", content?)
                 .insert_str("chapter_title", titles[i].clone())
                 .insert_str("json_data", self.html.get_json_ld()?)
@@ -469,6 +488,13 @@ impl<'a> HtmlDirRenderer<'a> {
         if let Ok(html_css_add) = self.html.book.options.get_str("html.css.add") {
             data = data.insert_str("additional_code", html_css_add);
         }
+        let mut font_faces = String::new();
+        for font in font::load_fonts(self.html.book, "html")? {
+            let path = format!("fonts/{}", font.file_name());
+            self.write_file(&path, &font.content)?;
+            font_faces.push_str(&font.font_face(&path));
+        }
+        data = data.insert_str("font_faces", font_faces);
         let data = data.build();
         let mut res: Vec<u8> = vec![];
         template_css.render_data(&mut res, &data)?;
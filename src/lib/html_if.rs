@@ -15,15 +15,17 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::book::{compile_str, Book};
+use crate::book::{compile_str, Book, Header};
 use crate::book_renderer::BookRenderer;
 use crate::error::{Error, Result, Source};
+use crate::font;
 use crate::html::Highlight;
 use crate::html::HtmlRenderer;
 use crate::parser::Parser;
 use crate::renderer::Renderer;
 use crate::token::Token;
 
+use crowbook_text_processing::escape;
 use rustc_serialize::base64::{self, ToBase64};
 
 use std::convert::{AsMut, AsRef};
@@ -183,6 +185,9 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             self.html
                 .handler
                 .add_link(chapter.filename.as_str(), format!("#chapter-{i}"));
+            self.html
+                .handler
+                .add_anchors(&chapter.filename, &chapter.content, |id| format!("#{id}"));
         }
 
         let pre_code = self
@@ -213,7 +218,12 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
                             title = self
                                 .html
                                 .book
-                                .get_chapter_header(
+                                .get_header(
+                                    if n.is_appendix() {
+                                        Header::Appendix
+                                    } else {
+                                        Header::Chapter
+                                    },
                                     self.html.current_chapter[1] + 1,
                                     self.html.render_vec(vec)?,
                                     |s| self.render_vec(&Parser::new().parse_inline(s)?),
@@ -239,8 +249,16 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
                 chapter_content.push_str(&self.parse_inner_code(post_code)?);
             }
 
+            let class = match chapter.metadata.get("class") {
+                Some(class) => format!(
+                    "chapter {} {}",
+                    chapter.matter,
+                    escape::quotes(escape::html(class.as_str()))
+                ),
+                None => format!("chapter {}", chapter.matter),
+            };
             chapters.push(format!(
-                "<div id = \"chapter-{i}\" class = \"chapter\">
+                "<div id = \"chapter-{i}\" class = \"{class}\">
   {chapter_content}
 </div>",
             ));
@@ -275,6 +293,11 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
         if let Ok(html_css_add) = self.html.book.options.get_str("html.css.add") {
             data = data.insert_str("additional_code", html_css_add);
         }
+        let font_faces: String = font::load_fonts(self.html.book, "html")?
+            .iter()
+            .map(|font| font.font_face(&font.data_url()))
+            .collect();
+        data = data.insert_str("font_faces", font_faces);
         let data = data.build();
         let mut res: Vec<u8> = vec![];
         template_css.render_data(&mut res, &data)?;
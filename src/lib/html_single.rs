@@ -15,9 +15,10 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::book::{compile_str, Book};
+use crate::book::{compile_str, Book, Header};
 use crate::book_renderer::BookRenderer;
 use crate::error::{Error, Result, Source};
+use crate::font;
 use crate::html::Highlight;
 use crate::html::HtmlRenderer;
 use crate::parser::Parser;
@@ -25,6 +26,7 @@ use crate::renderer::Renderer;
 use crate::templates::img;
 use crate::token::Token;
 
+use crowbook_text_processing::escape;
 use rustc_serialize::base64::{self, ToBase64};
 
 use std::convert::{AsMut, AsRef};
@@ -102,6 +104,9 @@ impl<'a> HtmlSingleRenderer<'a> {
             self.html
                 .handler
                 .add_link(chapter.filename.as_str(), format!("#chapter-{i}"));
+            self.html
+                .handler
+                .add_anchors(&chapter.filename, &chapter.content, |id| format!("#{id}"));
         }
 
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
@@ -119,7 +124,12 @@ impl<'a> HtmlSingleRenderer<'a> {
                             title = self
                                 .html
                                 .book
-                                .get_chapter_header(
+                                .get_header(
+                                    if n.is_appendix() {
+                                        Header::Appendix
+                                    } else {
+                                        Header::Chapter
+                                    },
                                     self.html.current_chapter[1] + 1,
                                     self.html.render_vec(vec)?,
                                     |s| self.render_vec(&Parser::new().parse_inline(s)?),
@@ -135,11 +145,20 @@ impl<'a> HtmlSingleRenderer<'a> {
             }
             titles.push(title);
 
+            let class = match chapter.metadata.get("class") {
+                Some(class) => format!(
+                    "chapter {} {}",
+                    chapter.matter,
+                    escape::quotes(escape::html(class.as_str()))
+                ),
+                None => format!("chapter {}", chapter.matter),
+            };
             chapters.push(format!(
-                "<div id = \"chapter-{}\" class = \"chapter\">
+                "<div id = \"chapter-{}\" class = \"{}\">
   {}
 </div>",
                 i,
+                class,
                 HtmlRenderer::render_html(self, v, render_notes_chapter)?
             ));
         }
@@ -229,6 +248,11 @@ impl<'a> HtmlSingleRenderer<'a> {
         if let Ok(html_css_add) = self.html.book.options.get_str("html.css.add") {
             data = data.insert_str("additional_code", html_css_add);
         }
+        let font_faces: String = font::load_fonts(self.html.book, "html")?
+            .iter()
+            .map(|font| font.font_face(&font.data_url()))
+            .collect();
+        data = data.insert_str("font_faces", font_faces);
         let data = data.build();
         let mut res: Vec<u8> = vec![];
         template_css.render_data(&mut res, &data)?;
@@ -17,17 +17,26 @@
 
 use crate::book::{compile_str, Book};
 use crate::book_renderer::BookRenderer;
+use crate::chapter::Matter;
 use crate::error::{Error, Result, Source};
+use crate::lang;
+use crate::latex_log::{self, Level};
 use crate::number::Number;
 use crate::parser::Parser;
+use crate::print;
 use crate::renderer::Renderer;
 use crate::resource_handler::ResourceHandler;
-use crate::syntax::Syntax;
+use crate::stats::Stats;
+use crate::syntax::{self, CodeInfo, Syntax};
+use crate::text_view::view_as_text;
+use crate::token::Attributes;
 use crate::token::Data;
 use crate::token::Token;
+use crate::typst::{self, TypstRenderer};
 use crate::zipper::Zipper;
 
 use crowbook_text_processing::escape;
+use mustache::MapBuilder;
 
 use std::borrow::Cow;
 use std::fmt::Write;
@@ -36,6 +45,8 @@ use std::fs::File;
 use std::io;
 use std::io::Read;
 use std::iter::Iterator;
+use std::path::Path;
+use std::process::Command;
 
 /// LaTeX renderer
 pub struct LatexRenderer<'a> {
@@ -48,28 +59,43 @@ pub struct LatexRenderer<'a> {
     first_paragraph: bool,
     is_short: bool,
     proofread: bool,
+    manuscript: bool,
     syntax: Option<Syntax>,
     hyperref: bool,
     enum_level: usize,
+    /// LaTeX environments or commands used for classes, set by `tex.classes`
+    classes: Vec<(String, String)>,
+    /// Number of pages of the last PDF rendering, used to compute the gutter
+    page_count: Option<u32>,
 }
 
+/// Options of the `pdfx` package for PDF/X and PDF/A compliance
+const PDF_STANDARDS: &[&str] = &["x-1a", "x-3", "x-4", "a-1b", "a-2b", "a-2u", "a-3b", "a-3u"];
+
+/// Built-in designs of chapter headings, defined in the template
+const CHAPTER_STYLES: &[&str] = &["default", "centered", "hang", "rule", "smallcaps"];
+
+/// Styles of running headers, defined in the template
+const HEADER_STYLES: &[&str] = &["page", "chapter", "footer"];
+
 impl<'a> LatexRenderer<'a> {
     /// Creates new LatexRenderer
-    pub fn new(book: &'a Book) -> LatexRenderer<'a> {
+    pub fn new(book: &'a Book) -> Result<LatexRenderer<'a>> {
         let mut handler = ResourceHandler::new();
         handler.set_images_mapping(true);
         let syntax = if book.options.get_str("rendering.highlight").unwrap() == "syntect"
             && book.features.codeblock
         {
             Some(Syntax::new(
+                book,
                 book.options
                     .get_str("tex.highlight.theme")
                     .unwrap_or_else(|_| book.options.get_str("rendering.highlight.theme").unwrap()),
-            ))
+            )?)
         } else {
             None
         };
-        LatexRenderer {
+        Ok(LatexRenderer {
             book,
             current_chapter: Number::Default,
             handler,
@@ -79,10 +105,13 @@ impl<'a> LatexRenderer<'a> {
             first_paragraph: true,
             is_short: book.options.get_str("tex.class").unwrap() == "article",
             proofread: false,
+            manuscript: false,
             syntax,
             hyperref: book.options.get_bool("tex.hyperref").unwrap(),
             enum_level: 0,
-        }
+            classes: vec![],
+            page_count: None,
+        })
     }
 
     /// Set proofreading to true
@@ -92,8 +121,48 @@ impl<'a> LatexRenderer<'a> {
         self
     }
 
+    /// Use the standard manuscript format template
+    #[doc(hidden)]
+    pub fn manuscript(mut self) -> Self {
+        self.manuscript = true;
+        self
+    }
+
+    /// Returns the number of pages of the last PDF rendering, if it could be found
+    pub fn page_count(&self) -> Option<u32> {
+        self.page_count
+    }
+
     /// Render pdf to a file
+    ///
+    /// If `tex.margin.gutter` is set to `auto`, the PDF is rendered again if its page
+    /// count requires a wider gutter than the one that was used.
     pub fn render_pdf(&mut self, to: &mut dyn io::Write) -> Result<String> {
+        if self.book.options.get_str("tex.margin.gutter") != Ok("auto") {
+            return self.compile_pdf(to);
+        }
+        let previous = print::gutter(self.page_count.unwrap_or(0));
+        let mut pdf = vec![];
+        let mut output = self.compile_pdf(&mut pdf)?;
+        if print::gutter(self.page_count.unwrap_or(0)) != previous {
+            debug!(
+                "{}",
+                lformat!("Rendering PDF again, with a gutter matching its page count")
+            );
+            pdf.clear();
+            output = self.compile_pdf(&mut pdf)?;
+        }
+        to.write_all(&pdf).map_err(|e| {
+            Error::render(
+                &self.book.source,
+                lformat!("problem when writing PDF: {error}", error = e),
+            )
+        })?;
+        Ok(output)
+    }
+
+    /// Render the book to LaTeX and compile it to PDF
+    fn compile_pdf(&mut self, to: &mut dyn io::Write) -> Result<String> {
         let content = self.render_book()?;
         debug!("{}", lformat!("Attempting to run LaTeX on generated file"));
         let mut zipper = Zipper::new(&self.book.options.get_path("crowbook.temp_dir").unwrap())?;
@@ -114,16 +183,354 @@ impl<'a> LatexRenderer<'a> {
             zipper.write(dest, &content, true)?;
         }
 
-        zipper.generate_pdf(
+        let result = zipper.generate_pdf(
             self.book.options.get_str("tex.command").unwrap(),
             "result.tex",
             to,
-        )
+        );
+
+        // Report what LaTeX complained about, where it comes from in the Markdown files
+        let log = fs::read_to_string(zipper.path().join("result.log")).unwrap_or_default();
+        let mut error = None;
+        for message in latex_log::parse_log(&log, &content) {
+            match message.level {
+                Level::Warning => warn!(
+                    "{}",
+                    lformat!(
+                        "LaTeX ({source}): {message}",
+                        source = message.source,
+                        message = message.text
+                    )
+                ),
+                Level::Error if error.is_none() => error = Some(message),
+                Level::Error => error!(
+                    "{}",
+                    lformat!(
+                        "LaTeX ({source}): {message}",
+                        source = message.source,
+                        message = message.text
+                    )
+                ),
+            }
+        }
+        self.page_count = latex_log::page_count(&log);
+        let output = match (result, error) {
+            (Err(_), Some(message)) => Err(Error::render(
+                message.source,
+                lformat!("LaTeX error: {message}", message = message.text),
+            )),
+            (result, _) => result,
+        }?;
+        if self.book.options.get_bool("tex.fonts.check").unwrap()
+            || self.book.options.get_str("tex.pdf.standard").is_ok()
+        {
+            self.check_fonts(&zipper.path().join("result.pdf"))?;
+        }
+        Ok(output)
+    }
+
+    /// Checks with `pdffonts` that all the fonts of a PDF file are embedded
+    ///
+    /// Missing fonts are an error if the PDF must comply with PDF/X or PDF/A, else a warning.
+    fn check_fonts(&self, pdf: &Path) -> Result<()> {
+        let output = match Command::new("pdffonts").arg(pdf).output() {
+            Ok(output) if output.status.success() => output,
+            _ => {
+                warn!(
+                    "{}",
+                    lformat!("could not run pdffonts, fonts embedding was not checked")
+                );
+                return Ok(());
+            }
+        };
+        let fonts = print::unembedded_fonts(&String::from_utf8_lossy(&output.stdout));
+        if fonts.is_empty() {
+            return Ok(());
+        }
+        let msg = lformat!(
+            "some fonts are not embedded in the PDF: {fonts}",
+            fonts = fonts.join(", ")
+        );
+        if self.book.options.get_str("tex.pdf.standard").is_ok() {
+            Err(Error::render(&self.book.source, msg))
+        } else {
+            warn!("{}", msg);
+            Ok(())
+        }
+    }
+
+    /// Completes template data with the layout of print-ready PDF files, if a trim size is set
+    fn insert_print_layout(&self, data: MapBuilder, book: bool) -> Result<MapBuilder> {
+        let options = &self.book.options;
+        let trim = match options.get_str("tex.trim.size") {
+            Ok(trim) => trim,
+            Err(_) => {
+                if options.get_str("tex.bleed").is_ok()
+                    || options.get_bool("tex.crop_marks").unwrap()
+                    || options.get_str("tex.margin.gutter").is_ok()
+                {
+                    warn!(
+                        "{}",
+                        lformat!(
+                            "tex.bleed, tex.crop_marks and tex.margin.gutter are only used if \
+                             tex.trim.size is set"
+                        )
+                    );
+                }
+                return Ok(data);
+            }
+        };
+        let trim = print::trim_size(trim).ok_or_else(|| {
+            Error::book_option(
+                &self.book.source,
+                lformat!(
+                    "tex.trim.size set to '{value}', expected a width and a height (e.g. '6x9' \
+                     or '148mmx210mm') or one of {names}",
+                    value = trim,
+                    names = print::trim_size_names().join(", ")
+                ),
+            )
+        })?;
+        let dimension = |key: &str, default: &str| -> Result<f64> {
+            let value = options.get_str(key).unwrap_or(default);
+            print::parse_dimension(value, "pt").ok_or_else(|| {
+                Error::book_option(
+                    &self.book.source,
+                    lformat!(
+                        "{key} set to '{value}', which is not a valid dimension",
+                        key = key,
+                        value = value
+                    ),
+                )
+            })
+        };
+        let bleed = dimension("tex.bleed", "0pt")?;
+        let top = dimension("tex.margin.top", "2cm")?;
+        let bottom = dimension("tex.margin.bottom", "1.5cm")?;
+        let mut inner = dimension("tex.margin.left", if book { "2.5cm" } else { "2cm" })?;
+        let outer = dimension("tex.margin.right", if book { "1.5cm" } else { "2cm" })?;
+        match options.get_str("tex.margin.gutter") {
+            Ok("auto") => inner = inner.max(print::gutter(self.page_count.unwrap_or(0))),
+            Ok(_) => inner = inner.max(dimension("tex.margin.gutter", "0pt")?),
+            Err(_) => (),
+        }
+
+        let layout = print::Layout::new(
+            trim,
+            bleed,
+            options.get_bool("tex.crop_marks").unwrap(),
+            [top, bottom, inner, outer],
+        );
+        let mut data = data
+            .insert_bool("print_layout", true)
+            .insert_str("paper_width", print::bp(layout.paper.0))
+            .insert_str("paper_height", print::bp(layout.paper.1))
+            .insert_str("margin_top", print::bp(layout.margins[0]))
+            .insert_str("margin_bottom", print::bp(layout.margins[1]))
+            .insert_str("margin_inner", print::bp(layout.margins[2]))
+            .insert_str("margin_outer", print::bp(layout.margins[3]))
+            .insert_str("page_boxes", layout.page_boxes())
+            .insert_str("page_dict", format!("<<{}>>", layout.page_boxes()));
+        if let Some((width, height)) = layout.stock {
+            data = data
+                .insert_bool("crop_marks", true)
+                .insert_str("stock_width", print::bp(width))
+                .insert_str("stock_height", print::bp(height));
+        }
+        Ok(data)
+    }
+
+    /// Completes template data with the PDF/X or PDF/A standard the PDF must comply with,
+    /// and the metadata it requires
+    fn insert_pdf_standard(&self, data: MapBuilder) -> Result<MapBuilder> {
+        let standard = match self.book.options.get_str("tex.pdf.standard") {
+            Ok(standard) => standard,
+            Err(_) => return Ok(data),
+        };
+        if !PDF_STANDARDS.contains(&standard) {
+            return Err(Error::book_option(
+                &self.book.source,
+                lformat!(
+                    "tex.pdf.standard set to '{value}', valid values are {values}",
+                    value = standard,
+                    values = PDF_STANDARDS.join(", ")
+                ),
+            ));
+        }
+        let mut xmpdata = String::new();
+        for (key, command) in &[
+            ("title", "Title"),
+            ("author", "Author"),
+            ("lang", "Language"),
+            ("description", "Subject"),
+            ("subject", "Keywords"),
+            ("license", "Copyright"),
+        ] {
+            if let Ok(value) = self.book.options.get_str(key) {
+                let text = view_as_text(&Parser::from(self.book).parse_inline(value)?);
+                writeln!(xmpdata, "\\{command}{{{}}}", escape::tex(text))?;
+            }
+        }
+        Ok(data
+            .insert_str("pdf_standard", standard)
+            .insert_bool("pdf_x", standard.starts_with('x'))
+            .insert_str("xmpdata", xmpdata))
+    }
+
+    /// Completes template data with fonts, spacing, headings and headers options
+    fn insert_typography(&self, data: MapBuilder, xelatex: bool) -> Result<MapBuilder> {
+        let options = &self.book.options;
+        let one_of = |key: &str, values: &[&str]| -> Result<String> {
+            let value = options.get_str(key).unwrap();
+            if values.contains(&value) {
+                Ok(value.to_owned())
+            } else {
+                Err(Error::book_option(
+                    &self.book.source,
+                    lformat!(
+                        "{key} set to '{value}', valid values are {values}",
+                        key = key,
+                        value = value,
+                        values = values.join(", ")
+                    ),
+                ))
+            }
+        };
+        let mut data = data;
+
+        for key in &["main", "sans", "mono"] {
+            if let Ok(font) = options.get_str(&format!("tex.font.{key}")) {
+                if xelatex {
+                    data = data
+                        .insert_bool(format!("has_font_{key}"), true)
+                        .insert_str(format!("font_{key}"), font);
+                } else {
+                    warn!(
+                        "{}",
+                        lformat!(
+                            "tex.font.{key} is ignored, as fonts can only be set if tex.command \
+                             is xelatex or tectonic",
+                            key = key
+                        )
+                    );
+                }
+            }
+        }
+        if let Ok(spacing) = options.get_f32("tex.line_spacing") {
+            data = data
+                .insert_bool("has_line_spacing", true)
+                .insert_str("line_spacing", format!("{spacing}"));
+        }
+        match one_of("tex.paragraph.style", &["indent", "spacing"])?.as_str() {
+            "spacing" => data = data.insert_bool("paragraph_spacing", true),
+            _ => {
+                if let Ok(indent) = options.get_str("tex.paragraph.indent") {
+                    data = data
+                        .insert_bool("has_paragraph_indent", true)
+                        .insert_str("paragraph_indent", indent);
+                }
+            }
+        }
+        if let Ok(penalty) = options.get_i32("tex.penalty.widow") {
+            data = data
+                .insert_bool("has_widow_penalty", true)
+                .insert_str("widow_penalty", format!("{penalty}"));
+        }
+        if let Ok(penalty) = options.get_i32("tex.penalty.orphan") {
+            data = data
+                .insert_bool("has_orphan_penalty", true)
+                .insert_str("orphan_penalty", format!("{penalty}"));
+        }
+        let chapter_style = one_of("tex.chapter.style", CHAPTER_STYLES)?;
+        if chapter_style != "default" {
+            data = data
+                .insert_bool("chapter_style", true)
+                .insert_bool(format!("chapter_style_{chapter_style}"), true);
+        }
+        let headers = one_of("tex.headers", HEADER_STYLES)?;
+        data = data.insert_bool(format!("headers_{headers}"), true);
+        data = data.insert_str(
+            "initials_lines",
+            format!("{}", options.get_i32("tex.initials.lines").unwrap()),
+        );
+        if let Ok(oversize) = options.get_f32("tex.initials.oversize") {
+            data = data
+                .insert_bool("has_initials_oversize", true)
+                .insert_str("initials_oversize", format!("{oversize}"));
+        }
+        if options.get_bool("tex.microtype").unwrap() {
+            data = data.insert_bool("microtype", true).insert_str(
+                "microtype_options",
+                options.get_str("tex.microtype.options").unwrap_or(""),
+            );
+        }
+        Ok(data)
+    }
+
+    /// Reads the LaTeX environments or commands used for classes, e.g. `epigraph=flushright`
+    fn read_classes(&self) -> Result<Vec<(String, String)>> {
+        let mut classes = vec![];
+        if let Ok(entries) = self.book.options.get_str_vec("tex.classes") {
+            for entry in entries {
+                let name = entry
+                    .split_once('=')
+                    .map(|(class, name)| (class.trim(), name.trim()))
+                    .filter(|(class, name)| {
+                        !class.is_empty()
+                            && !name.is_empty()
+                            && name.chars().all(|c| c.is_ascii_alphabetic() || c == '*')
+                    });
+                match name {
+                    Some((class, name)) => classes.push((class.to_owned(), name.to_owned())),
+                    None => {
+                        return Err(Error::book_option(
+                            &self.book.source,
+                            lformat!(
+                                "invalid entry '{entry}' in tex.classes, expected \
+                                 'class=environment'",
+                                entry = entry
+                            ),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(classes)
+    }
+
+    /// Renders an element with attributes, adding a label if it has an id, and wrapping it in
+    /// the environments (for blocks) or commands (for inline elements) set for its classes
+    fn render_attributes(
+        &self,
+        attributes: Option<&Attributes>,
+        content: String,
+        block: bool,
+    ) -> String {
+        let attributes = match attributes {
+            Some(attributes) => attributes,
+            None => return content,
+        };
+        let mut res = content;
+        for class in attributes.classes.iter().rev() {
+            if let Some((_, name)) = self.classes.iter().find(|(c, _)| c == class) {
+                res = if block {
+                    format!("\\begin{{{name}}}\n{res}\\end{{{name}}}\n\n")
+                } else {
+                    format!("\\{name}{{{res}}}")
+                };
+            }
+        }
+        match attributes.id {
+            Some(ref id) if self.hyperref => format!("\\phantomsection\\label{{{id}}}{res}"),
+            Some(ref id) => format!("\\label{{{id}}}{res}"),
+            None => res,
+        }
     }
 
     /// Render latex in a string
     pub fn render_book(&mut self) -> Result<String> {
         let mut content = String::new();
+        self.classes = self.read_classes()?;
 
         // set tex numbering and toc display to book's parameters
         let numbering = self.book.options.get_i32("rendering.num_depth").unwrap() - 1;
@@ -140,20 +547,71 @@ impl<'a> LatexRenderer<'a> {
         for (i, chapter) in self.book.chapters.iter().enumerate() {
             self.handler
                 .add_link(chapter.filename.as_str(), format!("chapter-{i}"));
+            self.handler
+                .add_anchors(&chapter.filename, &chapter.content, |id| id.to_owned());
         }
 
+        // \frontmatter, \mainmatter and \backmatter are only defined by book classes
+        let has_matters = !self.manuscript
+            && matches!(
+                self.book.options.get_str("tex.class").unwrap(),
+                "book" | "scrbook" | "memoir"
+            );
+        let mut matter = Matter::Main;
         for (i, chapter) in self.book.chapters.iter().enumerate() {
+            if chapter.matter != matter {
+                match chapter.matter {
+                    Matter::Front if has_matters => content.push_str("\\frontmatter\n"),
+                    Matter::Main if has_matters => content.push_str("\\mainmatter\n"),
+                    Matter::Appendix => {
+                        if matter == Matter::Front && has_matters {
+                            content.push_str("\\mainmatter\n");
+                        }
+                        content.push_str("\\appendix\n");
+                    }
+                    Matter::Back if has_matters => content.push_str("\\backmatter\n"),
+                    _ => (),
+                }
+                matter = chapter.matter;
+            }
             let n = chapter.number;
             self.current_chapter = n;
             let v = &chapter.content;
             self.source = Source::new(chapter.filename.as_str());
+            // Mark where blocks come from, to map LaTeX errors back to Markdown
+            let lines: &[u32] = if chapter.lines.len() == v.len() {
+                &chapter.lines[..]
+            } else {
+                &[]
+            };
+            let marker = |content: &mut String, j: usize| {
+                if !content.ends_with('\n') {
+                    content.push('\n');
+                }
+                content.push_str(&latex_log::source_marker(
+                    &chapter.filename,
+                    lines.get(j).copied(),
+                ));
+            };
             let mut offset = 0;
+            marker(&mut content, 0);
             if !v.is_empty() && v[0].is_header() {
                 content.push_str(&self.render_token(&v[0])?);
                 offset = 1;
             }
             writeln!(content, "\\label{{chapter-{i}}}")?;
-            content.push_str(&self.render_vec(&v[offset..])?);
+            for key in &["subtitle", "author", "date", "epigraph"] {
+                if let Some(value) = chapter.metadata.get(*key) {
+                    let value = self.render_vec(&Parser::from(self.book).parse_inline(value)?)?;
+                    writeln!(content, "\\mdchapter{key}{{{value}}}")?;
+                }
+            }
+            for (j, token) in v.iter().enumerate().skip(offset) {
+                if !lines.is_empty() && j > 0 {
+                    marker(&mut content, j);
+                }
+                content.push_str(&self.render_token(token)?);
+            }
         }
         self.source = Source::empty();
 
@@ -210,10 +668,15 @@ impl<'a> LatexRenderer<'a> {
             }
         });
 
+        let template_name = if self.manuscript {
+            "tex.manuscript.template"
+        } else {
+            "tex.template"
+        };
         let template = compile_str(
-            self.book.get_template("tex.template")?.as_ref(),
+            self.book.get_template(template_name)?.as_ref(),
             &self.book.source,
-            "tex.template",
+            template_name,
         )?;
         let mut data = self
             .book
@@ -238,7 +701,22 @@ impl<'a> LatexRenderer<'a> {
             .insert_bool("use_codeblocks", self.book.features.codeblock)
             .insert_bool("use_images", self.book.features.image)
             .insert_bool("use_strikethrough", self.book.features.strikethrough)
+            .insert_bool("use_callouts", self.book.features.callout)
             .insert_str("tex_lang", tex_lang);
+        if self.manuscript {
+            // Manuscripts usually display word count rounded to the nearest hundred
+            let word_count = Stats::new(self.book, false).word_count();
+            let rounded = std::cmp::max(100, (word_count + 50) / 100 * 100);
+            data = data
+                .insert_str("word_count", format!("{rounded}"))
+                .insert_str(
+                    "manuscript_papersize",
+                    self.book
+                        .options
+                        .get_str("tex.manuscript.paper.size")
+                        .unwrap(),
+                );
+        }
         if let Ok(tex_tmpl_add) = self.book.options.get_str("tex.template.add") {
             data = data.insert_str("additional_code", tex_tmpl_add);
         }
@@ -278,6 +756,9 @@ impl<'a> LatexRenderer<'a> {
                 self.book.options.get_str("tex.margin.top").unwrap(),
             );
 
+        data = self.insert_print_layout(data, book)?;
+        data = self.insert_pdf_standard(data)?;
+
         if let Ok(chapter_name) = self.book.options.get_str("rendering.chapter") {
             data = data.insert_str("chapter_name", chapter_name);
         }
@@ -287,12 +768,24 @@ impl<'a> LatexRenderer<'a> {
         if self.book.options.get_bool("rendering.initials") == Ok(true) {
             data = data.insert_bool("initials", true);
         }
+        data = data.insert_str(
+            "scene_break",
+            escape::tex(self.book.options.get_str("rendering.scene_break").unwrap()),
+        );
+        if let Ok(image) = self.book.options.get_path("rendering.scene_break.image") {
+            let image = self.handler.map_image(&self.book.source, image)?;
+            data = data
+                .insert_bool("use_images", true)
+                .insert_bool("has_scene_break_image", true)
+                .insert_str("scene_break_image", image);
+        }
         // Insert xelatex if tex.command is set to xelatex or tectonic
-        if (self.book.options.get_str("tex.command") == Ok("xelatex"))
-            | (self.book.options.get_str("tex.command") == Ok("tectonic"))
-        {
+        let xelatex = (self.book.options.get_str("tex.command") == Ok("xelatex"))
+            | (self.book.options.get_str("tex.command") == Ok("tectonic"));
+        if xelatex {
             data = data.insert_bool("xelatex", true);
         }
+        data = self.insert_typography(data, xelatex)?;
         let data = data.build();
         let mut res: Vec<u8> = vec![];
         template.render_data(&mut res, &data)?;
@@ -318,7 +811,8 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 };
                 if self.first_letter {
                     self.first_letter = false;
-                    if self.book.options.get_bool("rendering.initials").unwrap() {
+                    if self.book.options.get_bool("rendering.initials").unwrap() && !self.manuscript
+                    {
                         let mut chars = content.chars().peekable();
                         let initial = chars.next().ok_or_else(|| {
                             Error::parser(
@@ -357,12 +851,14 @@ impl<'a> Renderer for LatexRenderer<'a> {
             Token::Paragraph(ref vec) => {
                 if self.first_paragraph {
                     self.first_paragraph = false;
-                    if !vec.is_empty() && vec[0].is_str() {
+                    let first = vec.iter().find(|t| !matches!(t, Token::Attributes(_)));
+                    if first.map_or(false, |t| t.is_str()) {
                         // Only use initials if first element is a Token::str
                         self.first_letter = true;
                     }
                 }
-                Ok(format!("{}\n\n", self.render_vec(vec)?))
+                let content = format!("{}\n\n", self.render_vec(vec)?);
+                Ok(self.render_attributes(token.attributes(), content, true))
             }
             Token::Header(n, ref vec) => {
                 let mut content = String::new();
@@ -374,7 +870,9 @@ impl<'a> Renderer for LatexRenderer<'a> {
                         } else {
                             return Ok(r#"\section*{}"#.to_owned());
                         }
-                    } else if let Number::Specified(n) = self.current_chapter {
+                    } else if let Number::Specified(n) | Number::SpecifiedAppendix(n) =
+                        self.current_chapter
+                    {
                         content.push_str(r"\setcounter{chapter}{");
                         write!(content, "{}", n - 1)?;
                         content.push_str("}\n");
@@ -412,6 +910,9 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 content.push('{');
                 content.push_str(&self.render_vec(vec)?);
                 content.push_str("}\n");
+                if let Some(id) = token.attributes().and_then(|a| a.id.as_ref()) {
+                    writeln!(content, "\\label{{{id}}}")?;
+                }
                 Ok(content)
             }
             Token::TaskItem(checked, ref vec) => Ok(format!(
@@ -437,24 +938,35 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 "\\begin{{mdblockquote}}\n{}\n\\end{{mdblockquote}}\n",
                 self.render_vec(vec)?
             )),
-            Token::CodeBlock(ref language, ref code) => {
+            Token::CodeBlock(ref info, ref code) => {
+                let options = &self.book.options;
+                let mut info = CodeInfo::new(info);
+                info.line_numbers |= options.get_bool("rendering.code.line_numbers").unwrap();
+                let wrap = options.get_bool("tex.code.wrap").unwrap();
                 let mut res: String = if let Some(ref syntax) = self.syntax {
-                    syntax.to_tex(code, language)?
+                    syntax.to_tex(code, &info, wrap)?
                 } else {
-                    format!(
-                        "\\begin{{spverbatim}}
-{code}
-\\end{{spverbatim}}"
-                    )
+                    syntax::plain_tex(code, &info, wrap)
                 };
+                if let Some(ref title) = info.title {
+                    res = format!("\\mdcodetitle{{{}}}\n{res}", escape::tex(title.as_str()));
+                }
                 res = format!(
                     "\\begin{{mdcodeblock}}
 {res}
 \\end{{mdcodeblock}}"
                 );
+                if let Some(ref caption) = info.caption {
+                    res = format!(
+                        "{res}\n\\mdcodecaption{{{}}}",
+                        escape::tex(caption.as_str())
+                    );
+                }
                 Ok(res)
             }
             Token::Rule => Ok(String::from("\\mdrule\n")),
+            Token::SceneBreak => Ok(String::from("\\mdscenebreak\n")),
+            Token::PageBreak(_) => Ok(String::new()),
             Token::SoftBreak => Ok(String::from(" ")),
             Token::HardBreak => Ok(String::from("\\mdhardbreak\n")),
             Token::DescriptionList(ref v) => Ok(format!(
@@ -510,33 +1022,31 @@ impl<'a> Renderer for LatexRenderer<'a> {
             Token::Link(ref url, _, ref vec) => {
                 let content = self.render_vec(vec)?;
 
-                if self.hyperref && self.handler.contains_link(url) {
-                    Ok(format!(
-                        "\\hyperref[{}]{{{content}}}",
-                        escape::tex(self.handler.get_link(url)),
-                    ))
+                let link = if self.hyperref && self.handler.contains_link(url) {
+                    // Labels are chapter numbers or ids, which must not be escaped
+                    format!("\\hyperref[{}]{{{content}}}", self.handler.get_link(url))
                 } else {
                     let url = escape::tex(url.as_str());
                     if content == url {
-                        Ok(format!("\\url{{{content}}}"))
+                        format!("\\url{{{content}}}")
                     } else if self
                         .book
                         .options
                         .get_bool("tex.links_as_footnotes")
                         .unwrap()
                     {
-                        Ok(format!(
-                            "\\href{{{url}}}{{{content}}}\\protect\\footnote{{\\url{{{url}}}}}"
-                        ))
+                        format!("\\href{{{url}}}{{{content}}}\\protect\\footnote{{\\url{{{url}}}}}")
                     } else {
-                        Ok(format!("\\href{{{url}}}{{{content}}}"))
+                        format!("\\href{{{url}}}{{{content}}}")
                     }
-                }
+                };
+                Ok(self.render_attributes(token.attributes(), link, false))
             }
             Token::StandaloneImage(ref url, _, _) => {
                 if ResourceHandler::is_local(url) {
                     let img = self.handler.map_image(&self.source, url.as_str())?;
-                    Ok(format!("\\mdstandaloneimage{{{img}}}\n"))
+                    let img = format!("\\mdstandaloneimage{{{img}}}\n");
+                    Ok(self.render_attributes(token.attributes(), img, true))
                 } else {
                     debug!(
                         "{}",
@@ -552,10 +1062,11 @@ impl<'a> Renderer for LatexRenderer<'a> {
             }
             Token::Image(ref url, _, _) => {
                 if ResourceHandler::is_local(url) {
-                    Ok(format!(
+                    let img = format!(
                         "\\mdimage{{{}}}",
                         self.handler.map_image(&self.source, url.as_str())?
-                    ))
+                    );
+                    Ok(self.render_attributes(token.attributes(), img, false))
                 } else {
                     debug!(
                         "{}",
@@ -624,6 +1135,32 @@ impl<'a> Renderer for LatexRenderer<'a> {
                     Ok(content)
                 }
             }
+            Token::Attributes(_) => Ok(String::new()),
+            Token::Div(ref attributes, ref vec) => {
+                let content = self.render_vec(vec)?;
+                Ok(self.render_attributes(Some(attributes), content, true))
+            }
+            Token::Callout(ref kind, ref vec) => {
+                let title = lang::get_str(self.book.options.get_str("lang").unwrap(), kind);
+                let content = format!(
+                    "\\begin{{mdcallout}}{{{kind}}}{{{}}}\n{}\\end{{mdcallout}}\n\n",
+                    escape::tex(title),
+                    self.render_vec(vec)?
+                );
+                Ok(self.render_attributes(token.attributes(), content, true))
+            }
+            Token::Span(ref attributes, ref vec) => {
+                let content = self.render_vec(vec)?;
+                Ok(self.render_attributes(Some(attributes), content, false))
+            }
+            Token::Conditional(ref conditions, ref vec) => {
+                let format = if self.manuscript { "manuscript" } else { "tex" };
+                if self.book.is_rendered(conditions, format) {
+                    self.render_vec(vec)
+                } else {
+                    Ok(String::new())
+                }
+            }
         }
     }
 }
@@ -632,6 +1169,8 @@ pub struct Latex;
 pub struct ProofLatex;
 pub struct Pdf;
 pub struct ProofPdf;
+pub struct Manuscript;
+pub struct ManuscriptPdf;
 
 impl BookRenderer for Latex {
     fn auto_path(&self, book_name: &str) -> Result<String> {
@@ -639,7 +1178,7 @@ impl BookRenderer for Latex {
     }
 
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        let mut latex = LatexRenderer::new(book);
+        let mut latex = LatexRenderer::new(book)?;
         let result = latex.render_book()?;
         to.write_all(result.as_bytes()).map_err(|e| {
             Error::render(
@@ -657,7 +1196,7 @@ impl BookRenderer for ProofLatex {
     }
 
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        let mut latex = LatexRenderer::new(book).proofread();
+        let mut latex = LatexRenderer::new(book)?.proofread();
         let result = latex.render_book()?;
         to.write_all(result.as_bytes()).map_err(|e| {
             Error::render(
@@ -675,7 +1214,32 @@ impl BookRenderer for Pdf {
     }
 
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        LatexRenderer::new(book).render_pdf(to)?;
+        match book.options.get_str("pdf.engine").unwrap() {
+            "latex" => (),
+            "typst" => {
+                if typst::is_available(book) {
+                    TypstRenderer::new(book).render_pdf(to)?;
+                    return Ok(());
+                }
+                warn!(
+                    "{}",
+                    lformat!(
+                        "could not run '{command}', falling back on LaTeX to render PDF",
+                        command = book.options.get_str("typst.command").unwrap()
+                    )
+                );
+            }
+            value => {
+                return Err(Error::book_option(
+                    &book.source,
+                    lformat!(
+                        "pdf.engine set to '{value}', valid values are 'latex' or 'typst'",
+                        value = value
+                    ),
+                ))
+            }
+        }
+        LatexRenderer::new(book)?.render_pdf(to)?;
         Ok(())
     }
 }
@@ -686,7 +1250,36 @@ impl BookRenderer for ProofPdf {
     }
 
     fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
-        LatexRenderer::new(book).proofread().render_pdf(to)?;
+        LatexRenderer::new(book)?.proofread().render_pdf(to)?;
+        Ok(())
+    }
+}
+
+impl BookRenderer for Manuscript {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.manuscript.tex"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
+        let mut latex = LatexRenderer::new(book)?.manuscript();
+        let result = latex.render_book()?;
+        to.write_all(result.as_bytes()).map_err(|e| {
+            Error::render(
+                &book.source,
+                lformat!("problem when writing LaTeX: {error}", error = e),
+            )
+        })?;
+        Ok(())
+    }
+}
+
+impl BookRenderer for ManuscriptPdf {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.manuscript.pdf"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
+        LatexRenderer::new(book)?.manuscript().render_pdf(to)?;
         Ok(())
     }
 }
@@ -0,0 +1,226 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parsing of LaTeX logs, mapping errors and warnings back to the Markdown sources
+
+use crate::error::Source;
+
+/// Prefix of the comments marking, in the generated LaTeX, where Markdown blocks come from
+const MARKER: &str = "% crowbook-source: ";
+
+/// Length of the lines of the log, that TeX wraps
+const LOG_LINE_LENGTH: usize = 79;
+
+/// Severity of a message of the log
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Level {
+    /// Error, that stopped the compilation or broke the output
+    Error,
+    /// Warning, e.g. an overfull box or an undefined reference
+    Warning,
+}
+
+/// A message of the LaTeX log
+#[derive(Debug, PartialEq)]
+pub struct Message {
+    /// Severity of the message
+    pub level: Level,
+    /// Text of the message
+    pub text: String,
+    /// Markdown file and line the message comes from, if it could be found
+    pub source: Source,
+}
+
+/// Returns a comment marking that the following LaTeX code comes from `file`, at `line`
+pub fn source_marker(file: &str, line: Option<u32>) -> String {
+    match line {
+        Some(line) => format!("{MARKER}{file}:{line}\n"),
+        None => format!("{MARKER}{file}\n"),
+    }
+}
+
+/// Returns true if the log asks to run LaTeX again (e.g. to get cross-references right)
+pub fn needs_rerun(log: &str) -> bool {
+    let log = unwrap_lines(log);
+    log.contains("Rerun to get")
+        || log.contains("Label(s) may have changed")
+        || log.contains("Rerun LaTeX")
+}
+
+/// Returns the number of pages of the output, according to the log
+pub fn page_count(log: &str) -> Option<u32> {
+    let log = unwrap_lines(log);
+    let line = log.lines().find(|l| l.starts_with("Output written on "))?;
+    let (_, count) = line.rsplit_once('(')?;
+    split_number(count).map(|(n, _)| n)
+}
+
+/// Parses the log of a LaTeX run on `tex`
+///
+/// Returns errors, overfull boxes and undefined references or citations, with the Markdown
+/// source they come from, according to the markers of `tex`.
+pub fn parse_log(log: &str, tex: &str) -> Vec<Message> {
+    let log = unwrap_lines(log);
+    let lines: Vec<&str> = log.lines().collect();
+    let mut messages = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(text) = line.strip_prefix("! ") {
+            // The line of the error is given a few lines later, with its context
+            let context = lines[i + 1..]
+                .iter()
+                .take(10)
+                .find_map(|l| l.strip_prefix("l.").and_then(split_number));
+            let (text, tex_line) = match context {
+                Some((n, context)) if !context.trim().is_empty() => {
+                    (format!("{} ({})", text, context.trim()), Some(n))
+                }
+                Some((n, _)) => (text.to_owned(), Some(n)),
+                None => (text.to_owned(), None),
+            };
+            messages.push(Message {
+                level: Level::Error,
+                text,
+                source: source_at(tex, tex_line),
+            });
+        } else if line.starts_with("Overfull \\hbox") || line.starts_with("Overfull \\vbox") {
+            let tex_line = line
+                .split(" at line")
+                .nth(1)
+                .map(|rest| rest.trim_start_matches('s').trim_start())
+                .and_then(split_number)
+                .map(|(n, _)| n);
+            messages.push(Message {
+                level: Level::Warning,
+                text: line
+                    .split(" in paragraph")
+                    .next()
+                    .unwrap_or(line)
+                    .to_owned(),
+                source: source_at(tex, tex_line),
+            });
+        } else if let Some(text) = line
+            .strip_prefix("LaTeX Warning: ")
+            .or_else(|| line.strip_prefix("Package natbib Warning: "))
+        {
+            if !(text.starts_with("Reference") || text.starts_with("Citation"))
+                || !text.contains("undefined")
+            {
+                continue;
+            }
+            let tex_line = text
+                .split("on input line ")
+                .nth(1)
+                .and_then(split_number)
+                .map(|(n, _)| n);
+            messages.push(Message {
+                level: Level::Warning,
+                text: text
+                    .split(" on input line")
+                    .next()
+                    .unwrap_or(text)
+                    .to_owned(),
+                source: source_at(tex, tex_line),
+            });
+        }
+    }
+    messages
+}
+
+/// Returns the Markdown source of a line of the generated LaTeX file
+fn source_at(tex: &str, line: Option<u32>) -> Source {
+    let line = match line {
+        Some(line) => line as usize,
+        None => return Source::empty(),
+    };
+    let marker = tex
+        .lines()
+        .take(line)
+        .filter_map(|l| l.strip_prefix(MARKER))
+        .last();
+    match marker {
+        None => Source::empty(),
+        Some(marker) => match marker.rsplit_once(':').map(|(file, n)| (file, n.parse())) {
+            Some((file, Ok(n))) => {
+                let mut source = Source::new(file);
+                source.set_line(n);
+                source
+            }
+            _ => Source::new(marker),
+        },
+    }
+}
+
+/// Splits a number at the beginning of a string from the rest of it
+fn split_number(s: &str) -> Option<(u32, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s[..end].parse().ok().map(|n| (n, &s[end..]))
+}
+
+/// Joins the lines of the log that TeX wrapped
+fn unwrap_lines(log: &str) -> String {
+    let mut res = String::with_capacity(log.len());
+    for line in log.lines() {
+        res.push_str(line);
+        if line.chars().count() != LOG_LINE_LENGTH {
+            res.push('\n');
+        }
+    }
+    res
+}
+
+#[test]
+fn latex_log() {
+    let tex = "\\documentclass{book}
+% crowbook-source: intro.md:1
+\\chapter{Intro}
+% crowbook-source: intro.md:3
+Some \\foo text.
+
+Too long to fit in the line.
+";
+    let log = "This is XeTeX, Version 3.141592653
+! Undefined control sequence.
+l.5 Some \\foo
+               text.
+Overfull \\hbox (12.5pt too wide) in paragraph at lines 7--7
+LaTeX Warning: Reference `chapter-3' on page 1 undefined on input line 3.
+LaTeX Warning: There were undefined references.
+";
+    let messages = parse_log(log, tex);
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0].level, Level::Error);
+    assert_eq!(messages[0].text, "Undefined control sequence. (Some \\foo)");
+    assert_eq!(messages[0].source.file.as_deref(), Some("intro.md"));
+    assert_eq!(messages[0].source.line, Some(3));
+    assert_eq!(messages[1].text, "Overfull \\hbox (12.5pt too wide)");
+    assert_eq!(messages[1].source.line, Some(3));
+    assert_eq!(messages[2].level, Level::Warning);
+    assert_eq!(messages[2].source.line, Some(1));
+
+    assert!(!needs_rerun(log));
+    assert_eq!(page_count(log), None);
+    assert_eq!(
+        page_count("Output written on result.pdf (123 pages, 456789 bytes).\n"),
+        Some(123)
+    );
+    // TeX wraps lines after 79 characters
+    let wrapped = format!(
+        "{:<76}Rer\nun to get outlines right\n",
+        "Package rerunfilecheck Warning: File `result.out' has changed."
+    );
+    assert!(needs_rerun(&wrapped));
+}
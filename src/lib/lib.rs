@@ -117,13 +117,17 @@ pub use book::Book;
 pub use book_renderer::BookRenderer;
 pub use bookoption::BookOption;
 pub use bookoptions::BookOptions;
-pub use chapter::Chapter;
+pub use chapter::{Chapter, Matter};
+pub use epub_import::EpubImport;
 pub use error::{Error, Result, Source};
 pub use number::Number;
+pub use pandoc::PandocConversion;
 pub use parser::Parser;
 pub use renderer::Renderer;
 pub use resource_handler::ResourceHandler;
 pub use stats::Stats;
+pub use token::Attributes;
+pub use token::Condition;
 pub use token::Data;
 pub use token::Token;
 
@@ -137,22 +141,32 @@ mod book_renderer;
 mod bookoptions;
 mod chapter;
 mod cleaner;
+mod cover;
 mod epub;
+mod epub_import;
 mod error;
+mod font;
 mod html_dir;
 mod html_if;
 mod html_single;
 mod lang;
 mod latex;
+mod latex_log;
+mod markdown;
+mod media_overlay;
 mod number;
 #[cfg(feature = "odt")]
 mod odt;
+mod pandoc;
 mod parser;
+mod print;
 mod renderer;
 mod resource_handler;
 mod stats;
 mod syntax;
 mod token;
+mod txt;
+mod typst;
 
 #[cfg(feature = "binary")]
 mod style;
@@ -172,6 +186,7 @@ mod bookoption;
 mod misc;
 mod templates;
 mod text_view;
+mod xml;
 mod zipper;
 
 #[cfg(feature = "proofread")]
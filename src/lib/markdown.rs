@@ -0,0 +1,544 @@
+// Copyright (C) 2016-2020 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::book::Book;
+use crate::book_renderer::BookRenderer;
+use crate::error::{Error, Result, Source};
+use crate::number::Counter;
+use crate::renderer::Renderer;
+use crate::token::Token;
+
+use std::fmt::Write;
+use std::io;
+
+/// Markdown renderer
+///
+/// Renders the AST back to (normalized) CommonMark, with the extensions
+/// supported by crowbook's parser (footnotes, tables, task items,
+/// strikethrough, description lists).
+pub struct MarkdownRenderer<'a> {
+    book: &'a Book,
+    source: Source,
+    footnote_prefix: String,
+}
+
+impl<'a> MarkdownRenderer<'a> {
+    /// Creates a new Markdown renderer
+    pub fn new(book: &'a Book) -> MarkdownRenderer<'a> {
+        MarkdownRenderer {
+            book,
+            source: Source::empty(),
+            footnote_prefix: String::new(),
+        }
+    }
+
+    /// Renders the whole book as a single Markdown file
+    pub fn render_book(&mut self) -> Result<String> {
+        let mut content = String::new();
+
+        // YAML front matter with the main metadata
+        content.push_str("---\n");
+        for key in &["title", "author", "subtitle", "lang", "date", "license"] {
+            if let Ok(value) = self.book.options.get_str(key) {
+                writeln!(content, "{key}: {}", yaml_quote(value))?;
+            }
+        }
+        content.push_str("---\n\n");
+
+        let several = self.book.chapters.len() > 1;
+        let numbering = self.book.options.get_i32("rendering.num_depth").unwrap() >= 1;
+        let mut counter = Counter::new(
+            self.book
+                .options
+                .get_bool("rendering.part.reset_counter")
+                .unwrap(),
+        );
+        for (i, chapter) in self.book.chapters.iter().enumerate() {
+            self.source = Source::new(chapter.filename.as_str());
+            // Footnotes names must be unique once all chapters are merged
+            self.footnote_prefix = if several {
+                format!("{}-", i + 1)
+            } else {
+                String::new()
+            };
+
+            // Only level-1 headers at the start of a chapter are numbered
+            let mut offset = 0;
+            if let Some(Token::Header(1, ref title)) = chapter.content.first() {
+                offset = 1;
+                let number = counter.next(chapter.number);
+                if !chapter.number.is_hidden() {
+                    let title = self.render_vec(title)?.replace('\n', " ");
+                    let title = match number {
+                        Some((header_type, n)) if numbering => {
+                            self.book
+                                .get_header(header_type, n, title, |s| Ok(s.to_owned()))?
+                                .text
+                        }
+                        _ => title,
+                    };
+                    writeln!(content, "# {}\n", escape_header_end(title.trim()))?;
+                }
+            }
+            content.push_str(&self.render_markdown(&chapter.content[offset..])?);
+        }
+        self.source = Source::empty();
+
+        Ok(format!("{}\n", content.trim_end()))
+    }
+
+    /// Renders a list of tokens (e.g. as returned by `Parser`) to Markdown
+    pub fn render_markdown(&mut self, tokens: &[Token]) -> Result<String> {
+        self.render_blocks(tokens, false)
+    }
+
+    /// Renders a list of block-level tokens, separated by blank lines.
+    ///
+    /// Inline tokens that are not inside a block (e.g. HTML blocks) are
+    /// grouped in a paragraph. If `tight` is set, lists following a paragraph
+    /// are not separated by a blank line (e.g. inside a list item).
+    fn render_blocks(&mut self, tokens: &[Token], tight: bool) -> Result<String> {
+        let mut blocks: Vec<(String, bool)> = vec![];
+        let mut inline = vec![];
+        let mut previous: Option<&Token> = None;
+        for token in tokens {
+            if is_block(token) {
+                if !inline.is_empty() {
+                    let text = self.render_vec(&inline)?;
+                    blocks.push((escape_line_starts(text.trim()), false));
+                    inline.clear();
+                }
+                let mut text = match (previous, token) {
+                    // Two consecutive lists would be merged, so use another marker
+                    (Some(Token::List(_)), Token::List(v)) => self.render_list(v, None, true)?,
+                    (Some(Token::OrderedList(..)), Token::OrderedList(n, v)) => {
+                        self.render_list(v, Some(*n), true)?
+                    }
+                    _ => self.render_token(token)?,
+                };
+                text.truncate(text.trim_end().len());
+                let follows = match *token {
+                    Token::List(_) | Token::OrderedList(1, _) => {
+                        matches!(previous, Some(Token::Paragraph(_)))
+                    }
+                    _ => false,
+                };
+                blocks.push((text, follows));
+                previous = Some(token);
+            } else {
+                inline.push(token.clone());
+                previous = None;
+            }
+        }
+        if !inline.is_empty() {
+            let text = self.render_vec(&inline)?;
+            blocks.push((escape_line_starts(text.trim()), false));
+        }
+
+        let mut content = String::new();
+        for (i, (text, follows)) in blocks.into_iter().enumerate() {
+            if text.is_empty() {
+                continue;
+            }
+            if i > 0 && !content.is_empty() {
+                content.push_str(if tight && follows { "\n" } else { "\n\n" });
+            }
+            content.push_str(&text);
+        }
+        if !content.is_empty() {
+            content.push_str("\n\n");
+        }
+        Ok(content)
+    }
+
+    /// Renders a (possibly ordered) list
+    fn render_list(&mut self, items: &[Token], start: Option<usize>, alt: bool) -> Result<String> {
+        let mut rendered = vec![];
+        for (i, item) in items.iter().enumerate() {
+            let marker = match start {
+                None => String::from(if alt { "*" } else { "-" }),
+                Some(n) => format!("{}{}", n + i, if alt { ')' } else { '.' }),
+            };
+            let inner = match *item {
+                Token::Item(ref v) => v.as_slice(),
+                ref token => std::slice::from_ref(token),
+            };
+            let content = self.render_blocks(inner, true)?;
+            rendered.push(indent_with(content.trim_end(), &format!("{marker} ")));
+        }
+        let loose = rendered.iter().any(|s| s.contains("\n\n"));
+        let mut content = rendered.join(if loose { "\n\n" } else { "\n" });
+        content.push_str("\n\n");
+        Ok(content)
+    }
+
+    /// Renders a table row (or head)
+    fn render_row(&mut self, cells: &[Token]) -> Result<String> {
+        let mut row = String::from("|");
+        for cell in cells {
+            let text = self.render_token(cell)?;
+            write!(row, " {} |", text.trim().replace('\n', " "))?;
+        }
+        Ok(row)
+    }
+}
+
+impl<'a> Renderer for MarkdownRenderer<'a> {
+    fn render_token(&mut self, token: &Token) -> Result<String> {
+        match *token {
+            Token::Str(ref text) => Ok(escape_markdown(&self.book.clean(text.as_str()))),
+            Token::Paragraph(ref vec) => Ok(format!(
+                "{}{}\n\n",
+                escape_line_starts(self.render_vec(vec)?.trim()),
+                render_attributes(token, " ")
+            )),
+            Token::Header(n, ref vec) => Ok(format!(
+                "{} {}{}\n\n",
+                "#".repeat(n.clamp(1, 6) as usize),
+                escape_header_end(self.render_vec(vec)?.replace('\n', " ").trim()),
+                render_attributes(token, " ")
+            )),
+            Token::Emphasis(ref vec) => Ok(format!("*{}*", self.render_vec(vec)?)),
+            Token::Strong(ref vec) => Ok(format!("**{}**", self.render_vec(vec)?)),
+            Token::Strikethrough(ref vec) => Ok(format!("~~{}~~", self.render_vec(vec)?)),
+            Token::Superscript(ref vec) => Ok(format!("^{}^", self.render_vec(vec)?)),
+            Token::Subscript(ref vec) => Ok(format!("~{}~", self.render_vec(vec)?)),
+            Token::Code(ref s) => {
+                let fence = "`".repeat(longest_run(s, '`') + 1);
+                if s.starts_with('`')
+                    || s.ends_with('`')
+                    || (s.starts_with(' ') && s.ends_with(' '))
+                {
+                    Ok(format!("{fence} {s} {fence}"))
+                } else {
+                    Ok(format!("{fence}{s}{fence}"))
+                }
+            }
+            Token::CodeBlock(ref language, ref code) => {
+                let fence = "`".repeat(std::cmp::max(3, longest_run(code, '`') + 1));
+                let newline = if code.ends_with('\n') || code.is_empty() {
+                    ""
+                } else {
+                    "\n"
+                };
+                Ok(format!("{fence}{language}\n{code}{newline}{fence}\n\n"))
+            }
+            Token::BlockQuote(ref vec) => {
+                let content = self.render_blocks(vec, false)?;
+                Ok(format!("{}\n", quote(&content)))
+            }
+            Token::Callout(ref kind, ref vec) => {
+                let content = self.render_blocks(vec, false)?;
+                match token.attributes() {
+                    // Only fenced callouts can have attributes
+                    Some(attributes) => {
+                        let mut attributes = attributes.clone();
+                        attributes.classes.insert(0, kind.clone());
+                        Ok(format!("::: {attributes}\n{}\n:::\n", content.trim_end()))
+                    }
+                    None => Ok(format!(
+                        "> [!{}]\n{}\n",
+                        kind.to_uppercase(),
+                        quote(&content)
+                    )),
+                }
+            }
+            Token::Rule => Ok(String::from("___\n\n")),
+            Token::SceneBreak => Ok(String::from("* * *\n\n")),
+            Token::PageBreak(ref label) => Ok(format!("{{{{#page {label}}}}}")),
+            Token::SoftBreak => Ok(String::from("\n")),
+            Token::HardBreak => Ok(String::from("\\\n")),
+            Token::List(ref vec) => self.render_list(vec, None, false),
+            Token::OrderedList(n, ref vec) => self.render_list(vec, Some(n), false),
+            Token::Item(_) => self.render_list(std::slice::from_ref(token), None, false),
+            Token::TaskItem(checked, ref vec) => Ok(format!(
+                "{}{}",
+                if checked { "[x] " } else { "[ ] " },
+                self.render_vec(vec)?
+            )),
+            Token::DescriptionList(ref vec) => self.render_blocks(vec, false),
+            Token::DescriptionItem(ref vec) => self.render_blocks(vec, false),
+            Token::DescriptionTerm(ref vec) => Ok(format!(
+                "{}\n\n",
+                escape_line_starts(self.render_vec(vec)?.trim())
+            )),
+            Token::DescriptionDetails(ref vec) => {
+                let content = self.render_blocks(vec, false)?;
+                Ok(format!("{}\n\n", indent_with(content.trim_end(), ": ")))
+            }
+            Token::Table(_, ref vec) => {
+                let mut res = String::new();
+                for row in vec {
+                    match *row {
+                        Token::TableHead(ref cells) => {
+                            writeln!(res, "{}", self.render_row(cells)?)?;
+                            res.push('|');
+                            for _ in cells {
+                                res.push_str(" --- |");
+                            }
+                            res.push('\n');
+                        }
+                        Token::TableRow(ref cells) => {
+                            writeln!(res, "{}", self.render_row(cells)?)?;
+                        }
+                        _ => {
+                            return Err(Error::render(
+                                &self.source,
+                                lformat!("Markdown: found unexpected element inside a table"),
+                            ))
+                        }
+                    }
+                }
+                res.push('\n');
+                Ok(res)
+            }
+            Token::TableHead(ref vec) | Token::TableRow(ref vec) => {
+                Ok(format!("{}\n", self.render_row(vec)?))
+            }
+            Token::TableCell(ref vec) => self.render_vec(vec),
+            Token::FootnoteReference(ref name) => Ok(format!("[^{}{name}]", self.footnote_prefix)),
+            Token::FootnoteDefinition(ref name, ref vec) => {
+                let content = self.render_blocks(vec, false)?;
+                let content = indent_with(content.trim_end(), "    ");
+                Ok(format!(
+                    "[^{}{name}]: {}\n\n",
+                    self.footnote_prefix,
+                    content.trim_start()
+                ))
+            }
+            Token::Link(ref url, ref title, ref vec) => {
+                if title.is_empty() {
+                    if let [Token::Str(ref text)] = vec.as_slice() {
+                        // Autolinks
+                        if (text == url && url.contains(':'))
+                            || url.strip_prefix("mailto:") == Some(text.as_str())
+                        {
+                            return Ok(format!("<{text}>"));
+                        }
+                    }
+                }
+                Ok(format!(
+                    "[{}]({}){}",
+                    self.render_vec(vec)?,
+                    link_destination(url, title),
+                    render_attributes(token, "")
+                ))
+            }
+            Token::Image(ref url, ref title, ref vec)
+            | Token::StandaloneImage(ref url, ref title, ref vec) => Ok(format!(
+                "![{}]({}){}",
+                self.render_vec(vec)?,
+                link_destination(url, title),
+                render_attributes(token, "")
+            )),
+            Token::Annotation(_, ref vec) => self.render_vec(vec),
+            Token::Attributes(_) => Ok(String::new()),
+            Token::Span(ref attributes, ref vec) => {
+                Ok(format!("[{}]{attributes}", self.render_vec(vec)?))
+            }
+            Token::Div(ref attributes, ref vec) => {
+                let content = self.render_blocks(vec, false)?;
+                Ok(format!("::: {attributes}\n{}\n:::\n", content.trim_end()))
+            }
+            Token::Conditional(ref conditions, ref vec) => {
+                if self.book.is_rendered(conditions, "md") {
+                    self.render_blocks(vec, false)
+                } else {
+                    Ok(String::new())
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if a token must be rendered on its own block
+fn is_block(token: &Token) -> bool {
+    matches!(
+        *token,
+        Token::Paragraph(..)
+            | Token::Header(..)
+            | Token::BlockQuote(..)
+            | Token::CodeBlock(..)
+            | Token::List(..)
+            | Token::OrderedList(..)
+            | Token::Item(..)
+            | Token::DescriptionList(..)
+            | Token::DescriptionItem(..)
+            | Token::DescriptionTerm(..)
+            | Token::DescriptionDetails(..)
+            | Token::Table(..)
+            | Token::FootnoteDefinition(..)
+            | Token::Rule
+            | Token::SceneBreak
+            | Token::StandaloneImage(..)
+            | Token::Conditional(..)
+            | Token::Div(..)
+            | Token::Callout(..)
+    )
+}
+
+/// Prefixes the lines of `content` with `>`
+fn quote(content: &str) -> String {
+    let mut res = String::new();
+    for line in content.trim_end().lines() {
+        if line.is_empty() {
+            res.push_str(">\n");
+        } else {
+            res.push_str("> ");
+            res.push_str(line);
+            res.push('\n');
+        }
+    }
+    res
+}
+
+/// Renders the generic attributes of a token, if it has some, after a separator
+fn render_attributes(token: &Token, separator: &str) -> String {
+    match token.attributes() {
+        Some(attributes) => format!("{separator}{attributes}"),
+        None => String::new(),
+    }
+}
+
+/// Returns the length of the longest run of character `c` in `s`
+fn longest_run(s: &str, c: char) -> usize {
+    let mut max = 0;
+    let mut current = 0;
+    for x in s.chars() {
+        if x == c {
+            current += 1;
+            max = std::cmp::max(max, current);
+        } else {
+            current = 0;
+        }
+    }
+    max
+}
+
+/// Prefixes the first line of `content` with `prefix`, and indent the other ones
+/// with as many spaces.
+fn indent_with(content: &str, prefix: &str) -> String {
+    let indent = " ".repeat(prefix.chars().count());
+    let mut res = String::new();
+    for (i, line) in content.lines().enumerate() {
+        if i == 0 {
+            res.push_str(prefix);
+        } else {
+            res.push('\n');
+            if !line.is_empty() {
+                res.push_str(&indent);
+            }
+        }
+        res.push_str(line);
+    }
+    if res.is_empty() {
+        res.push_str(prefix.trim_end());
+    }
+    res
+}
+
+/// Formats the destination (and optional title) of a link or an image
+fn link_destination(url: &str, title: &str) -> String {
+    let url = if url.is_empty() || url.contains(|c: char| c.is_whitespace() || c == '(' || c == ')')
+    {
+        format!("<{}>", url.replace('<', "\\<").replace('>', "\\>"))
+    } else {
+        url.to_owned()
+    };
+    if title.is_empty() {
+        url
+    } else {
+        format!(
+            "{url} \"{}\"",
+            title.replace('\\', "\\\\").replace('"', "\\\"")
+        )
+    }
+}
+
+/// Escape characters that have a meaning inside Markdown inline text
+fn escape_markdown(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '~' | '^' | '&' | '|' => {
+                res.push('\\');
+                res.push(c);
+            }
+            '\n' => res.push(' '),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Escape characters at the beginning of lines that would otherwise start a new block
+/// (headers, lists, description details, ...)
+fn escape_line_starts(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            res.push('\n');
+        }
+        let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+        if line.starts_with(['#', '+', '-', '=', ':'])
+            || (digits > 0 && line[digits..].starts_with(['.', ')']))
+        {
+            let (begin, end) = line.split_at(digits);
+            res.push_str(begin);
+            res.push('\\');
+            res.push_str(end);
+        } else {
+            res.push_str(line);
+        }
+    }
+    res
+}
+
+/// Escape a trailing '#' in a header, which would otherwise be seen as a closing sequence
+fn escape_header_end(text: &str) -> String {
+    if let Some(s) = text.strip_suffix('#') {
+        if !s.ends_with('\\') {
+            return format!("{s}\\#");
+        }
+    }
+    text.to_owned()
+}
+
+/// Quotes a string so it can be used as a YAML value
+fn yaml_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub struct Markdown;
+
+impl BookRenderer for Markdown {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.md"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
+        let mut markdown = MarkdownRenderer::new(book);
+        let result = markdown.render_book()?;
+        to.write_all(result.as_bytes()).map_err(|e| {
+            Error::render(
+                &book.source,
+                lformat!("problem when writing Markdown: {error}", error = e),
+            )
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Media overlays (EPUB 3 read aloud), synchronizing chapters with their narration
+
+use crate::error::{Error, Result, Source};
+
+use crowbook_text_processing::escape;
+
+use std::path::Path;
+
+/// A clip of the narration, during which an element of the chapter is read
+#[derive(Debug, PartialEq)]
+pub struct Clip {
+    /// Id of the element, or `None` for the next paragraph
+    pub id: Option<String>,
+    /// Beginning of the clip, in seconds
+    pub begin: f64,
+    /// End of the clip, in seconds
+    pub end: f64,
+}
+
+/// Media overlay of a page, rendered as a SMIL file
+pub struct Overlay {
+    /// File name of the synchronized page
+    pub page: String,
+    /// Id of the SMIL file in the manifest
+    pub id: String,
+    /// Path of the SMIL file, relative to the content directory
+    pub path: String,
+    /// Content of the SMIL file
+    pub smil: String,
+    /// Total duration of the clips, in seconds
+    pub duration: f64,
+}
+
+/// Parses a timing file
+///
+/// Each line contains the beginning and the end of a clip, either in seconds (`4.25`)
+/// or as clock values (`1:04.25`, `0:01:04.250`), optionally followed by the id of
+/// the element read during this clip. Empty lines and lines starting with `#` are
+/// ignored. Fields are separated by spaces or tabs, so label files exported by
+/// Audacity can be used as is.
+pub fn parse_timings(text: &str, file: &str) -> Result<Vec<Clip>> {
+    let mut clips = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let begin = fields.next().and_then(parse_clock);
+        let end = fields.next().and_then(parse_clock);
+        let (begin, end) = match (begin, end) {
+            (Some(begin), Some(end)) if begin <= end => (begin, end),
+            _ => {
+                let mut source = Source::new(file);
+                source.set_line(n as u32 + 1);
+                return Err(Error::parser(
+                    source,
+                    lformat!(
+                        "invalid timing '{line}', expected the beginning and the end of a clip, \
+                         followed by an optional id",
+                        line = line
+                    ),
+                ));
+            }
+        };
+        let id: Vec<&str> = fields.collect();
+        clips.push(Clip {
+            id: if id.is_empty() {
+                None
+            } else {
+                Some(id.join(" "))
+            },
+            begin,
+            end,
+        });
+    }
+    Ok(clips)
+}
+
+/// Synchronizes the pages of a chapter with its narration
+///
+/// `pages` contains the file name and the rendered XHTML of each page, and `audio` the
+/// path of the narration, relative to the content directory. Clips without an id are
+/// matched with the paragraphs of the chapter, in order.
+///
+/// Returns an error if a clip refers to an element that is not in the rendered pages.
+pub fn render_overlays(
+    pages: &[(String, String)],
+    audio: &str,
+    clips: &[Clip],
+    source: &Source,
+) -> Result<Vec<Overlay>> {
+    let ids: Vec<Vec<&str>> = pages.iter().map(|(_, xhtml)| xhtml_ids(xhtml)).collect();
+    let mut paragraphs = ids.iter().enumerate().flat_map(|(j, ids)| {
+        ids.iter()
+            .filter(|id| id.starts_with("para-"))
+            .map(move |id| (j, *id))
+    });
+
+    let mut timed: Vec<Vec<(&str, &Clip)>> = pages.iter().map(|_| vec![]).collect();
+    for clip in clips {
+        let (j, id) = match clip.id {
+            None => paragraphs.next().ok_or_else(|| {
+                Error::render(
+                    source,
+                    lformat!("media overlay: there are more clips than paragraphs in the chapter"),
+                )
+            })?,
+            Some(ref id) => ids
+                .iter()
+                .position(|ids| ids.contains(&id.as_str()))
+                .map(|j| (j, id.as_str()))
+                .ok_or_else(|| {
+                    Error::render(
+                        source,
+                        lformat!(
+                            "media overlay: could not find an element with id '{id}' in the chapter",
+                            id = id
+                        ),
+                    )
+                })?,
+        };
+        timed[j].push((id, clip));
+    }
+
+    let mut overlays = vec![];
+    for ((page, _), clips) in pages.iter().zip(timed) {
+        if clips.is_empty() {
+            continue;
+        }
+        let stem = Path::new(page)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(page);
+        let mut smil = format!(
+            "<?xml version = \"1.0\" encoding = \"UTF-8\"?>
+<smil xmlns = \"http://www.w3.org/ns/SMIL\" xmlns:epub = \"http://www.idpf.org/2007/ops\" version = \"3.0\">
+  <body>
+    <seq epub:textref = \"../{page}\">\n"
+        );
+        let mut duration = 0.0;
+        for (n, (id, clip)) in clips.iter().enumerate() {
+            smil.push_str(&format!(
+                "      <par id = \"par-{n}\">
+        <text src = \"../{page}#{id}\" />
+        <audio src = \"../{audio}\" clipBegin = \"{begin}\" clipEnd = \"{end}\" />
+      </par>\n",
+                n = n + 1,
+                id = escape::html(*id),
+                audio = escape::html(audio),
+                begin = clock_value(clip.begin),
+                end = clock_value(clip.end),
+            ));
+            duration += clip.end - clip.begin;
+        }
+        smil.push_str("    </seq>\n  </body>\n</smil>\n");
+        overlays.push(Overlay {
+            page: page.clone(),
+            id: format!("smil-{stem}"),
+            path: format!("smil/{stem}.smil"),
+            smil,
+            duration,
+        });
+    }
+    Ok(overlays)
+}
+
+/// Formats a duration in seconds as a SMIL clock value
+pub fn clock_value(secs: f64) -> String {
+    let ms = (secs * 1000.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Parses a time, either in seconds or as a clock value
+fn parse_clock(s: &str) -> Option<f64> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut secs = 0.0;
+    for part in parts {
+        let value: f64 = part.parse().ok()?;
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        secs = secs * 60.0 + value;
+    }
+    Some(secs)
+}
+
+/// Returns the ids of the elements of an XHTML document, in order
+fn xhtml_ids(xhtml: &str) -> Vec<&str> {
+    let mut ids = vec![];
+    let mut rest = xhtml;
+    while let Some(pos) = rest.find("id") {
+        let after_space = rest[..pos]
+            .chars()
+            .next_back()
+            .map_or(false, char::is_whitespace);
+        rest = &rest[pos + 2..];
+        if !after_space {
+            continue;
+        }
+        let value = match rest.trim_start().strip_prefix('=') {
+            Some(value) => value.trim_start(),
+            None => continue,
+        };
+        if let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let value = &value[1..];
+            if let Some(end) = value.find(quote) {
+                ids.push(&value[..end]);
+            }
+        }
+    }
+    ids
+}
+
+#[test]
+fn timings() {
+    let clips = parse_timings(
+        "# Chapter 1
+0\t4.25
+4.25 1:02.5 title
+0:01:02.500   0:01:10   para-3\n",
+        "timings.txt",
+    )
+    .unwrap();
+    assert_eq!(clips.len(), 3);
+    assert_eq!(clips[0].id, None);
+    assert_eq!(clips[1].end, 62.5);
+    assert_eq!(clips[2].id.as_deref(), Some("para-3"));
+    assert_eq!(clock_value(clips[2].end), "0:01:10.000");
+
+    assert!(parse_timings("4.25 0", "timings.txt").is_err());
+    assert!(parse_timings("0 one", "timings.txt").is_err());
+}
+
+#[test]
+fn overlays() {
+    let pages = vec![(
+        String::from("chapter_000.xhtml"),
+        String::from(
+            "<h1 id = 'link-0'>Title</h1>\n<p id = \"para-1\">One</p>\n<p id=\"para-2\">Two</p>",
+        ),
+    )];
+    let clips = parse_timings("0 1 link-0\n1 2\n2 4\n", "timings.txt").unwrap();
+    let overlays =
+        render_overlays(&pages, "audio/chapter_000.mp3", &clips, &Source::empty()).unwrap();
+    assert_eq!(overlays.len(), 1);
+    assert_eq!(overlays[0].id, "smil-chapter_000");
+    assert_eq!(overlays[0].duration, 4.0);
+    assert!(overlays[0].smil.contains("../chapter_000.xhtml#para-2"));
+
+    let clips = parse_timings("0 1 missing", "timings.txt").unwrap();
+    assert!(render_overlays(&pages, "audio/chapter_000.mp3", &clips, &Source::empty()).is_err());
+    let clips = parse_timings("0 1\n1 2\n2 3", "timings.txt").unwrap();
+    assert!(render_overlays(&pages, "audio/chapter_000.mp3", &clips, &Source::empty()).is_err());
+}
@@ -19,7 +19,9 @@
 
 use crate::token::Token;
 
+use std::cmp::Ordering;
 use std::io::Result;
+use std::iter::Peekable;
 use std::path::{Path, PathBuf};
 
 /// Try to canonicalize a path using std::fs::canonicalize, and returns the
@@ -58,3 +60,112 @@ pub fn insert_title(tokens: &mut Vec<Token>) {
     }
     tokens.insert(0, Token::Header(1, vec![]));
 }
+
+/// Compares two strings in "natural" order, that is, comparing sequences of
+/// digits by their numeric value (so `chapter2.md` comes before `chapter10.md`)
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                // Compare numeric values, ignoring leading zeroes
+                let (x_value, y_value) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ord = x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Consumes a sequence of digits
+fn take_number<I: Iterator<Item = char>>(chars: &mut Peekable<I>) -> String {
+    let mut number = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        number.push(c);
+    }
+    number
+}
+
+/// Checks whether a file name matches a glob pattern.
+///
+/// Supports `*` (any sequence of characters), `?` (any character) and
+/// `[...]` (any character in the set, or not in it with `[!...]`).
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_match_chars(&pattern, &name)
+}
+
+fn glob_match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| glob_match_chars(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && glob_match_chars(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let end = match pattern.iter().skip(2).position(|&c| c == ']') {
+                Some(i) => i + 2,
+                None => {
+                    return name.first() == Some(&'[')
+                        && glob_match_chars(&pattern[1..], &name[1..])
+                }
+            };
+            let (negated, set) = match pattern[1] {
+                '!' | '^' => (true, &pattern[2..end]),
+                _ => (false, &pattern[1..end]),
+            };
+            match name.first() {
+                Some(&c) => {
+                    let mut found = false;
+                    let mut i = 0;
+                    while i < set.len() {
+                        if i + 2 < set.len() && set[i + 1] == '-' {
+                            found |= set[i] <= c && c <= set[i + 2];
+                            i += 3;
+                        } else {
+                            found |= set[i] == c;
+                            i += 1;
+                        }
+                    }
+                    found != negated && glob_match_chars(&pattern[end + 1..], &name[1..])
+                }
+                None => false,
+            }
+        }
+        Some(&c) => name.first() == Some(&c) && glob_match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Returns true if a path contains glob metacharacters
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Converts a (strictly positive) number to letters: 1 is `A`, 26 is `Z`, 27 is `AA`...
+pub fn to_letters(mut n: u32) -> String {
+    let mut letters = vec![];
+    while n > 0 {
+        n -= 1;
+        letters.push((b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    letters.iter().rev().collect()
+}
@@ -1,3 +1,5 @@
+use crate::book::Header;
+
 /// Numbering for a given chapter or part
 ///
 /// This Enum is only public so it can be passed to `Book` methods, but
@@ -29,6 +31,14 @@ pub enum Number {
     /// Chapter is actually a part whose number is specified
     #[doc(hidden)]
     SpecifiedPart(i32),
+
+    /// Chapter is an appendix following book numbering (displayed with letters)
+    #[doc(hidden)]
+    DefaultAppendix,
+
+    /// Chapter is an appendix whose number is specified
+    #[doc(hidden)]
+    SpecifiedAppendix(i32),
 }
 
 impl Number {
@@ -40,6 +50,14 @@ impl Number {
         )
     }
 
+    /// Returns true if self is a (numbered) appendix
+    pub fn is_appendix(&self) -> bool {
+        matches!(
+            *self,
+            Number::DefaultAppendix | Number::SpecifiedAppendix(..)
+        )
+    }
+
     /// Returns true if self is hidden
     pub fn is_hidden(&self) -> bool {
         *self == Number::Hidden
@@ -53,3 +71,57 @@ impl Number {
         )
     }
 }
+
+/// Counts chapters, appendices and parts while going through the chapters of a book,
+/// for renderers that number their titles themselves
+#[derive(Debug, Default)]
+pub struct Counter {
+    chapter: i32,
+    part: i32,
+    reset_on_part: bool,
+}
+
+impl Counter {
+    /// Creates a new counter. If `reset_on_part` is set, chapter numbers start again
+    /// at each part.
+    pub fn new(reset_on_part: bool) -> Counter {
+        Counter {
+            reset_on_part,
+            ..Counter::default()
+        }
+    }
+
+    /// Returns the kind of header and the number of the next chapter, or `None` if
+    /// it is not numbered
+    pub fn next(&mut self, number: Number) -> Option<(Header, i32)> {
+        match number {
+            Number::Default => {
+                self.chapter += 1;
+                Some((Header::Chapter, self.chapter))
+            }
+            Number::Specified(n) => {
+                self.chapter = n;
+                Some((Header::Chapter, self.chapter))
+            }
+            Number::DefaultAppendix => {
+                self.chapter += 1;
+                Some((Header::Appendix, self.chapter))
+            }
+            Number::SpecifiedAppendix(n) => {
+                self.chapter = n;
+                Some((Header::Appendix, self.chapter))
+            }
+            Number::DefaultPart | Number::SpecifiedPart(_) => {
+                self.part = match number {
+                    Number::SpecifiedPart(n) => n,
+                    _ => self.part + 1,
+                };
+                if self.reset_on_part {
+                    self.chapter = 0;
+                }
+                Some((Header::Part, self.part))
+            }
+            _ => None,
+        }
+    }
+}
@@ -1,6 +1,7 @@
-use crate::book::{compile_str, Book};
+use crate::book::{compile_str, Book, Header};
 use crate::book_renderer::BookRenderer;
 use crate::error::Result;
+use crate::lang;
 use crate::number::Number;
 use crate::parser::Parser;
 use crate::templates::odt;
@@ -18,6 +19,7 @@ pub struct OdtRenderer<'a> {
     book: &'a Book,
     current_numbering: i32,
     current_hide: bool,
+    current_appendix: bool,
     current_chapter: i32,
     automatic_styles: String,
 }
@@ -30,6 +32,7 @@ impl<'a> OdtRenderer<'a> {
             current_chapter: 1,
             current_numbering: book.options.get_i32("rendering.num_depth").unwrap(),
             current_hide: false,
+            current_appendix: false,
             automatic_styles: String::from(
                 "
 <style:style style:name=\"T1\" \
@@ -119,13 +122,14 @@ impl<'a> OdtRenderer<'a> {
             let n = chapter.number;
             let v = &chapter.content;
             self.current_hide = false;
+            self.current_appendix = n.is_appendix();
             match n {
                 Number::Unnumbered | Number::UnnumberedPart => self.current_numbering = 0,
-                Number::Default | Number::DefaultPart => {
+                Number::Default | Number::DefaultPart | Number::DefaultAppendix => {
                     self.current_numbering =
                         self.book.options.get_i32("rendering.num_depth").unwrap()
                 }
-                Number::Specified(n) | Number::SpecifiedPart(n) => {
+                Number::Specified(n) | Number::SpecifiedPart(n) | Number::SpecifiedAppendix(n) => {
                     self.current_numbering = self.book.options.get_i32("numbering").unwrap();
                     self.current_chapter = n;
                 }
@@ -189,9 +193,14 @@ impl<'a> OdtRenderer<'a> {
                 let s = if n == 1 && self.current_numbering >= 1 {
                     let chapter = self.current_chapter;
                     self.current_chapter += 1;
+                    let header = if self.current_appendix {
+                        Header::Appendix
+                    } else {
+                        Header::Chapter
+                    };
                     let res = self
                         .book
-                        .get_chapter_header(chapter, self.render_vec(vec), |s| {
+                        .get_header(header, chapter, self.render_vec(vec), |s| {
                             Ok(self.render_vec(&Parser::new().parse_inline(s)?))
                         });
                     res.unwrap().text
@@ -246,14 +255,37 @@ impl<'a> OdtRenderer<'a> {
             Token::CodeBlock(_, ref s) => {
                 format!("<text:p text:style-name=\"Text_20_Body\">{}</text:p>\n", s)
             }
+            Token::Callout(ref kind, ref vec) => format!(
+                "<text:p><text:span text:style-name=\"T2\">{}</text:span></text:p>\n{}",
+                escape::html(lang::get_str(
+                    self.book.options.get_str("lang").unwrap(),
+                    kind
+                )),
+                self.render_vec(vec)
+            ),
             Token::SoftBreak | Token::HardBreak => String::from(" "),
             Token::Rule => String::from("<text:p /><text:p>***</text:p><text:p />"),
+            Token::SceneBreak => format!(
+                "<text:p /><text:p>{}</text:p><text:p />",
+                escape::html(self.book.options.get_str("rendering.scene_break").unwrap())
+            ),
+            Token::PageBreak(_) => String::new(),
             Token::Image(_, _, _) | Token::StandaloneImage(_, _, _) => String::from(" "),
             Token::Table(_, _) | Token::TableHead(_) | Token::TableRow(_) | Token::TableCell(_) => {
                 String::from(" ")
             }
             Token::FootnoteReference(..) | Token::FootnoteDefinition(..) => String::new(),
-            Token::Annotation(_, ref vec) => self.render_vec(vec),
+            Token::Annotation(_, ref vec) | Token::Span(_, ref vec) | Token::Div(_, ref vec) => {
+                self.render_vec(vec)
+            }
+            Token::Attributes(_) => String::new(),
+            Token::Conditional(ref conditions, ref vec) => {
+                if self.book.is_rendered(conditions, "odt") {
+                    self.render_vec(vec)
+                } else {
+                    String::new()
+                }
+            }
             Token::DescriptionList(ref v)
             | Token::DescriptionItem(ref v)
             | Token::DescriptionTerm(ref v)
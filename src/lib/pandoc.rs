@@ -0,0 +1,431 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::book::Book;
+use crate::bookoptions::BookOptions;
+use crate::error::{Error, Result, Source};
+
+use std::fs;
+use std::path::Path;
+
+use yaml_rust::yaml::Hash;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+/// Output formats that can be deduced from the extension of pandoc's `output-file`
+static OUTPUT_FORMATS: &[&str] = &["epub", "html", "pdf", "tex", "odt", "md", "txt"];
+
+/// Keys that only appear in pandoc's defaults files, and not in metadata files
+static DEFAULTS_KEYS: &[&str] = &[
+    "metadata",
+    "metadata-file",
+    "metadata-files",
+    "variables",
+    "input-file",
+    "input-files",
+    "output-file",
+    "from",
+    "to",
+    "reader",
+    "writer",
+];
+
+/// Sides of the page for `tex.margin.*` and pandoc's `geometry`
+static SIDES: &[&str] = &["left", "right", "top", "bottom"];
+
+/// Result of a conversion between a pandoc configuration and a Crowbook one.
+///
+/// # Example
+///
+/// ```
+/// use crowbook::PandocConversion;
+/// let yaml = "
+/// metadata:
+///   title: Some title
+///   author: [Joan Doe, John Smith]
+/// highlight-style: tango";
+/// let conversion = PandocConversion::from_pandoc(yaml, ".", &[]).unwrap();
+/// assert!(conversion.content.contains("author: \"Joan Doe, John Smith\""));
+/// assert_eq!(conversion.unmapped, vec!["highlight-style".to_owned()]);
+/// ```
+#[derive(Debug)]
+pub struct PandocConversion {
+    /// The converted configuration file
+    pub content: String,
+    /// Options of the source configuration that have no equivalent
+    pub unmapped: Vec<String>,
+}
+
+impl PandocConversion {
+    /// Converts a pandoc metadata file (or defaults file) to a `.book` configuration file.
+    ///
+    /// # Arguments
+    /// * `yaml`: the content of pandoc's `metadata.yaml` or `defaults.yaml` file
+    /// * `root`: the directory containing this file, used to find `metadata-files`
+    /// * `files`: Markdown files to add as chapters, after pandoc's `input-files`
+    pub fn from_pandoc<P: AsRef<Path>>(
+        yaml: &str,
+        root: P,
+        files: &[String],
+    ) -> Result<PandocConversion> {
+        let hash = load_hash(yaml, Source::empty())?;
+        let is_metadata = !hash
+            .keys()
+            .any(|key| matches!(key.as_str(), Some(key) if DEFAULTS_KEYS.contains(&key)));
+
+        let mut entries = vec![];
+        let mut inputs = vec![];
+        collect_entries(hash, root.as_ref(), is_metadata, &mut entries, &mut inputs)?;
+        inputs.extend(files.iter().cloned());
+
+        let mut options = BookOptions::new();
+        let mut book = Hash::new();
+        let mut unmapped = vec![];
+        let mut numbered = false;
+        for (key, value, is_metadata) in entries {
+            if key == "number-sections" {
+                match value {
+                    Yaml::Boolean(b) => numbered = b,
+                    _ => unmapped.push(key),
+                }
+                continue;
+            }
+            let pairs = import_option(&key, &value, is_metadata).filter(|pairs| {
+                pairs.iter().all(|(key, value)| {
+                    options
+                        .set_yaml(Yaml::String(key.clone()), value.clone())
+                        .is_ok()
+                })
+            });
+            if let Some(pairs) = pairs {
+                for (key, value) in pairs {
+                    book.insert(Yaml::String(key), value);
+                }
+            } else {
+                unmapped.push(key);
+            }
+        }
+
+        let mut content = emit_yaml(book)?;
+        content.push_str(&lformat!("\n## List of chapters\n"));
+        for mut file in inputs {
+            if file.contains(char::is_whitespace) {
+                // Quoted names can't contain quotes
+                if file.contains('"') {
+                    return Err(Error::config_parser(
+                        Source::new(file.as_str()),
+                        lformat!("chapter filenames containing whitespace must not contain quotes"),
+                    ));
+                }
+                file = format!("\"{file}\"");
+            }
+            content.push_str(&format!("{} {file}\n", if numbered { '+' } else { '-' }));
+        }
+
+        Ok(PandocConversion { content, unmapped })
+    }
+
+    /// Converts the configuration of a book to a pandoc defaults file.
+    ///
+    /// Only the options that have been set to a value other than their default
+    /// are exported.
+    pub fn to_pandoc(book: &Book) -> Result<PandocConversion> {
+        let options = &book.options;
+        let mut defaults = Hash::new();
+        let mut metadata = Hash::new();
+        let mut variables = Hash::new();
+        let mut geometry = vec![];
+        let mut unmapped = vec![];
+
+        let keys = options.get_set_keys();
+        let outputs: Vec<&str> = keys
+            .iter()
+            .filter_map(|key| key.strip_prefix("output."))
+            .filter(|format| OUTPUT_FORMATS.contains(format))
+            .collect();
+
+        let mut number_sections = true;
+        for key in &keys {
+            let key = *key;
+            let string = |key: &str| options.get_str(key).map(|s| Yaml::String(s.to_owned()));
+            let path = |key: &str| {
+                options
+                    .get_relative_path(key)
+                    .map(|s| Yaml::String(s.to_owned()))
+            };
+            let (hash, pandoc_key, value) = match key {
+                "title" | "subtitle" | "author" | "lang" | "date" | "subject" | "description" => {
+                    (&mut metadata, key, string(key)?)
+                }
+                "license" => (&mut metadata, "rights", string(key)?),
+                "cover" => (&mut defaults, "epub-cover-image", path(key)?),
+                "rendering.inline_toc" => {
+                    (&mut defaults, "toc", Yaml::Boolean(options.get_bool(key)?))
+                }
+                "rendering.num_depth" if options.get_i32(key)? == 0 => {
+                    number_sections = false;
+                    continue;
+                }
+                "tex.command" => (&mut defaults, "pdf-engine", string(key)?),
+                "html.css" => (&mut defaults, "css", path(key)?),
+                "tex.class" => (&mut variables, "documentclass", string(key)?),
+                "tex.paper.size" => {
+                    let size = options.get_str(key)?;
+                    let size = size.strip_suffix("paper").unwrap_or(size);
+                    (&mut variables, "papersize", Yaml::String(size.to_owned()))
+                }
+                "tex.font.size" => {
+                    let size = format!("{}pt", options.get_i32(key)?);
+                    (&mut variables, "fontsize", Yaml::String(size))
+                }
+                key if key
+                    .strip_prefix("tex.margin.")
+                    .map_or(false, |side| SIDES.contains(&side)) =>
+                {
+                    geometry.push(Yaml::String(format!(
+                        "{}={}",
+                        &key["tex.margin.".len()..],
+                        options.get_str(key)?
+                    )));
+                    continue;
+                }
+                key if key.starts_with("metadata.") => {
+                    let value = string(key)?;
+                    metadata.insert(Yaml::String(key["metadata.".len()..].to_owned()), value);
+                    continue;
+                }
+                key if outputs.len() == 1
+                    && key.strip_prefix("output.") == Some(outputs[0])
+                    && options.get_relative_path(key)? != "auto" =>
+                {
+                    (&mut defaults, "output-file", path(key)?)
+                }
+                _ => {
+                    unmapped.push(key.to_owned());
+                    continue;
+                }
+            };
+            hash.insert(Yaml::String(pandoc_key.to_owned()), value);
+        }
+
+        let mut inputs = vec![];
+        let mut numbered = false;
+        for chapter in &book.chapters {
+            if chapter.number.is_part() {
+                unmapped.push(lformat!("parts"));
+            } else if chapter.number.is_numbered() {
+                numbered = true;
+            }
+            if !chapter.filename.is_empty() {
+                inputs.push(Yaml::String(chapter.filename.clone()));
+            }
+        }
+        unmapped.dedup();
+
+        let mut pandoc = Hash::new();
+        pandoc.insert(Yaml::String("input-files".to_owned()), Yaml::Array(inputs));
+        for (key, value) in defaults {
+            pandoc.insert(key, value);
+        }
+        pandoc.insert(
+            Yaml::String("number-sections".to_owned()),
+            Yaml::Boolean(numbered && number_sections),
+        );
+        if !geometry.is_empty() {
+            variables.insert(Yaml::String("geometry".to_owned()), Yaml::Array(geometry));
+        }
+        for (key, hash) in [("metadata", metadata), ("variables", variables)] {
+            if !hash.is_empty() {
+                pandoc.insert(Yaml::String(key.to_owned()), Yaml::Hash(hash));
+            }
+        }
+
+        Ok(PandocConversion {
+            content: emit_yaml(pandoc)?,
+            unmapped,
+        })
+    }
+}
+
+/// Loads a YAML document that must be a hash
+fn load_hash(yaml: &str, source: Source) -> Result<Hash> {
+    let docs = YamlLoader::load_from_str(yaml).map_err(|err| {
+        Error::config_parser(
+            &source,
+            lformat!("YAML block was not valid YAML: {error}", error = err),
+        )
+    })?;
+    match docs.into_iter().next() {
+        Some(Yaml::Hash(hash)) => Ok(hash),
+        None => Ok(Hash::new()),
+        Some(_) => Err(Error::config_parser(
+            &source,
+            lformat!("pandoc configuration is not a valid hashmap"),
+        )),
+    }
+}
+
+/// Flattens pandoc's configuration, following `metadata-files` and collecting `input-files`
+fn collect_entries(
+    hash: Hash,
+    root: &Path,
+    is_metadata: bool,
+    entries: &mut Vec<(String, Yaml, bool)>,
+    inputs: &mut Vec<String>,
+) -> Result<()> {
+    for (key, value) in hash {
+        let key = if let Some(key) = as_string(&key) {
+            key
+        } else {
+            continue;
+        };
+        match (key.as_str(), value) {
+            ("metadata", Yaml::Hash(inner)) if !is_metadata => {
+                collect_entries(inner, root, true, entries, inputs)?
+            }
+            ("variables", Yaml::Hash(inner)) if !is_metadata => {
+                collect_entries(inner, root, false, entries, inputs)?
+            }
+            ("input-file" | "input-files", value) if !is_metadata => {
+                inputs.extend(as_list(&value).unwrap_or_default())
+            }
+            ("metadata-file" | "metadata-files", value) if !is_metadata => {
+                for file in as_list(&value).unwrap_or_default() {
+                    let path = root.join(&file);
+                    let content = fs::read_to_string(&path).map_err(|_| {
+                        Error::file_not_found(
+                            Source::empty(),
+                            lformat!("metadata file"),
+                            format!("{}", path.display()),
+                        )
+                    })?;
+                    let inner = load_hash(&content, Source::new(file.as_str()))?;
+                    collect_entries(inner, root, true, entries, inputs)?;
+                }
+            }
+            (_, value) => entries.push((key, value, is_metadata)),
+        }
+    }
+    Ok(())
+}
+
+/// Maps a pandoc option to a list of Crowbook options, or returns `None` if
+/// it has no equivalent.
+fn import_option(key: &str, value: &Yaml, is_metadata: bool) -> Option<Vec<(String, Yaml)>> {
+    let string = |key: &str| Some(vec![(key.to_owned(), Yaml::String(as_string(value)?))]);
+    match key {
+        "title" | "subtitle" | "lang" | "date" | "subject" => string(key),
+        "description" | "abstract" => string("description"),
+        "rights" => string("license"),
+        "author" => {
+            let authors = as_list(value)?.join(", ");
+            Some(vec![("author".to_owned(), Yaml::String(authors))])
+        }
+        "cover-image" | "epub-cover-image" => string("cover"),
+        "documentclass" => string("tex.class"),
+        "pdf-engine" => string("tex.command"),
+        "papersize" => {
+            let size = as_string(value)?.to_lowercase();
+            let size = if size.ends_with("paper") {
+                size
+            } else {
+                format!("{size}paper")
+            };
+            Some(vec![("tex.paper.size".to_owned(), Yaml::String(size))])
+        }
+        "fontsize" => {
+            let size = as_string(value)?;
+            let size = size.trim_end_matches("pt").parse::<i64>().ok()?;
+            Some(vec![("tex.font.size".to_owned(), Yaml::Integer(size))])
+        }
+        "geometry" => {
+            let mut margins = vec![];
+            for entry in as_list(value)?.iter().flat_map(|s| s.split(',')) {
+                let (side, margin) = entry.split_once('=')?;
+                let margin = Yaml::String(margin.trim().to_owned());
+                match side.trim() {
+                    "margin" => {
+                        for side in SIDES {
+                            margins.push((format!("tex.margin.{side}"), margin.clone()));
+                        }
+                    }
+                    side if SIDES.contains(&side) => {
+                        margins.push((format!("tex.margin.{side}"), margin))
+                    }
+                    _ => return None,
+                }
+            }
+            Some(margins)
+        }
+        "toc" | "table-of-contents" => {
+            Some(vec![("rendering.inline_toc".to_owned(), value.clone())])
+        }
+        "css" => {
+            let css = as_list(value)?;
+            if css.len() != 1 {
+                return None;
+            }
+            Some(vec![("html.css".to_owned(), Yaml::String(css[0].clone()))])
+        }
+        "output-file" => {
+            let file = as_string(value)?;
+            let format = Path::new(&file).extension()?.to_str()?.to_owned();
+            if !OUTPUT_FORMATS.contains(&format.as_str()) {
+                return None;
+            }
+            Some(vec![(format!("output.{format}"), Yaml::String(file))])
+        }
+        _ if is_metadata => string(&format!("metadata.{key}")),
+        _ => None,
+    }
+}
+
+/// Returns a scalar YAML value as a string
+fn as_string(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(s) | Yaml::Real(s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns a YAML value that is either a scalar or a list of scalars as a list of strings
+fn as_list(value: &Yaml) -> Option<Vec<String>> {
+    match value {
+        Yaml::Array(array) => array.iter().map(as_string).collect(),
+        value => Some(vec![as_string(value)?]),
+    }
+}
+
+/// Emits a YAML hash, without the document start marker
+pub fn emit_yaml(hash: Hash) -> Result<String> {
+    if hash.is_empty() {
+        return Ok(String::new());
+    }
+    let mut out = String::new();
+    YamlEmitter::new(&mut out)
+        .dump(&Yaml::Hash(hash))
+        .map_err(|err| {
+            Error::default(
+                Source::empty(),
+                lformat!("could not emit YAML: {error:?}", error = err),
+            )
+        })?;
+    let mut out = out.trim_start_matches("---").trim_start().to_owned();
+    out.push('\n');
+    Ok(out)
+}
@@ -18,14 +18,16 @@
 
 use crate::book::Book;
 use crate::error::{Error, Result, Source};
-use crate::token::Token;
+use crate::misc;
+use crate::resource_handler::ResourceHandler;
+use crate::token::{Attributes, Condition, Token};
 
 use std::convert::AsRef;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
 use std::mem;
 use std::ops::BitOr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use comrak::nodes::{AstNode, ListType, NodeValue};
 use comrak::{parse_document, Arena, ComrakOptions};
@@ -47,6 +49,7 @@ pub struct Features {
     pub superscript: bool,
     pub strikethrough: bool,
     pub taskitem: bool,
+    pub callout: bool,
 }
 
 impl Features {
@@ -64,6 +67,7 @@ impl Features {
             superscript: false,
             strikethrough: false,
             taskitem: false,
+            callout: false,
         }
     }
 }
@@ -90,6 +94,7 @@ impl BitOr for Features {
             superscript: self.superscript | rhs.superscript,
             strikethrough: self.strikethrough | rhs.strikethrough,
             taskitem: self.taskitem | rhs.taskitem,
+            callout: self.callout | rhs.callout,
         }
     }
 }
@@ -127,6 +132,30 @@ pub struct Parser {
     html_as_text: bool,
     superscript: bool,
     parse_frontmatter: bool,
+
+    /// Conditions and attributes of the fenced blocks found while preprocessing
+    blocks: Vec<(Vec<Condition>, Attributes)>,
+    /// Include directives found while preprocessing
+    includes: Vec<Include>,
+    /// Directory that paths of files are relative to
+    root: PathBuf,
+    /// Canonical paths of the files that include the one being parsed
+    included: Vec<PathBuf>,
+    /// Whether links of included files must be made relative to them
+    link_offsets: bool,
+    /// Whether images of included files must be made relative to them
+    image_offsets: bool,
+    /// Line of the source file each line of the preprocessed text comes from
+    /// (empty if preprocessing didn't change lines)
+    line_map: Vec<u32>,
+    /// Number of lines of the front matter, that comrak doesn't count
+    front_matter_lines: u32,
+    /// Line in the source file of each top-level token of the last parsed document
+    lines: Vec<u32>,
+    /// Whether `***` and `* * *` paragraphs are scene breaks instead of rules
+    detect_scene_breaks: bool,
+    /// Lines of the preprocessed text that are scene breaks (`***` or `* * *`)
+    scene_breaks: Vec<u32>,
 }
 
 impl Parser {
@@ -139,6 +168,17 @@ impl Parser {
             html_as_text: true,
             superscript: false,
             parse_frontmatter: false,
+            blocks: vec![],
+            includes: vec![],
+            root: PathBuf::new(),
+            included: vec![],
+            link_offsets: true,
+            image_offsets: true,
+            line_map: vec![],
+            front_matter_lines: 0,
+            lines: vec![],
+            detect_scene_breaks: false,
+            scene_breaks: vec![],
         }
     }
 
@@ -147,10 +187,18 @@ impl Parser {
         let mut parser = Parser::new();
         parser.html_as_text = book.options.get_bool("crowbook.html_as_text").unwrap();
         parser.parse_frontmatter = book.options.get_bool("input.yaml_blocks").unwrap();
+        parser.detect_scene_breaks = book.options.get_bool("input.scene_breaks").unwrap();
         parser.superscript = book
             .options
             .get_bool("crowbook.markdown.superscript")
             .unwrap();
+        parser.root = book.root.clone();
+        // Same rules as for chapters: base paths, if set, override the file's directory
+        let base_path = book.options.get_path("resources.base_path").is_ok();
+        parser.link_offsets =
+            !base_path && book.options.get_path("resources.base_path.links").is_err();
+        parser.image_offsets =
+            !base_path && book.options.get_path("resources.base_path.images").is_err();
         parser
     }
 
@@ -159,6 +207,11 @@ impl Parser {
         self.html_as_text = b;
     }
 
+    /// Enable/disable parsing `***` and `* * *` paragraphs as scene breaks
+    pub fn scene_breaks(&mut self, b: bool) {
+        self.detect_scene_breaks = b;
+    }
+
     /// Sets a parser's source file
     pub fn set_source_file(&mut self, s: &str) {
         self.source = Source::new(s);
@@ -208,12 +261,34 @@ impl Parser {
             options.extension.front_matter_delimiter = Some("---".to_owned());
         }
 
-        let root = parse_document(&arena, s, &options);
+        let s = self.preprocess(s)?;
+        let root = parse_document(&arena, &s, &options);
+        // comrak parses them as thematic breaks, like other rules
+        self.scene_breaks = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| self.detect_scene_breaks && is_scene_break(line))
+            .map(|(i, _)| i as u32 + 1)
+            .collect();
 
+        self.lines.clear();
+        self.front_matter_lines = root
+            .first_child()
+            .and_then(|c| match c.data.borrow().value {
+                NodeValue::FrontMatter(ref v) => Some(v.iter().filter(|&&b| b == b'\n').count() as u32),
+                _ => None,
+            })
+            .unwrap_or(0);
         let mut res = self.parse_node(root, &mut yaml)?;
 
         collapse(&mut res);
 
+        find_attributes(&mut res);
+
+        if find_callouts(&mut res) {
+            self.features.callout = true;
+        }
+
         find_standalone(&mut res);
 
         Ok(res)
@@ -245,6 +320,22 @@ impl Parser {
         self.features
     }
 
+    /// Returns the line, in the source file, of each top-level token returned by the last call to `parse`
+    ///
+    /// Tokens coming from an included file or a conditional block get the line of the directive.
+    pub fn lines(&self) -> &[u32] {
+        &self.lines
+    }
+
+    /// Returns the line of a node in the source file
+    fn source_line<'a>(&self, node: &'a AstNode<'a>) -> u32 {
+        let line = node.data.borrow().start_line + self.front_matter_lines;
+        line.checked_sub(1)
+            .and_then(|i| self.line_map.get(i as usize))
+            .copied()
+            .unwrap_or(line)
+    }
+
     fn parse_node<'a>(&mut self, node: &'a AstNode<'a>, yaml_block: &mut Option<&mut String>) -> Result<Vec<Token>> {
         let mut inner = vec![];
 
@@ -252,9 +343,48 @@ impl Parser {
         if let NodeValue::DescriptionTerm = node.data.borrow().value {
             self.ignore_paragraphs = true;
         }
+        // Fenced blocks that are not closed yet, with their content
+        let mut open: Vec<(Vec<Condition>, Attributes, Vec<Token>)> = vec![];
+        // Keep the line of each top-level token
+        let top_level = matches!(node.data.borrow().value, NodeValue::Document);
+        let mut line = 0;
         for c in node.children() {
-            let mut v = self.parse_node(c, yaml_block)?;
-            inner.append(&mut v);
+            if top_level {
+                self.lines.resize(inner.len(), line);
+                if open.is_empty() {
+                    line = self.source_line(c);
+                }
+            }
+            match self.marker(c) {
+                Some(Marker::Block(i)) => {
+                    let (conditions, attributes) = self.blocks[i].clone();
+                    open.push((conditions, attributes, vec![]))
+                }
+                Some(Marker::Include(i)) => {
+                    let mut v = self.include(i)?;
+                    let target = open.last_mut().map_or(&mut inner, |(_, _, v)| v);
+                    target.append(&mut v);
+                }
+                Some(Marker::End) => {
+                    if let Some((conditions, attributes, content)) = open.pop() {
+                        let target = open.last_mut().map_or(&mut inner, |(_, _, v)| v);
+                        target.push(fenced_block(conditions, attributes, content));
+                    }
+                }
+                None => {
+                    let mut v = self.parse_node(c, yaml_block)?;
+                    let target = open.last_mut().map_or(&mut inner, |(_, _, v)| v);
+                    target.append(&mut v);
+                }
+            }
+        }
+        // Close blocks whose end is missing (or in another container)
+        while let Some((conditions, attributes, content)) = open.pop() {
+            let target = open.last_mut().map_or(&mut inner, |(_, _, v)| v);
+            target.push(fenced_block(conditions, attributes, content));
+        }
+        if top_level {
+            self.lines.resize(inner.len(), line);
         }
         // Reset state after special cases shenanigans
         if let NodeValue::DescriptionTerm = node.data.borrow().value {
@@ -321,13 +451,34 @@ impl Parser {
             }
             NodeValue::Paragraph => {
                 if !self.ignore_paragraphs {
-                    vec![Token::Paragraph(inner)]
+                    // An escaped scene break (e.g. `\*\*\*`) is still one
+                    let text: Option<String> = inner
+                        .iter()
+                        .map(|token| match token {
+                            Token::Str(s) => Some(s.as_str()),
+                            _ => None,
+                        })
+                        .collect();
+                    if self.detect_scene_breaks
+                        && text.map_or(false, |text| is_scene_break(&text))
+                    {
+                        vec![Token::SceneBreak]
+                    } else {
+                        vec![Token::Paragraph(inner)]
+                    }
                 } else {
                     inner
                 }
             }
             NodeValue::Heading(ref heading) => vec![Token::Header(heading.level as i32, inner)],
-            NodeValue::ThematicBreak => vec![Token::Rule],
+            NodeValue::ThematicBreak => {
+                let line = node.data.borrow().start_line + self.front_matter_lines;
+                if self.scene_breaks.contains(&line) {
+                    vec![Token::SceneBreak]
+                } else {
+                    vec![Token::Rule]
+                }
+            }
             NodeValue::FootnoteDefinition(ref def) => {
                 let reference = String::from_utf8(def.clone()).map_err(|_| {
                     Error::parser(
@@ -344,7 +495,7 @@ impl Parser {
                         lformat!("Markdown file contains invalid UTF-8"),
                     )
                 })?;
-                vec![Token::Str(text)]
+                split_page_breaks(text)
             }
             NodeValue::Code(ref code) => {
                 let text = String::from_utf8(code.literal.clone()).map_err(|_| {
@@ -416,6 +567,670 @@ impl Parser {
     }
 }
 
+impl Parser {
+    /// Replaces the fences of blocks with conditions or attributes (e.g. `::: {only=epub}`,
+    /// `::: {.note}` and `:::`)
+    /// and the `{{#include ...}}` directives by HTML comments that won't be mixed
+    /// up with the surrounding Markdown.
+    ///
+    /// Other fenced blocks, and fences inside code blocks, are left untouched.
+    /// Include directives inside code blocks are directly replaced by the
+    /// content of the file (unless they are escaped, e.g. `\{{#include foo.rs}}`).
+    fn preprocess(&mut self, s: &str) -> Result<String> {
+        self.blocks.clear();
+        self.includes.clear();
+        self.line_map.clear();
+        if !s.contains(":::") && !s.contains("{{#include") {
+            return Ok(s.to_owned());
+        }
+        let mut res = String::with_capacity(s.len());
+        // Whether each open fenced block is a conditional one
+        let mut fences: Vec<bool> = vec![];
+        // Code fence character and length, if in a code block
+        let mut code_fence: Option<(char, usize)> = None;
+        // Length of the preprocessed text whose lines are already mapped
+        let mut mapped = 0;
+        let mut n = 0;
+        for (i, line) in s.split_inclusive('\n').enumerate() {
+            // Map the lines written for the previous line
+            map_lines(&mut self.line_map, &res[mapped..], i as u32);
+            mapped = res.len();
+            n = i as u32 + 1;
+            let trimmed = line.trim();
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let include = trimmed
+                .strip_prefix("{{#include")
+                .and_then(|r| r.strip_suffix("}}"))
+                .map(|r| self.parse_include(r, i as u32 + 1))
+                .transpose()?;
+            if let Some((c, n)) = code_fence {
+                if trimmed.chars().take_while(|&x| x == c).count() >= n
+                    && trimmed.chars().all(|x| x == c)
+                {
+                    code_fence = None;
+                } else if let Some(include) = include {
+                    let (_, content) = self.read_include(&include)?;
+                    for l in content.lines() {
+                        res.push_str(indent);
+                        res.push_str(l);
+                        res.push('\n');
+                    }
+                    continue;
+                } else if trimmed.starts_with("\\{{#include") {
+                    // Escaped directive, e.g. for documentation
+                    res.push_str(&line.replacen("\\{{#include", "{{#include", 1));
+                    continue;
+                }
+            } else if let Some(c) = trimmed.chars().next().filter(|&c| c == '`' || c == '~') {
+                let n = trimmed.chars().take_while(|&x| x == c).count();
+                if n >= 3 {
+                    code_fence = Some((c, n));
+                }
+            } else if let Some(include) = include {
+                write_marker(
+                    &mut res,
+                    indent,
+                    &format!("include:{}", self.includes.len()),
+                );
+                self.includes.push(include);
+                continue;
+            } else if trimmed.starts_with(":::") {
+                let rest = trimmed.trim_start_matches(':').trim();
+                if rest.is_empty() {
+                    if let Some(block) = fences.pop() {
+                        if block {
+                            write_marker(&mut res, indent, "block:end");
+                            continue;
+                        }
+                    }
+                } else {
+                    let rest = rest.trim_end_matches(':').trim_end();
+                    // Either attributes, or a single class (e.g. `::: note`)
+                    let block = match rest.strip_prefix('{').and_then(|r| r.strip_suffix('}')) {
+                        Some(attributes) => self.parse_block(attributes)?,
+                        None => parse_attributes(&format!(".{rest}")).map(|a| (vec![], a)),
+                    };
+                    match block {
+                        Some(block) => {
+                            fences.push(true);
+                            write_marker(&mut res, indent, &format!("block:{}", self.blocks.len()));
+                            self.blocks.push(block);
+                            continue;
+                        }
+                        None => fences.push(false),
+                    }
+                }
+            }
+            res.push_str(line);
+        }
+        map_lines(&mut self.line_map, &res[mapped..], n);
+        if !res.ends_with('\n') {
+            self.line_map.push(n);
+        }
+        Ok(res)
+    }
+
+    /// Parses the attributes of a fenced block, e.g. `.note only=epub if=!teacher_edition`,
+    /// separating the conditions from the other attributes.
+    ///
+    /// Returns `None` if these are not valid attributes.
+    fn parse_block(&self, attributes: &str) -> Result<Option<(Vec<Condition>, Attributes)>> {
+        let mut attributes = match parse_attributes(attributes) {
+            Some(attributes) => attributes,
+            None => return Ok(None),
+        };
+        let mut conditions = vec![];
+        let mut pairs = vec![];
+        for (key, value) in attributes.pairs.drain(..) {
+            let values: Vec<String> = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|v| !v.is_empty())
+                .map(|v| v.to_owned())
+                .collect();
+            let condition = match key.as_str() {
+                "only" if !values.is_empty() => Condition::Only(values),
+                "if" if values.len() == 1 => match values[0].strip_prefix('!') {
+                    Some(flag) => Condition::If(flag.to_owned(), false),
+                    None => Condition::If(values[0].clone(), true),
+                },
+                "only" | "if" => {
+                    return Err(Error::parser(
+                        &self.source,
+                        lformat!(
+                            "invalid value '{value}' for '{key}' in conditional block",
+                            value = value,
+                            key = key
+                        ),
+                    ))
+                }
+                _ => {
+                    pairs.push((key, value));
+                    continue;
+                }
+            };
+            conditions.push(condition);
+        }
+        attributes.pairs = pairs;
+        if conditions.is_empty() && attributes.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((conditions, attributes)))
+        }
+    }
+
+    /// Parses the argument of an include directive, e.g. `src/main.rs:10:20`
+    fn parse_include(&self, arg: &str, line: u32) -> Result<Include> {
+        let mut source = self.source.clone();
+        source.set_line(line);
+        let parts: Vec<&str> = arg.trim().split(':').collect();
+        let number = |s: &str| -> Result<Option<usize>> {
+            if s.is_empty() {
+                return Ok(None);
+            }
+            match s.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err(Error::parser(
+                    &source,
+                    lformat!(
+                        "invalid line number '{number}' in include directive",
+                        number = s
+                    ),
+                )),
+            }
+        };
+        let (start, end) = match parts.len() {
+            1 => (None, None),
+            2 => (number(parts[1])?, number(parts[1])?),
+            3 => (number(parts[1])?, number(parts[2])?),
+            _ => {
+                return Err(Error::parser(
+                    &source,
+                    lformat!(
+                        "invalid include directive '{directive}'",
+                        directive = arg.trim()
+                    ),
+                ))
+            }
+        };
+        if parts[0].is_empty() {
+            return Err(Error::parser(
+                &source,
+                lformat!("missing file name in include directive"),
+            ));
+        }
+        Ok(Include {
+            path: parts[0].to_owned(),
+            start,
+            end,
+            source,
+        })
+    }
+
+    /// Reads the (selected lines of the) file of an include directive.
+    ///
+    /// Returns the name of the file (as it would be set in the book configuration file)
+    /// and the included content.
+    fn read_include(&self, include: &Include) -> Result<(String, String)> {
+        let dir = self
+            .source
+            .file
+            .as_ref()
+            .and_then(|f| Path::new(f).parent())
+            .unwrap_or_else(|| Path::new(""));
+        let file = format!("{}", dir.join(&include.path).display());
+        let path = self.root.join(&file);
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.included.contains(&canonical) || self.current_file() == Some(canonical) {
+            return Err(Error::parser(
+                &include.source,
+                lformat!(
+                    "file {file} includes itself (directly or indirectly)",
+                    file = misc::normalize(&file)
+                ),
+            ));
+        }
+        let content = fs::read_to_string(&path).map_err(|_| {
+            Error::file_not_found(
+                &include.source,
+                lformat!("included file"),
+                format!("{}", path.display()),
+            )
+        })?;
+        let lines: Vec<&str> = content.lines().collect();
+        let start = include.start.unwrap_or(1);
+        let end = include.end.unwrap_or(lines.len());
+        if start > end || end > lines.len() {
+            return Err(Error::parser(
+                &include.source,
+                lformat!(
+                    "no line {start} to {end} in included file {file}",
+                    start = start,
+                    end = end,
+                    file = misc::normalize(&file)
+                ),
+            ));
+        }
+        let mut selected = lines[start - 1..end].join("\n");
+        selected.push('\n');
+        Ok((file, selected))
+    }
+
+    /// Returns the canonical path of the file being parsed, if it is known
+    fn current_file(&self) -> Option<PathBuf> {
+        let file = self.source.file.as_ref()?;
+        self.root.join(file).canonicalize().ok()
+    }
+
+    /// Parses the file of the i-th include directive.
+    ///
+    /// Markdown files are parsed (with links and images relative to the included
+    /// file), while other files are included as a code block.
+    fn include(&mut self, i: usize) -> Result<Vec<Token>> {
+        let (file, content) = self.read_include(&self.includes[i])?;
+        let extension = Path::new(&file)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_owned();
+        if extension != "md" && extension != "markdown" {
+            self.features.codeblock = true;
+            return Ok(vec![Token::CodeBlock(extension, content)]);
+        }
+        let mut parser = Parser {
+            source: Source::new(file.as_str()),
+            html_as_text: self.html_as_text,
+            superscript: self.superscript,
+            detect_scene_breaks: self.detect_scene_breaks,
+            root: self.root.clone(),
+            included: self.included.clone(),
+            link_offsets: self.link_offsets,
+            image_offsets: self.image_offsets,
+            ..Parser::new()
+        };
+        parser.included.extend(self.current_file());
+        let mut tokens = parser.parse(&content, None)?;
+        self.features = self.features | parser.features;
+
+        let offset = Path::new(&self.includes[i].path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        let empty = Path::new("");
+        ResourceHandler::add_offset(
+            if self.link_offsets { offset } else { empty },
+            if self.image_offsets { offset } else { empty },
+            &mut tokens,
+        );
+        Ok(tokens)
+    }
+
+    /// Checks whether a node is a marker inserted by `preprocess`.
+    fn marker<'a>(&self, node: &'a AstNode<'a>) -> Option<Marker> {
+        if let NodeValue::HtmlBlock(ref block) = node.data.borrow().value {
+            let text = std::str::from_utf8(&block.literal).ok()?.trim();
+            let marker = text.strip_prefix("<!-- crowbook:")?.strip_suffix(" -->")?;
+            if marker == "block:end" {
+                Some(Marker::End)
+            } else if let Some(n) = marker.strip_prefix("block:") {
+                n.parse::<usize>()
+                    .ok()
+                    .filter(|&i| i < self.blocks.len())
+                    .map(Marker::Block)
+            } else {
+                marker
+                    .strip_prefix("include:")?
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&i| i < self.includes.len())
+                    .map(Marker::Include)
+            }
+        } else {
+            None
+        }
+    }
+}
+
+/// An include directive, e.g. `{{#include src/main.rs:10:20}}`
+struct Include {
+    /// Path of the included file, relative to the including one
+    path: String,
+    /// First line to include (starting from 1)
+    start: Option<usize>,
+    /// Last line to include
+    end: Option<usize>,
+    /// Where the directive is, for error messages
+    source: Source,
+}
+
+/// A marker inserted by `Parser::preprocess`
+enum Marker {
+    /// Start of the i-th fenced block
+    Block(usize),
+    /// End of a fenced block
+    End,
+    /// The i-th include directive
+    Include(usize),
+}
+
+/// Maps each line of a preprocessed text to the line of the source file it comes from
+fn map_lines(line_map: &mut Vec<u32>, text: &str, line: u32) {
+    line_map.extend(text.matches('\n').map(|_| line));
+}
+
+/// Writes a marker as a standalone HTML block
+fn write_marker(res: &mut String, indent: &str, marker: &str) {
+    res.push('\n');
+    res.push_str(indent);
+    res.push_str("<!-- crowbook:");
+    res.push_str(marker);
+    res.push_str(" -->\n\n");
+}
+
+/// Returns the token of a fenced block, from its conditions and attributes
+fn fenced_block(conditions: Vec<Condition>, attributes: Attributes, content: Vec<Token>) -> Token {
+    if attributes.is_empty() {
+        Token::Conditional(conditions, content)
+    } else if conditions.is_empty() {
+        Token::Div(attributes, content)
+    } else {
+        Token::Conditional(conditions, vec![Token::Div(attributes, content)])
+    }
+}
+
+/// Checks whether a string can be used as an id or a class
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic())
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+}
+
+/// Checks whether a string can be used as the key of an attribute
+fn is_key(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_alphabetic())
+        && s.chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+/// Parses generic attributes, e.g. `#intro .centered lang=fr title="A title"`
+///
+/// Returns `None` if one of them is not an id, a class or a key/value pair.
+fn parse_attributes(s: &str) -> Option<Attributes> {
+    let mut attributes = Attributes::default();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        if let Some((key, r)) = rest.split_once('=').filter(|(key, _)| is_key(key)) {
+            let (value, r) = match r.strip_prefix('"') {
+                Some(r) => {
+                    let end = r.find('"')?;
+                    (&r[..end], &r[end + 1..])
+                }
+                None => {
+                    let end = r.find(char::is_whitespace).unwrap_or(r.len());
+                    (&r[..end], &r[end..])
+                }
+            };
+            attributes.pairs.push((key.to_owned(), value.to_owned()));
+            rest = r.trim_start();
+            continue;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let item = &rest[..end];
+        if let Some(id) = item.strip_prefix('#').filter(|id| is_identifier(id)) {
+            attributes.id = Some(id.to_owned());
+        } else if let Some(class) = item.strip_prefix('.').filter(|c| is_identifier(c)) {
+            attributes.classes.push(class.to_owned());
+        } else {
+            return None;
+        }
+        rest = rest[end..].trim_start();
+    }
+    Some(attributes)
+}
+
+/// Splits a text ending with attributes (e.g. `Title {#intro}`) into the text and the attributes
+fn split_trailing_attributes(text: &str) -> Option<(&str, Attributes)> {
+    let text = text.trim_end().strip_suffix('}')?;
+    let start = text.rfind('{')?;
+    let attributes = parse_attributes(&text[start + 1..])?;
+    Some((&text[..start], attributes))
+}
+
+/// Splits a text starting with attributes (e.g. `{.small} and more`) into the attributes
+/// and the rest of the text
+fn split_leading_attributes(text: &str) -> Option<(Attributes, &str)> {
+    let end = text.strip_prefix('{')?.find('}')? + 1;
+    let attributes = parse_attributes(&text[1..end])?;
+    Some((attributes, &text[end + 1..]))
+}
+
+/// Reads the generic attributes following headers, paragraphs, links, images and spans
+/// (`[some text]{.smallcaps}`), and stores them in the tokens
+fn find_attributes(ast: &mut Vec<Token>) {
+    for token in ast.iter_mut() {
+        if let Some(inner) = token.inner_mut() {
+            find_attributes(inner);
+        }
+        if let Token::Header(_, ref mut inner) | Token::Paragraph(ref mut inner) = *token {
+            let found = match inner.last() {
+                Some(Token::Str(text)) => split_trailing_attributes(text)
+                    .map(|(text, attributes)| (text.trim_end().to_owned(), attributes)),
+                _ => None,
+            };
+            if let Some((text, attributes)) = found {
+                if text.is_empty() && inner.len() == 1 {
+                    continue;
+                } else if text.is_empty() {
+                    inner.pop();
+                } else {
+                    *inner.last_mut().unwrap() = Token::Str(text);
+                }
+                inner.insert(0, Token::Attributes(attributes));
+            }
+        }
+    }
+
+    let mut i = 0;
+    while i < ast.len() {
+        // Attributes right after a link or an image
+        if let Some(Token::Str(text)) = ast.get(i + 1) {
+            if let Some((attributes, rest)) = split_leading_attributes(text) {
+                let rest = rest.to_owned();
+                if let Token::Link(_, _, ref mut inner)
+                | Token::Image(_, _, ref mut inner)
+                | Token::StandaloneImage(_, _, ref mut inner) = ast[i]
+                {
+                    inner.insert(0, Token::Attributes(attributes));
+                    if rest.is_empty() {
+                        ast.remove(i + 1);
+                    } else {
+                        ast[i + 1] = Token::Str(rest);
+                    }
+                }
+            }
+        }
+        // Spans: look for the closing bracket followed by attributes, then for the opening one
+        let found = match ast[i] {
+            Token::Str(ref text) => text.match_indices("]{").find_map(|(pos, _)| {
+                split_leading_attributes(&text[pos + 1..])
+                    .map(|(attributes, rest)| (pos, attributes, rest.len()))
+            }),
+            _ => None,
+        };
+        if let Some((pos, attributes, rest_len)) = found {
+            if let Some(span) = take_span(ast, i, pos, rest_len, attributes) {
+                i = span;
+                continue;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Replaces the tokens of a span, whose closing bracket is at `pos` in the i-th token
+/// and followed by attributes and `rest_len` bytes of text, by a `Token::Span`
+///
+/// Returns the index of the span, or `None` if there is no opening bracket.
+fn take_span(
+    ast: &mut Vec<Token>,
+    i: usize,
+    pos: usize,
+    rest_len: usize,
+    attributes: Attributes,
+) -> Option<usize> {
+    // Position of the opening bracket: index of the token and position in it
+    let (j, start) = (0..=i).rev().find_map(|j| match ast[j] {
+        Token::Str(ref text) => {
+            let text = if j == i { &text[..pos] } else { text.as_str() };
+            text.rfind('[').map(|start| (j, start))
+        }
+        _ => None,
+    })?;
+    let (before, after) = match (&ast[j], &ast[i]) {
+        (Token::Str(first), Token::Str(last)) => (
+            first[..start].to_owned(),
+            last[last.len() - rest_len..].to_owned(),
+        ),
+        _ => unreachable!(),
+    };
+    let tail = ast.split_off(i + 1);
+    let mut inner = ast.split_off(j);
+    if j == i {
+        if let Token::Str(ref text) = inner[0] {
+            inner[0] = Token::Str(text[start + 1..pos].to_owned());
+        }
+    } else {
+        if let Token::Str(ref text) = inner[0] {
+            inner[0] = Token::Str(text[start + 1..].to_owned());
+        }
+        let last = inner.len() - 1;
+        if let Token::Str(ref text) = inner[last] {
+            inner[last] = Token::Str(text[..pos].to_owned());
+        }
+    }
+    inner.retain(|token| token != &Token::Str(String::new()));
+
+    let mut tokens = vec![];
+    if !before.is_empty() {
+        tokens.push(Token::Str(before));
+    }
+    let index = j + tokens.len();
+    tokens.push(Token::Span(attributes, inner));
+    if !after.is_empty() {
+        tokens.push(Token::Str(after));
+    }
+    ast.extend(tokens);
+    ast.extend(tail);
+    Some(index)
+}
+
+/// Kinds of callouts, whose titles are translated in `lang/document`
+const CALLOUTS: &[&str] = &["note", "tip", "important", "warning", "caution"];
+
+/// Replaces block quotes starting with `[!NOTE]` (or another kind of callout), and fenced
+/// blocks with the class of a kind of callout (`::: warning`), by callouts
+///
+/// Returns true if a callout was found.
+fn find_callouts(ast: &mut [Token]) -> bool {
+    let mut found = false;
+    for token in ast.iter_mut() {
+        if let Some(inner) = token.inner_mut() {
+            found |= find_callouts(inner);
+        }
+        let callout = match *token {
+            Token::BlockQuote(ref mut inner) => {
+                callout_marker(inner).map(|kind| (kind, mem::take(inner)))
+            }
+            Token::Div(ref mut attributes, ref mut inner) => attributes
+                .classes
+                .iter()
+                .position(|c| CALLOUTS.contains(&c.as_str()))
+                .map(|i| {
+                    let kind = attributes.classes.remove(i);
+                    let mut inner = mem::take(inner);
+                    if !attributes.is_empty() {
+                        inner.insert(0, Token::Attributes(mem::take(attributes)));
+                    }
+                    (kind, inner)
+                }),
+            _ => None,
+        };
+        if let Some((kind, inner)) = callout {
+            *token = Token::Callout(kind, inner);
+            found = true;
+        }
+    }
+    found
+}
+
+/// Removes the `[!NOTE]` marker (or the one of another kind of callout) at the beginning
+/// of a block quote, and returns the kind of callout
+fn callout_marker(inner: &mut Vec<Token>) -> Option<String> {
+    let paragraph = match inner.first_mut() {
+        Some(Token::Paragraph(paragraph)) => paragraph,
+        _ => return None,
+    };
+    let i = paragraph
+        .iter()
+        .position(|t| !matches!(t, Token::Attributes(_)))?;
+    let (kind, rest) = match paragraph[i] {
+        Token::Str(ref text) => {
+            let (marker, rest) = text.strip_prefix("[!")?.split_once(']')?;
+            let kind = marker.to_lowercase();
+            if !CALLOUTS.contains(&kind.as_str())
+                || !(rest.is_empty() || rest.starts_with(char::is_whitespace))
+            {
+                return None;
+            }
+            (kind, rest.trim_start().to_owned())
+        }
+        _ => return None,
+    };
+    if rest.is_empty() {
+        paragraph.remove(i);
+    } else {
+        paragraph[i] = Token::Str(rest);
+    }
+    if paragraph.iter().all(|t| matches!(t, Token::Attributes(_))) {
+        inner.remove(0);
+    }
+    Some(kind)
+}
+
+/// Checks whether a line or a paragraph is a scene break, `***` or `* * *`
+fn is_scene_break(text: &str) -> bool {
+    matches!(text.trim(), "***" | "* * *")
+}
+
+/// Splits a text on page break markers (e.g. `{{#page 12}}`)
+fn split_page_breaks(text: String) -> Vec<Token> {
+    if !text.contains("{{#page") {
+        return vec![Token::Str(text)];
+    }
+    let mut res = vec![];
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find("{{#page") {
+        let after = &rest[start + "{{#page".len()..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => break,
+        };
+        let number = after[..end].trim();
+        if number.is_empty() || !after.starts_with(char::is_whitespace) {
+            res.push(Token::Str(rest[..start + "{{#page".len()].to_owned()));
+            rest = after;
+            continue;
+        }
+        if start > 0 {
+            res.push(Token::Str(rest[..start].to_owned()));
+        }
+        res.push(Token::PageBreak(number.to_owned()));
+        rest = &after[end + 2..];
+    }
+    if !rest.is_empty() {
+        res.push(Token::Str(rest.to_owned()));
+    }
+    res
+}
+
 impl Default for Parser {
     fn default() -> Self {
         Self::new()
@@ -461,6 +1276,13 @@ fn collapse(ast: &mut Vec<Token>) {
 /// Replace images which are alone in a paragraph by standalone images
 fn find_standalone(ast: &mut Vec<Token>) {
     for token in ast {
+        if let Token::Conditional(_, ref mut inner)
+        | Token::Div(_, ref mut inner)
+        | Token::Callout(_, ref mut inner) = *token
+        {
+            find_standalone(inner);
+            continue;
+        }
         let res = if let &mut Token::Paragraph(ref mut inner) = token {
             if inner.len() == 1 {
                 if inner[0].is_image() {
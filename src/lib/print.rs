@@ -0,0 +1,231 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Layout of print-ready PDF files: trim sizes, bleed, crop marks and gutter
+//!
+//! All lengths are in big points (`bp`, 1/72 inch), the unit of PDF boxes.
+
+/// Big points in an inch
+const INCH: f64 = 72.0;
+
+/// Space left around the bleed for crop marks
+const MARKS: f64 = 0.5 * INCH;
+
+/// Named trim sizes, commonly offered by print-on-demand services, in inches
+const TRIM_SIZES: &[(&str, f64, f64)] = &[
+    ("pocket", 4.25, 6.87),
+    ("digest", 5.5, 8.5),
+    ("us-trade", 6.0, 9.0),
+    ("royal", 6.14, 9.21),
+    ("crown-quarto", 7.44, 9.69),
+    ("executive", 7.0, 10.0),
+    ("letter", 8.5, 11.0),
+    ("a5", 5.83, 8.27),
+    ("b5", 6.93, 9.84),
+    ("a4", 8.27, 11.69),
+];
+
+/// Parses a TeX dimension (e.g. `2cm`, `0.125in`, `10pt`), returning it in big points
+///
+/// A number without unit is read in `default_unit`.
+pub fn parse_dimension(s: &str, default_unit: &str) -> Option<f64> {
+    let s = s.trim();
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let value: f64 = s[..end].parse().ok()?;
+    let unit = match s[end..].trim() {
+        "" => default_unit,
+        unit => unit,
+    };
+    let factor = match unit {
+        "bp" => 1.0,
+        "pt" => INCH / 72.27,
+        "in" => INCH,
+        "cm" => INCH / 2.54,
+        "mm" => INCH / 25.4,
+        "pc" => 12.0 * INCH / 72.27,
+        _ => return None,
+    };
+    Some(value * factor)
+}
+
+/// Parses a trim size, either one of the named ones (e.g. `royal`) or a width and a height
+/// separated by `x` (e.g. `6x9`, in inches if units are not given, or `148mmx210mm`)
+///
+/// Returns the width and the height, in big points.
+pub fn trim_size(s: &str) -> Option<(f64, f64)> {
+    if let Some(&(_, width, height)) = TRIM_SIZES.iter().find(|(name, _, _)| *name == s) {
+        return Some((width * INCH, height * INCH));
+    }
+    let (width, height) = s.split_once('x')?;
+    Some((
+        parse_dimension(width, "in")?,
+        parse_dimension(height, "in")?,
+    ))
+}
+
+/// Returns the names of the trim sizes that can be used instead of dimensions
+pub fn trim_size_names() -> Vec<&'static str> {
+    TRIM_SIZES.iter().map(|(name, _, _)| *name).collect()
+}
+
+/// Returns the minimal inner margin required by print-on-demand services for a book
+/// of `pages` pages
+pub fn gutter(pages: u32) -> f64 {
+    INCH * match pages {
+        0..=150 => 0.375,
+        151..=300 => 0.5,
+        301..=500 => 0.625,
+        501..=700 => 0.75,
+        _ => 0.875,
+    }
+}
+
+/// Formats a length in big points for TeX
+pub fn bp(length: f64) -> String {
+    format!("{length:.2}bp")
+}
+
+/// Page layout of a print-ready PDF
+#[derive(Debug, PartialEq)]
+pub struct Layout {
+    /// Size of the pages, as seen by LaTeX
+    pub paper: (f64, f64),
+    /// Size of the sheets the pages are centered on, if crop marks are printed
+    pub stock: Option<(f64, f64)>,
+    /// Top, bottom, inner and outer margins
+    pub margins: [f64; 4],
+    /// Trim box of the pages, in PDF coordinates
+    pub trim_box: [f64; 4],
+    /// Bleed box of the pages, in PDF coordinates
+    pub bleed_box: [f64; 4],
+}
+
+impl Layout {
+    /// Computes the layout of pages of size `trim`, with `bleed` around them and the given
+    /// (top, bottom, inner, outer) margins, measured from the trimmed edges
+    ///
+    /// Without crop marks, pages are enlarged by the bleed, so content can extend up to it.
+    /// With crop marks, pages keep their trim size and are centered on larger sheets
+    /// where the marks are drawn, around the bleed.
+    pub fn new(trim: (f64, f64), bleed: f64, crop_marks: bool, margins: [f64; 4]) -> Layout {
+        let (width, height) = trim;
+        if crop_marks {
+            let offset = bleed + MARKS;
+            Layout {
+                paper: trim,
+                stock: Some((width + 2.0 * offset, height + 2.0 * offset)),
+                margins,
+                trim_box: [offset, offset, width + offset, height + offset],
+                bleed_box: [
+                    offset - bleed,
+                    offset - bleed,
+                    width + offset + bleed,
+                    height + offset + bleed,
+                ],
+            }
+        } else {
+            Layout {
+                paper: (width + 2.0 * bleed, height + 2.0 * bleed),
+                stock: None,
+                margins: [
+                    margins[0] + bleed,
+                    margins[1] + bleed,
+                    margins[2] + bleed,
+                    margins[3] + bleed,
+                ],
+                trim_box: [bleed, bleed, width + bleed, height + bleed],
+                bleed_box: [0.0, 0.0, width + 2.0 * bleed, height + 2.0 * bleed],
+            }
+        }
+    }
+
+    /// Returns the page attributes setting the trim and bleed boxes
+    pub fn page_boxes(&self) -> String {
+        let boxes = |b: &[f64; 4]| {
+            b.iter()
+                .map(|x| format!("{x:.2}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        format!(
+            "/TrimBox [{}] /BleedBox [{}]",
+            boxes(&self.trim_box),
+            boxes(&self.bleed_box)
+        )
+    }
+}
+
+/// Returns the names of the fonts that are not embedded, according to the output of `pdffonts`
+pub fn unembedded_fonts(pdffonts: &str) -> Vec<String> {
+    pdffonts
+        .lines()
+        .skip_while(|line| !line.starts_with("---"))
+        .skip(1)
+        .filter_map(|line| {
+            // Font types can contain spaces, so columns are read from the end:
+            // emb, sub, uni, object and ID
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 6 {
+                return None;
+            }
+            if columns[columns.len() - 5] == "no" {
+                Some(columns[0].to_owned())
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn print_layout() {
+        assert_eq!(parse_dimension("1in", "in"), Some(72.0));
+        assert_eq!(parse_dimension("2.54cm", "in"), Some(72.0));
+        assert_eq!(parse_dimension("0.5", "in"), Some(36.0));
+        assert_eq!(parse_dimension("2 furlongs", "in"), None);
+        assert_eq!(trim_size("6x9"), Some((432.0, 648.0)));
+        assert_eq!(trim_size("royal"), trim_size("6.14x9.21"));
+        assert!(trim_size("huge").is_none());
+        assert_eq!(gutter(320), 45.0);
+
+        let margins = [36.0, 36.0, 45.0, 36.0];
+        let layout = Layout::new((432.0, 648.0), 9.0, false, margins);
+        assert_eq!(layout.paper, (450.0, 666.0));
+        assert_eq!(layout.margins, [45.0, 45.0, 54.0, 45.0]);
+        assert_eq!(
+            layout.page_boxes(),
+            "/TrimBox [9.00 9.00 441.00 657.00] /BleedBox [0.00 0.00 450.00 666.00]"
+        );
+        let layout = Layout::new((432.0, 648.0), 9.0, true, margins);
+        assert_eq!(layout.paper, (432.0, 648.0));
+        assert_eq!(layout.stock, Some((522.0, 738.0)));
+        assert_eq!(layout.trim_box, [45.0, 45.0, 477.0, 693.0]);
+
+        let pdffonts = "name                                 type              encoding         emb sub uni object ID
+------------------------------------ ----------------- ---------------- --- --- --- ---------
+ABCDEF+LinLibertineO                 CID Type 0C       Identity-H       yes yes yes      7  0
+Helvetica                            Type 1            WinAnsi          no  no  no       9  0
+";
+        assert_eq!(unembedded_fonts(pdffonts), vec!["Helvetica"]);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::{Error, Result, Source};
-use crate::token::Token;
+use crate::token::{self, Token};
 
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -165,6 +165,19 @@ impl ResourceHandler {
         self.links.insert(from.into(), to.into());
     }
 
+    /// Add the matches of the anchors set in a chapter (e.g. `{#intro}`), so that links
+    /// to `#intro` or `chapter.md#intro` can be resolved
+    pub fn add_anchors<F>(&mut self, chapter: &str, tokens: &[Token], to: F)
+    where
+        F: Fn(&str) -> String,
+    {
+        for id in token::collect_ids(tokens) {
+            let dest = to(&id);
+            self.add_link(format!("{chapter}#{id}"), dest.clone());
+            self.add_link(format!("#{id}"), dest);
+        }
+    }
+
     /// Get a destination link from an original link
     pub fn get_link<'a>(&'a self, from: &'a str) -> &'a str {
         if let Some(link) = self.links.get(from) {
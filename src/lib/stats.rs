@@ -130,6 +130,11 @@ impl Stats {
         stats
     }
 
+    /// Returns the total number of words of the book
+    pub fn word_count(&self) -> usize {
+        self.chapters.iter().map(|c| c.word_count).sum()
+    }
+
     // Returns the Language (defined by Hyphenation crate) according to the str code
     #[cfg(feature = "nightly")]
     fn language_from_str(lang: &str) -> Language {
@@ -15,16 +15,318 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::book::Book;
+#[cfg(feature = "syntect")]
+use crate::error::Error;
 use crate::error::Result;
 
 use crowbook_text_processing::escape;
 
 #[cfg(feature = "syntect")]
+use std::collections::HashMap;
+use std::mem;
+#[cfg(feature = "syntect")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "syntect")]
+use std::sync::{Arc, Mutex};
+
+/// Informations about a code block, parsed from its info string,
+/// e.g. `rust,linenos,hl_lines=3-5,title="main.rs"`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CodeInfo {
+    /// Language of the code block (first bare item of the info string)
+    pub language: String,
+    /// Whether lines must be numbered
+    pub line_numbers: bool,
+    /// Number of the first line
+    pub first_line: u32,
+    /// Ranges (inclusive, starting at 1) of emphasized lines
+    pub highlighted: Vec<(u32, u32)>,
+    /// Title, displayed above the code block
+    pub title: Option<String>,
+    /// Caption, displayed below the code block
+    pub caption: Option<String>,
+}
+
+impl CodeInfo {
+    /// Parses the info string of a code block.
+    ///
+    /// Unknown items are ignored.
+    pub fn new(info: &str) -> CodeInfo {
+        let mut res = CodeInfo {
+            first_line: 1,
+            ..CodeInfo::default()
+        };
+        for (i, item) in split_info(info).into_iter().enumerate() {
+            match item.split_once('=') {
+                Some(("hl_lines", value)) => res.highlighted = parse_ranges(value),
+                Some(("title", value)) => res.title = Some(value.to_owned()),
+                Some(("caption", value)) => res.caption = Some(value.to_owned()),
+                Some(("linenostart", value)) => {
+                    if let Ok(n) = value.parse() {
+                        res.first_line = n;
+                        res.line_numbers = true;
+                    }
+                }
+                Some(_) => (),
+                None if item == "linenos" => res.line_numbers = true,
+                None if i == 0 => res.language = item,
+                None => (),
+            }
+        }
+        res
+    }
+
+    /// Returns true if lines must be rendered one by one, because they are numbered or
+    /// some of them are emphasized
+    pub fn has_line_decorations(&self) -> bool {
+        self.line_numbers || !self.highlighted.is_empty()
+    }
+
+    /// Returns true if the line (starting at 1) is emphasized
+    pub fn is_highlighted(&self, line: u32) -> bool {
+        self.highlighted
+            .iter()
+            .any(|&(start, end)| line >= start && line <= end)
+    }
+
+    /// Splits code in lines, ignoring the final newline
+    pub fn lines(code: &str) -> impl Iterator<Item = &str> {
+        code.strip_suffix('\n').unwrap_or(code).split('\n')
+    }
+
+    /// Joins already formatted HTML lines, adding line numbers and emphasis if needed
+    pub fn html_lines(&self, lines: Vec<String>) -> String {
+        if !self.has_line_decorations() {
+            return lines.join("\n");
+        }
+        let mut res = Vec::with_capacity(lines.len());
+        for (i, line) in lines.into_iter().enumerate() {
+            let i = i as u32 + 1;
+            let class = if self.is_highlighted(i) {
+                "code-line highlighted"
+            } else {
+                "code-line"
+            };
+            let number = if self.line_numbers {
+                format!(
+                    "<span class = \"line-number\">{}</span>",
+                    self.first_line + i - 1
+                )
+            } else {
+                String::new()
+            };
+            res.push(format!("<span class = \"{class}\">{number}{line}</span>"));
+        }
+        res.join("\n")
+    }
+
+    /// Joins already formatted LaTeX lines, adding line numbers and emphasis if needed
+    pub fn tex_lines(&self, lines: Vec<String>) -> String {
+        let mut res = Vec::with_capacity(lines.len());
+        for (i, mut line) in lines.into_iter().enumerate() {
+            let i = i as u32 + 1;
+            if line.is_empty() {
+                line = String::from("\\mbox{}");
+            }
+            if self.is_highlighted(i) {
+                line = format!("\\mdcodehighlight{{{line}}}");
+            }
+            if self.line_numbers {
+                line = format!("\\mdcodelinenumber{{{}}}{line}", self.first_line + i - 1);
+            }
+            res.push(line);
+        }
+        format!("{{\\sloppy {}}}", res.join("\\\\{}\n"))
+    }
+
+    /// Wraps a rendered HTML code block with its title and caption, if any
+    pub fn html_block(&self, code: String) -> String {
+        if self.title.is_none() && self.caption.is_none() {
+            return format!("{code}\n");
+        }
+        let mut res = String::from("<div class = \"code-block\">\n");
+        if let Some(ref title) = self.title {
+            res.push_str(&format!(
+                "<p class = \"code-title\">{}</p>\n",
+                escape::html(title.as_str())
+            ));
+        }
+        res.push_str(&code);
+        res.push('\n');
+        if let Some(ref caption) = self.caption {
+            res.push_str(&format!(
+                "<p class = \"code-caption\">{}</p>\n",
+                escape::html(caption.as_str())
+            ));
+        }
+        res.push_str("</div>\n");
+        res
+    }
+}
+
+/// Splits an info string on commas and whitespaces, except between double quotes,
+/// and removes these quotes
+fn split_info(info: &str) -> Vec<String> {
+    let mut items = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in info.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if !quoted && (c == ',' || c.is_whitespace()) => items.push(mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    items.push(current);
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Parses ranges of lines, e.g. "3-5 7" or "3-5,7"
+fn parse_ranges(value: &str) -> Vec<(u32, u32)> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            Some((start.trim().parse().ok()?, end.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Renders a line of code in LaTeX, without syntax highlighting
+///
+/// If `wrap` is set, allows line breaks between words and after some punctuation
+pub fn tex_text(text: &str, wrap: bool) -> String {
+    let mut content = escape::tex(text).into_owned();
+    if wrap {
+        content = crate::latex::insert_breaks(&content).replace(' ', "\\hphantom{ }\\allowbreak{}");
+    } else {
+        content = content.replace(' ', "\\hphantom{ }");
+    }
+    if content.is_empty() {
+        content
+    } else {
+        format!("\\texttt{{{content}}}")
+    }
+}
+
+/// Renders a code block in HTML, without syntax highlighting
+pub fn plain_html(code: &str, info: &CodeInfo) -> String {
+    let lines = CodeInfo::lines(code)
+        .map(|line| escape::html(line).into_owned())
+        .collect();
+    let class = if info.language.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " class = \"language-{}\"",
+            escape::quotes(escape::html(info.language.as_str()))
+        )
+    };
+    format!("<pre><code{class}>{}</code></pre>", info.html_lines(lines))
+}
+
+/// Renders a code block in LaTeX, without syntax highlighting
+///
+/// Verbatim environments are used unless lines must be numbered or emphasized.
+pub fn plain_tex(code: &str, info: &CodeInfo, wrap: bool) -> String {
+    if info.has_line_decorations() {
+        let lines = CodeInfo::lines(code)
+            .map(|line| tex_text(line, wrap))
+            .collect();
+        info.tex_lines(lines)
+    } else {
+        let env = if wrap { "spverbatim" } else { "verbatim" };
+        format!(
+            "\\begin{{{env}}}
+{}
+\\end{{{env}}}",
+            code.trim_end_matches('\n')
+        )
+    }
+}
+
+/// Syntax definitions and themes, loaded from syntect's defaults and from
+/// additional directories
+#[cfg(feature = "syntect")]
+struct Definitions {
+    syntax_set: syntect::parsing::SyntaxSet,
+    theme_set: syntect::highlighting::ThemeSet,
+}
+
+/// Directories of additional syntaxes and themes, if any
+#[cfg(feature = "syntect")]
+type DefinitionsKey = (Option<PathBuf>, Option<PathBuf>);
+
+#[cfg(feature = "syntect")]
+lazy_static! {
+    /// Definitions already loaded, by directories of additional syntaxes and themes
+    static ref DEFINITIONS: Mutex<HashMap<DefinitionsKey, Arc<Definitions>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[cfg(feature = "syntect")]
+impl Definitions {
+    /// Returns the definitions used by a book, loading them if they are not cached yet
+    fn get(book: &Book) -> Result<Arc<Definitions>> {
+        let dir = |key| book.options.get_path(key).ok().map(PathBuf::from);
+        let key = (
+            dir("rendering.highlight.syntaxes"),
+            dir("rendering.highlight.themes"),
+        );
+        // Keep the lock while loading, so renderers running in parallel don't load them twice
+        let mut cache = DEFINITIONS.lock().unwrap();
+        if let Some(definitions) = cache.get(&key) {
+            return Ok(definitions.clone());
+        }
+        let definitions = Arc::new(Definitions::load(book, key.0.as_deref(), key.1.as_deref())?);
+        cache.insert(key, definitions.clone());
+        Ok(definitions)
+    }
+
+    /// Loads the default definitions, plus the `.sublime-syntax` and `.tmTheme` files
+    /// found in the given directories
+    fn load(book: &Book, syntaxes: Option<&Path>, themes: Option<&Path>) -> Result<Definitions> {
+        let mut syntax_set = syntect::parsing::SyntaxSet::load_defaults_nonewlines();
+        if let Some(dir) = syntaxes {
+            let mut builder = syntax_set.into_builder();
+            builder.add_from_folder(dir, false).map_err(|err| {
+                Error::syntect(
+                    &book.source,
+                    lformat!(
+                        "could not load syntax definitions from {dir}: {error}",
+                        dir = dir.display(),
+                        error = err
+                    ),
+                )
+            })?;
+            syntax_set = builder.build();
+        }
+        let mut theme_set = syntect::highlighting::ThemeSet::load_defaults();
+        if let Some(dir) = themes {
+            theme_set.add_from_folder(dir).map_err(|err| {
+                Error::syntect(
+                    &book.source,
+                    lformat!(
+                        "could not load syntax highlighting themes from {dir}: {error}",
+                        dir = dir.display(),
+                        error = err
+                    ),
+                )
+            })?;
+        }
+        Ok(Definitions {
+            syntax_set,
+            theme_set,
+        })
+    }
+}
 
 /// Wrapper around syntect, so it can be more easily optionally compiled.
 #[cfg(feature = "syntect")]
 pub struct Syntax {
-    syntax_set: syntect::parsing::SyntaxSet,
+    definitions: Arc<Definitions>,
     theme: syntect::highlighting::Theme,
 }
 
@@ -34,77 +336,75 @@ pub struct Syntax {}
 #[cfg(feature = "syntect")]
 impl Syntax {
     /// Creates a new Syntax wrapper
-    pub fn new(theme_name: &str) -> Syntax {
-        let mut theme_set = syntect::highlighting::ThemeSet::load_defaults();
-        let theme = match theme_set.themes.remove(theme_name) {
-            Some(theme) => theme,
+    ///
+    /// Returns an error if the theme is neither a default one nor one found in
+    /// `rendering.highlight.themes`.
+    pub fn new(book: &Book, theme_name: &str) -> Result<Syntax> {
+        let definitions = Definitions::get(book)?;
+        let theme = match definitions.theme_set.themes.get(theme_name) {
+            Some(theme) => theme.clone(),
             None => {
-                error!(
-                    "{}",
-                    lformat!(
-                        "could not set syntect theme to {theme}, defaulting to \"InspiredGitHub\"",
-                        theme = theme_name
-                    )
-                );
-                info!(
-                    "{}",
+                let mut themes: Vec<_> = definitions
+                    .theme_set
+                    .themes
+                    .keys()
+                    .map(|s| format!("\"{s}\""))
+                    .collect();
+                themes.sort();
+                return Err(Error::book_option(
+                    &book.source,
                     lformat!(
-                        "valid theme names are: {themes}",
-                        themes = theme_set
-                            .themes
-                            .keys()
-                            .map(|s| s.to_owned())
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    )
-                );
-                theme_set.themes.remove("InspiredGitHub").unwrap()
+                        "unknown syntax highlighting theme \"{theme}\", valid theme names are: {themes}",
+                        theme = theme_name,
+                        themes = themes.join(", ")
+                    ),
+                ));
             }
         };
-        Syntax {
-            syntax_set: syntect::parsing::SyntaxSet::load_defaults_nonewlines(),
-            theme,
-        }
+        Ok(Syntax { definitions, theme })
     }
 
     /// Convert a string containing code to HTML
-    pub fn to_html(&self, code: &str, language: &str) -> Result<String> {
-        let language = strip_language(language);
+    pub fn to_html(&self, code: &str, info: &CodeInfo) -> Result<String> {
         let syntax = self
+            .definitions
             .syntax_set
-            .find_syntax_by_token(language)
-            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+            .find_syntax_by_token(&info.language)
+            .unwrap_or_else(|| self.definitions.syntax_set.find_syntax_plain_text());
         let mut h = syntect::easy::HighlightLines::new(syntax, &self.theme);
-        let mut formatted_code = String::new();
-        for line in code.split('\n') {
-            let regions = h.highlight_line(line, &self.syntax_set)?;
+        let mut lines = vec![];
+        for line in CodeInfo::lines(code) {
+            let regions = h.highlight_line(line, &self.definitions.syntax_set)?;
             let bg = syntect::html::IncludeBackground::No;
-            let res: String = syntect::html::styled_line_to_highlighted_html(&regions[..], bg)?;
-            formatted_code.push_str(&res);
+            lines.push(syntect::html::styled_line_to_highlighted_html(
+                &regions[..],
+                bg,
+            )?);
         }
-        Ok(format!("<pre>{formatted_code}</pre>"))
+        Ok(format!("<pre>{}</pre>", info.html_lines(lines)))
     }
 
-    pub fn to_tex(&self, code: &str, language: &str) -> Result<String> {
-        let language = strip_language(language);
-        use crate::latex::insert_breaks;
+    /// Convert a string containing code to LaTeX
+    ///
+    /// If `wrap` is set, long lines can be broken.
+    pub fn to_tex(&self, code: &str, info: &CodeInfo, wrap: bool) -> Result<String> {
         use syntect::highlighting::{Color, FontStyle};
         let syntax = self
+            .definitions
             .syntax_set
-            .find_syntax_by_token(language)
-            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+            .find_syntax_by_token(&info.language)
+            .unwrap_or_else(|| self.definitions.syntax_set.find_syntax_plain_text());
         let mut h = syntect::easy::HighlightLines::new(syntax, &self.theme);
 
-        let mut formatted_code = String::new();
-        for line in code.split('\n') {
-            let regions = h.highlight_line(line, &self.syntax_set)?;
+        let mut lines = vec![];
+        for line in CodeInfo::lines(code) {
+            let regions = h.highlight_line(line, &self.definitions.syntax_set)?;
+            let mut formatted_line = String::new();
             for (style, text) in regions {
-                let mut content = escape::tex(text).into_owned();
-                content = insert_breaks(&content);
-                content = content
-                    .replace('\n', "\\\\{}\n")
-                    .replace(' ', "\\hphantom{ }\\allowbreak{}");
-                content = format!("\\texttt{{{content}}}");
+                let mut content = tex_text(text, wrap);
+                if content.is_empty() {
+                    continue;
+                }
                 if style.foreground != Color::BLACK {
                     let r = style.foreground.r as f32 / 255.0;
                     let g = style.foreground.g as f32 / 255.0;
@@ -120,39 +420,26 @@ impl Syntax {
                 if style.font_style.contains(FontStyle::UNDERLINE) {
                     content = format!("\\underline{{{content}}}");
                 }
-                formatted_code.push_str(&content);
+                formatted_line.push_str(&content);
             }
+            lines.push(formatted_line);
         }
-        Ok(format!("{{\\sloppy {formatted_code}}}"))
+        Ok(info.tex_lines(lines))
     }
 }
 
-/// Strip language name of possible other infos, e.g. "rust,ignore" -> "rust"
-/// Currently only ',' is done
-fn strip_language(language: &str) -> &str {
-    let splits: Vec<_> = language.split(|c: char| matches!(c, ',')).collect();
-    splits[0].trim()
-}
-
 #[cfg(not(feature = "syntect"))]
 impl Syntax {
-    pub fn new(_: &str) -> Syntax {
+    pub fn new(_: &Book, _: &str) -> Result<Syntax> {
         error!("{}", lformat!("crowbook was compiled without syntect support, syntax highlighting will be disabled"));
-        Syntax {}
+        Ok(Syntax {})
     }
 
-    pub fn to_html(&self, code: &str, language: &str) -> Result<String> {
-        Ok(format!(
-            "<pre><code class = \"language-{lang}\">{code}</code></pre>",
-            code = escape::html(code),
-            lang = language
-        ))
+    pub fn to_html(&self, code: &str, info: &CodeInfo) -> Result<String> {
+        Ok(plain_html(code, info))
     }
 
-    pub fn to_tex(&self, code: &str, _: &str) -> Result<String> {
-        Ok(format!(
-            "\\begin{{spverbatim}}{}\\end{{spverbatim}}\n",
-            code
-        ))
+    pub fn to_tex(&self, code: &str, info: &CodeInfo, wrap: bool) -> Result<String> {
+        Ok(plain_tex(code, info, wrap))
     }
 }
@@ -52,6 +52,12 @@ pub mod html_if {
 
 pub mod latex {
     pub static TEMPLATE: &str = include_str!("../../templates/latex/template.tex");
+    pub static MANUSCRIPT: &str = include_str!("../../templates/latex/manuscript.tex");
+    pub static COVER: &str = include_str!("../../templates/latex/cover.tex");
+}
+
+pub mod typst {
+    pub static TEMPLATE: &str = include_str!("../../templates/typst/template.typ");
 }
 
 pub mod epub {
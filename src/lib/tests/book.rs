@@ -1,5 +1,14 @@
 use super::test_eq;
 use crate::book::Book;
+use crate::chapter::Matter;
+use crate::epub::EpubRenderer;
+use crate::number::Number;
+use crate::pandoc::PandocConversion;
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
 
 #[test]
 fn load_config() {
@@ -21,3 +30,634 @@ epub.version: 3";
     );
     assert_eq!(book.options.get_i32("epub.version").unwrap(), 3);
 }
+
+#[test]
+fn pandoc_conversion() {
+    let yaml = "
+metadata:
+  title: Some title
+  author: [Joan Doe, John Smith]
+variables:
+  documentclass: report
+  papersize: a4
+  fontsize: 12pt
+  geometry: margin=3cm
+toc: true
+highlight-style: tango";
+    let conversion = PandocConversion::from_pandoc(yaml, ".", &[]).unwrap();
+    assert_eq!(conversion.unmapped, vec!["highlight-style".to_owned()]);
+    let files = [String::from("my chapter.md")];
+    let with_files = PandocConversion::from_pandoc(yaml, ".", &files).unwrap();
+    assert!(with_files.content.contains("\"my chapter.md\"\n"));
+
+    let mut book = Book::new();
+    book.read_config(conversion.content.as_bytes()).unwrap();
+    test_eq(
+        book.options.get_str("author").unwrap(),
+        "Joan Doe, John Smith",
+    );
+    test_eq(book.options.get_str("tex.class").unwrap(), "report");
+    test_eq(book.options.get_str("tex.paper.size").unwrap(), "a4paper");
+    test_eq(book.options.get_str("tex.margin.top").unwrap(), "3cm");
+    assert_eq!(book.options.get_i32("tex.font.size").unwrap(), 12);
+    assert!(book.options.get_bool("rendering.inline_toc").unwrap());
+
+    let conversion = PandocConversion::to_pandoc(&book).unwrap();
+    assert!(conversion.unmapped.is_empty());
+    assert!(conversion.content.contains("toc: true\n"));
+    assert!(conversion.content.contains("  papersize: a4\n"));
+    assert!(conversion.content.contains("    - top=3cm\n"));
+}
+
+#[test]
+fn conditional_blocks() {
+    let content = "
+Common text.
+
+::: {only=epub}
+Ebook text.
+:::
+
+::: {only=print,text}
+Print text.
+:::
+
+::: {if=teacher}
+Teacher text.
+:::
+
+::: {if=!teacher}
+Student text.
+:::
+";
+    let mut book = Book::new();
+    book.set_options(&[("rendering.flags", "[teacher]")]);
+    book.add_chapter_from_source(Number::Hidden, content.as_bytes(), false)
+        .unwrap();
+    let mut txt = vec![];
+    book.render_format_to("txt", &mut txt).unwrap();
+    let txt = String::from_utf8(txt).unwrap();
+    assert!(txt.contains("Common text."));
+    assert!(!txt.contains("Ebook text."));
+    assert!(txt.contains("Print text."));
+    assert!(txt.contains("Teacher text."));
+    assert!(!txt.contains("Student text."));
+
+    let mut md = vec![];
+    book.render_format_to("md", &mut md).unwrap();
+    let md = String::from_utf8(md).unwrap();
+    assert!(md.contains("Common text."));
+    assert!(!md.contains("Ebook text."));
+    assert!(!md.contains("Print text."));
+    assert!(md.contains("Teacher text."));
+    assert!(!md.contains("Student text."));
+    assert!(!md.contains(":::"));
+}
+
+#[test]
+fn chapter_globs() {
+    let mut book = Book::new();
+    book.root = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/include"));
+    book.read_config("author: a\n\n+ \"sub/*.md\"\n- sub/\n".as_bytes())
+        .unwrap();
+    let files: Vec<&str> = book.chapters.iter().map(|c| c.filename.as_str()).collect();
+    assert_eq!(files, vec!["sub/section.md", "sub/section.md"]);
+
+    let mut book = Book::new();
+    assert!(book
+        .read_config("author: a\n\n+ \"unclosed.md\n".as_bytes())
+        .is_err());
+    assert!(book
+        .read_config("author: a\n\n+ nothing/*.md\n".as_bytes())
+        .is_err());
+}
+
+#[test]
+fn chapter_metadata() {
+    let content = "---
+chapter:
+  author: Jane *Doe*
+  epigraph: Some quote
+  class: story \"x\"
+chapter.numbered: false
+author: Someone else
+---
+
+# The Story
+
+Some text.
+";
+    let mut book = Book::new();
+    book.set_options(&[("input.yaml_blocks", "true")]);
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    let chapter = &book.chapters[0];
+    assert_eq!(chapter.number, Number::Unnumbered);
+    assert_eq!(chapter.metadata()["author"], "Jane *Doe*");
+    assert_eq!(chapter.metadata()["class"], "story \"x\"");
+    assert_eq!(chapter.metadata()["numbered"], "false");
+    assert_eq!(book.options.get_str("author").unwrap(), "Someone else");
+
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<p class = 'chapter-author'>Jane <em>Doe</em></p>"));
+    assert!(html.contains("<p class = 'chapter-epigraph'>Some quote</p>"));
+    assert!(html.contains("class = \"chapter mainmatter story 'x'\""));
+
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\mdchapterauthor{Jane \\emph{Doe}}"));
+}
+
+#[test]
+fn matters() {
+    let mut book = Book::new();
+    book.set_matter(Matter::Front);
+    book.add_chapter_from_source(Number::Default, "# Foreword\n".as_bytes(), true)
+        .unwrap();
+    book.set_matter(Matter::Main);
+    book.add_chapter_from_source(Number::Default, "# One\n".as_bytes(), true)
+        .unwrap();
+    book.set_matter(Matter::Appendix);
+    book.add_chapter_from_source(Number::Default, "# Glossary\n".as_bytes(), true)
+        .unwrap();
+    book.add_chapter_from_source(Number::Default, "# Timeline\n".as_bytes(), true)
+        .unwrap();
+    book.set_matter(Matter::Back);
+    book.add_chapter_from_source(Number::Default, "# Colophon\n".as_bytes(), true)
+        .unwrap();
+    let numbers: Vec<Number> = book.chapters.iter().map(|c| c.number).collect();
+    assert_eq!(
+        numbers,
+        vec![
+            Number::Unnumbered,
+            Number::Default,
+            Number::SpecifiedAppendix(1),
+            Number::DefaultAppendix,
+            Number::Unnumbered
+        ]
+    );
+
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("class = \"chapter appendix\""));
+    assert!(html.contains("Appendix B"));
+
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    let frontmatter = tex.find("\\frontmatter").unwrap();
+    let mainmatter = tex.find("\\mainmatter").unwrap();
+    let appendix = tex.find("\\appendix").unwrap();
+    let backmatter = tex.find("\\backmatter").unwrap();
+    assert!(frontmatter < mainmatter && mainmatter < appendix && appendix < backmatter);
+
+    let mut book = Book::new();
+    assert!(book.read_config("[middlematter]\n".as_bytes()).is_err());
+}
+
+#[test]
+fn epub_profile() {
+    let mut book = Book::new();
+    book.read_config("epub.profile: nook".as_bytes()).unwrap();
+    assert!(book.render_format_to("epub", &mut io::sink()).is_err());
+
+    let mut book = Book::new();
+    book.read_config("epub.profile: kindle".as_bytes()).unwrap();
+    assert!(EpubRenderer::new(&book).is_ok());
+
+    // Apple Books gets EPUB 3, unless the version is set explicitly, even to its default
+    let mut book = Book::new();
+    book.read_config("epub.profile: apple".as_bytes()).unwrap();
+    let files = epub_files(&mut book);
+    let opf = epub_file(&files, "OEBPS/content.opf").unwrap();
+    assert!(opf.contains("<package version=\"3.0\""));
+    let mut book = Book::new();
+    book.read_config("epub.profile: apple\nepub.version: 2".as_bytes())
+        .unwrap();
+    let files = epub_files(&mut book);
+    let opf = epub_file(&files, "OEBPS/content.opf").unwrap();
+    assert!(opf.contains("<package version=\"2.0\""));
+}
+
+/// Renders a book to EPUB and returns the name and content of its files, in order
+fn epub_files(book: &mut Book) -> Vec<(String, String)> {
+    let mut epub = vec![];
+    book.render_format_to("epub", &mut epub).unwrap();
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(epub)).unwrap();
+    (0..archive.len())
+        .map(|i| {
+            let mut file = archive.by_index(i).unwrap();
+            let mut content = vec![];
+            file.read_to_end(&mut content).unwrap();
+            let content = String::from_utf8_lossy(&content).into_owned();
+            (file.name().to_owned(), content)
+        })
+        .collect()
+}
+
+/// Returns the content of a file of an EPUB
+fn epub_file<'a>(files: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    files
+        .iter()
+        .find(|(file, _)| file == name)
+        .map(|(_, content)| content.as_str())
+}
+
+#[test]
+fn epub_fonts() {
+    let dir = env::temp_dir().join(format!(
+        "crowbook-fonts-{}",
+        uuid::Uuid::new_v4().as_simple()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("Serif-Bold.ttf"), [0u8; 2048]).unwrap();
+
+    let mut book = Book::new();
+    book.set_options(&[
+        ("epub.identifier", "urn:isbn:9780000000000"),
+        ("epub.fonts", "[Serif-Bold.ttf]"),
+        ("epub.fonts.obfuscate", "true"),
+        ("resources.base_path.files", dir.to_str().unwrap()),
+    ]);
+    book.add_chapter_from_source(Number::Default, "# One\n\nText.\n".as_bytes(), false)
+        .unwrap();
+    let files = epub_files(&mut book);
+    // Apple Books needs an option file to use embedded fonts
+    book.set_options(&[("epub.profile", "apple")]);
+    let apple = epub_files(&mut book);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let display_options = "META-INF/com.apple.ibooks.display-options.xml";
+    assert!(!epub_file(&files, display_options)
+        .unwrap()
+        .contains("<option name = \"specified-fonts\">"));
+    assert_eq!(
+        apple
+            .iter()
+            .filter(|(file, _)| file == display_options)
+            .count(),
+        1
+    );
+    assert!(epub_file(&apple, display_options)
+        .unwrap()
+        .contains("<option name = \"specified-fonts\">true</option>"));
+
+    assert_eq!(
+        files[0],
+        (
+            String::from("mimetype"),
+            String::from("application/epub+zip")
+        )
+    );
+    let opf = epub_file(&files, "OEBPS/content.opf").unwrap();
+    assert!(opf.contains(">urn:isbn:9780000000000</dc:identifier>"));
+    let encryption = epub_file(&files, "META-INF/encryption.xml").unwrap();
+    assert!(encryption.contains("URI = \"OEBPS/fonts/Serif-Bold.ttf\""));
+    let font = epub_file(&files, "OEBPS/fonts/Serif-Bold.ttf").unwrap();
+    assert!(font.bytes().take(1040).any(|b| b != 0));
+}
+
+#[test]
+fn epub_media_overlays() {
+    let dir = env::temp_dir().join(format!(
+        "crowbook-overlays-{}",
+        uuid::Uuid::new_v4().as_simple()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("one.mp3"), [0u8; 16]).unwrap();
+    fs::write(dir.join("one.txt"), "0 1.5\n1.5 4\n").unwrap();
+
+    let content = "---
+chapter:
+  audio: one.mp3
+  audio.timing: one.txt
+---
+
+# One
+
+First paragraph.
+
+Second paragraph.
+";
+    let mut book = Book::new();
+    book.set_options(&[
+        ("epub.version", "3"),
+        ("input.yaml_blocks", "true"),
+        ("resources.base_path.files", dir.to_str().unwrap()),
+    ]);
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    let files = epub_files(&mut book);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let opf = epub_file(&files, "OEBPS/content.opf").unwrap();
+    assert!(opf.contains("media-overlay=\"smil-chapter_000\""));
+    assert!(opf.contains("href=\"smil/chapter_000.smil\" media-type=\"application/smil+xml\""));
+    assert!(opf.contains("<meta property=\"media:duration\">0:00:04.000</meta>"));
+    assert!(epub_file(&files, "OEBPS/smil/chapter_000.smil").is_some());
+    assert!(epub_file(&files, "OEBPS/audio/chapter_000.mp3").is_some());
+}
+
+#[test]
+fn epub_fixed_layout() {
+    let mut book = Book::new();
+    book.read_config("epub.layout: scrolled".as_bytes())
+        .unwrap();
+    assert!(EpubRenderer::new(&book).is_err());
+
+    let mut book = Book::new();
+    book.read_config(
+        "epub.layout: pre-paginated
+epub.layout.pages: spread"
+            .as_bytes(),
+    )
+    .unwrap();
+    assert!(EpubRenderer::new(&book).is_err());
+
+    let mut book = Book::new();
+    book.read_config("epub.layout: pre-paginated\nepub.version: 2".as_bytes())
+        .unwrap();
+    assert!(EpubRenderer::new(&book).is_ok());
+
+    let mut book = Book::new();
+    book.read_config("epub.layout: pre-paginated\nepub.version: 3".as_bytes())
+        .unwrap();
+    book.add_chapter_from_source(Number::Default, "# One\n\nText.\n".as_bytes(), false)
+        .unwrap();
+    let files = epub_files(&mut book);
+    let opf = epub_file(&files, "OEBPS/content.opf").unwrap();
+    assert!(opf.contains("<meta property=\"rendition:layout\">pre-paginated</meta>"));
+    assert!(opf.contains("<meta property=\"rendition:spread\">auto</meta>"));
+}
+
+#[test]
+fn typst_rendering() {
+    let content = "# First chapter
+
+Some *emphasis* and a footnote[^1], then the same one again[^1].
+
+## A section
+
+| Name | Value |
+|------|-------|
+| #a   | [b]   |
+
+```rust
+fn main() {}
+```
+
+[^1]: A note.
+";
+    let mut book = Book::new();
+    book.set_options(&[("rendering.num_depth", "2"), ("author", "Jane Doe")]);
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    let mut typ = vec![];
+    book.render_format_to("typ", &mut typ).unwrap();
+    let typ = String::from_utf8(typ).unwrap();
+    assert!(typ.contains("author: \"Jane Doe\""));
+    assert!(typ.contains("#mdchapter[1. First chapter]"));
+    assert!(typ.contains("#metadata(none) <chapter-0>"));
+    assert!(typ.contains("#heading(level: 2)[1.1 A section]"));
+    assert!(typ.contains("#emph[emphasis];"));
+    assert!(typ.contains("#footnote[A note.] <footnote-0-1>"));
+    assert!(typ.contains("#footnote(<footnote-0-1>);"));
+    assert!(typ.contains("table.header([Name],\n[Value])"));
+    assert!(typ.contains("[\\#a],\n[\\[b\\]]"));
+    assert!(typ.contains("raw(block: true, lang: \"rust\", \"fn main() {}\")"));
+}
+
+#[test]
+fn scene_breaks() {
+    let content = "It ended.
+
+* * *
+
+It began.
+";
+    let mut book = Book::new();
+    book.set_options(&[
+        ("input.scene_breaks", "true"),
+        ("rendering.scene_break", "⁂"),
+    ]);
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    let mut render = |format: &str| {
+        let mut res = vec![];
+        book.render_format_to(format, &mut res).unwrap();
+        String::from_utf8(res).unwrap()
+    };
+    assert!(render("html").contains("<p class = \"scene-break\">⁂</p>"));
+    assert!(render("tex").contains("\\mdscenebreak\n"));
+    assert!(render("typ").contains("#mdscenebreak([⁂])"));
+    assert!(render("txt").contains("⁂"));
+}
+
+#[test]
+fn attributes() {
+    let content = "# Title {#start}
+
+See [the note](#note) or [the start](#start). {.lead}
+
+::: {#note .box}
+A [short]{.sc lang=en} note.
+:::
+";
+    let mut book = Book::new();
+    book.set_options(&[("tex.classes", "[box=quote, sc=textsc]")]);
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    let mut render = |format: &str| {
+        let mut res = vec![];
+        book.render_format_to(format, &mut res).unwrap();
+        String::from_utf8(res).unwrap()
+    };
+    let html = render("html");
+    assert!(html.contains("<div id = \"start\">"));
+    assert!(html.contains("class = \"lead\">See"));
+    assert!(html.contains("<a href = \"#note\">the note</a>"));
+    assert!(html.contains("<div id = \"note\" class = \"box\">"));
+    assert!(html.contains("<span class = \"sc\" lang = \"en\">short</span>"));
+    let tex = render("tex");
+    assert!(tex.contains("\\label{start}"));
+    assert!(tex.contains("\\hyperref[note]{the note}"));
+    assert!(tex.contains("\\phantomsection\\label{note}\\begin{quote}"));
+    assert!(tex.contains("\\textsc{short}"));
+    assert!(render("typ").contains("#metadata(none) <note>"));
+
+    let mut book = Book::new();
+    book.set_options(&[("tex.classes", "[box]")]);
+    assert!(book.render_format_to("tex", &mut io::sink()).is_err());
+}
+
+#[test]
+fn callouts() {
+    let content = "> [!NOTE]
+> Read this first.
+
+::: warning
+Do not try this at home.
+:::
+";
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    assert!(book.features.callout);
+    let mut render = |format: &str| {
+        let mut res = vec![];
+        book.render_format_to(format, &mut res).unwrap();
+        String::from_utf8(res).unwrap()
+    };
+    let html = render("html");
+    assert!(html.contains("<aside class = \"callout callout-note\">"));
+    assert!(html.contains("<p class = \"callout-title\">Warning</p>"));
+    let tex = render("tex");
+    assert!(tex.contains("{tcolorbox}"));
+    assert!(tex.contains("\\begin{mdcallout}{note}{Note}"));
+    assert!(render("typ").contains("#mdcallout(\"warning\", [Warning])"));
+    assert!(render("txt").contains("NOTE:"));
+    assert!(render("md").contains("> [!NOTE]\n> Read this first."));
+
+    book.set_options(&[("lang", "fr")]);
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<p class = \"callout-title\">Avertissement</p>"));
+}
+
+#[test]
+fn code_blocks() {
+    let content = "```rust,linenos,hl_lines=2,title=\"main.rs\",caption=\"Hello\"
+fn main() {
+    println!(\"<hello>\");
+}
+```
+
+```
+plain  code
+```
+
+```x<y>
+odd language
+```
+";
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    let render = |book: &mut Book, format: &str| {
+        let mut res = vec![];
+        book.render_format_to(format, &mut res).unwrap();
+        String::from_utf8(res).unwrap()
+    };
+    let html = render(&mut book, "html");
+    assert!(html.contains("<p class = \"code-title\">main.rs</p>"));
+    assert!(html.contains("<p class = \"code-caption\">Hello</p>"));
+    assert!(html.contains(
+        "<span class = \"code-line highlighted\"><span class = \"line-number\">2</span>"
+    ));
+    let tex = render(&mut book, "tex");
+    assert!(tex.contains("\\mdcodetitle{main.rs}"));
+    assert!(tex.contains("\\mdcodelinenumber{3}"));
+    assert!(tex.contains("\\mdcodehighlight{"));
+    assert!(tex.contains("\\mdcodecaption{Hello}"));
+    assert!(render(&mut book, "typ").contains("lang: \"rust\""));
+
+    book.set_options(&[("rendering.highlight", "none"), ("tex.code.wrap", "false")]);
+    let html = render(&mut book, "html");
+    assert!(html.contains("&lt;hello&gt;"));
+    assert!(html.contains("<pre><code>plain  code</code></pre>"));
+    assert!(html.contains("<pre><code class = \"language-x&lt;y&gt;\">odd language</code></pre>"));
+    let tex = render(&mut book, "tex");
+    assert!(tex.contains("\\begin{verbatim}\nplain  code\n\\end{verbatim}"));
+}
+
+#[test]
+#[cfg(feature = "syntect")]
+fn syntect_definitions() {
+    let mut book = Book::new();
+    book.add_chapter_from_source(
+        Number::Default,
+        "```rust\nfn main() {}\n```\n".as_bytes(),
+        false,
+    )
+    .unwrap();
+    book.set_options(&[("rendering.highlight.theme", "Unknown")]);
+    let err = book.render_format_to("html", &mut io::sink()).unwrap_err();
+    assert!(format!("{err}").contains("\"InspiredGitHub\""));
+    assert!(book.render_format_to("tex", &mut io::sink()).is_err());
+
+    book.set_options(&[
+        ("rendering.highlight.theme", "InspiredGitHub"),
+        ("rendering.highlight.themes", "does/not/exist"),
+    ]);
+    assert!(book.render_format_to("html", &mut io::sink()).is_err());
+}
+
+#[test]
+fn print_ready_tex() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("tex.trim.size", "6x9"),
+        ("tex.bleed", "0.125in"),
+        ("tex.margin.gutter", "1in"),
+        ("tex.pdf.standard", "x-1a"),
+        ("title", "Print"),
+    ]);
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("paperwidth=450.00bp, paperheight=666.00bp"));
+    assert!(tex.contains("inner=81.00bp"));
+    assert!(tex.contains("/TrimBox [9.00 9.00 441.00 657.00]"));
+    assert!(tex.contains("\\usepackage[x-1a]{pdfx}"));
+    assert!(tex.contains("\\Title{Print}"));
+    assert!(!tex.contains("{crop}"));
+
+    let mut book = Book::new();
+    book.set_options(&[("tex.trim.size", "huge")]);
+    assert!(book.render_format_to("tex", &mut io::sink()).is_err());
+    let mut book = Book::new();
+    book.set_options(&[("tex.pdf.standard", "x-2")]);
+    assert!(book.render_format_to("tex", &mut io::sink()).is_err());
+    // The print cover needs a trim size
+    let mut book = Book::new();
+    assert!(book
+        .render_format_to("cover.print", &mut io::sink())
+        .is_err());
+}
+
+#[test]
+fn latex_typography() {
+    let mut book = Book::new();
+    book.set_options(&[
+        ("tex.font.main", "Linux Libertine O"),
+        ("tex.line_spacing", "1.2"),
+        ("tex.paragraph.style", "spacing"),
+        ("tex.penalty.widow", "10000"),
+        ("tex.chapter.style", "rule"),
+        ("tex.headers", "chapter"),
+        ("tex.microtype", "true"),
+    ]);
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\setmainfont{Linux Libertine O}"));
+    assert!(tex.contains("\\setstretch{1.2}"));
+    assert!(tex.contains("{parskip}"));
+    assert!(tex.contains("\\widowpenalty=10000"));
+    assert!(!tex.contains("\\clubpenalty"));
+    assert!(tex.contains("\\titlerule"));
+    assert!(tex.contains("\\nouppercase{\\leftmark}"));
+    assert!(tex.contains("]{microtype}"));
+
+    let mut book = Book::new();
+    book.set_options(&[("tex.chapter.style", "baroque")]);
+    assert!(book.render_format_to("tex", &mut io::sink()).is_err());
+    let mut book = Book::new();
+    book.set_options(&[("tex.headers", "everywhere")]);
+    assert!(book.render_format_to("tex", &mut io::sink()).is_err());
+}
@@ -0,0 +1,131 @@
+use crate::book::Book;
+use crate::epub_import::{resolve, resolve_link, EpubImport};
+use crate::number::Number;
+use crate::xml;
+
+use std::env;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn xml_container_and_package() {
+    let container = xml::parse(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#,
+    );
+    let rootfile = container.find("rootfile").unwrap();
+    assert_eq!(rootfile.attribute("full-path"), Some("OEBPS/content.opf"));
+
+    let package = xml::parse(
+        r#"<package xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <metadata>
+    <dc:title>Tom &amp; Jerry</dc:title>
+    <!-- <dc:title>Not a title</dc:title> -->
+    <dc:creator>Joan</dc:creator>
+    <dc:creator>John</dc:creator>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" properties="nav scripted"/>
+    <item id="c1" href="text/ch%201.xhtml"/>
+  </manifest>
+  <spine><itemref idref="nav"/><itemref idref="c1"/></spine>
+</package>"#,
+    );
+    let title = package.find("title").unwrap();
+    assert_eq!(title.name, "dc:title");
+    assert_eq!(title.text(), "Tom & Jerry");
+    assert_eq!(package.find_all("title").len(), 1);
+    assert_eq!(package.find_all("creator").len(), 2);
+    let items = package.find_all("item");
+    assert!(items[0].has_token("properties", "nav"));
+    assert!(!items[1].has_token("properties", "nav"));
+    assert_eq!(items[1].attribute("href"), Some("text/ch%201.xhtml"));
+    assert_eq!(package.find("spine").unwrap().find_all("itemref").len(), 2);
+}
+
+#[test]
+fn xml_navigation() {
+    let nav = xml::parse(
+        r#"<html xmlns:epub="http://www.idpf.org/2007/ops"><body>
+<nav epub:type="toc"><ol><li><a href="chapter_000.xhtml">One<br>line</a></li></ol></nav>
+<p>Unclosed <i>element</p>
+</body></html>"#,
+    );
+    let toc = nav.find("nav").unwrap();
+    assert_eq!(toc.attribute("epub:type"), Some("toc"));
+    assert_eq!(toc.attribute("type"), Some("toc"));
+    let link = toc.find("a").unwrap();
+    assert_eq!(link.attribute("href"), Some("chapter_000.xhtml"));
+    assert_eq!(link.text(), "Oneline");
+    assert_eq!(nav.find("p").unwrap().text(), "Unclosed element");
+}
+
+#[test]
+fn resolve_paths() {
+    assert_eq!(
+        resolve("OEBPS/", "text/../images/a.png"),
+        "OEBPS/images/a.png"
+    );
+    assert_eq!(resolve("OEBPS/text/", "./b.xhtml"), "OEBPS/text/b.xhtml");
+    // Paths can't go outside the EPUB
+    assert_eq!(resolve("", "../../etc/passwd"), "etc/passwd");
+    assert_eq!(resolve("OEBPS/", "../../../a.png"), "a.png");
+    assert_eq!(
+        resolve_link("OEBPS/text/ch1.xhtml", "#note"),
+        "OEBPS/text/ch1.xhtml#note"
+    );
+    assert_eq!(
+        resolve_link("OEBPS/text/ch1.xhtml", "../notes%20a.xhtml#n1"),
+        "OEBPS/notes a.xhtml#n1"
+    );
+}
+
+#[test]
+fn import_rendered_epub() {
+    // Importing extracts the EPUB with the `unzip` command
+    if Command::new("unzip").arg("-v").output().is_err() {
+        eprintln!("unzip command not found, skipping EPUB import test");
+        return;
+    }
+    let mut book = Book::new();
+    book.set_options(&[("title", "Imported"), ("author", "Someone"), ("lang", "fr")]);
+    book.add_chapter_from_source(
+        Number::Default,
+        "# First\n\nSome *text*.\n".as_bytes(),
+        false,
+    )
+    .unwrap();
+    book.add_chapter_from_source(Number::Default, "# Second\n\nMore.\n".as_bytes(), false)
+        .unwrap();
+    let mut epub = vec![];
+    book.render_format_to("epub", &mut epub).unwrap();
+
+    let dir = env::temp_dir().join(format!(
+        "crowbook-import-{}",
+        uuid::Uuid::new_v4().as_simple()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("test.epub"), &epub).unwrap();
+    let book_file = dir.join("book").join("test.book");
+    let result = EpubImport::new(dir.join("test.epub"), &Book::new())
+        .and_then(|import| import.write(&book_file));
+    let config = fs::read_to_string(&book_file);
+    let first = fs::read_to_string(dir.join("book").join("chapter_000.md"));
+    fs::remove_dir_all(&dir).unwrap();
+    result.unwrap();
+
+    let config = config.unwrap();
+    assert!(config.contains("Imported"));
+    assert!(config.contains("Someone"));
+    assert!(config.contains("lang: fr"));
+    assert!(config.contains("- chapter_000.md\n- chapter_001.md\n"));
+    // The navigation document is not imported as a chapter
+    assert!(!config.contains("nav"));
+    let first = first.unwrap();
+    assert!(first.contains("First"));
+    assert!(first.contains("Some *text*."));
+}
@@ -9,4 +9,5 @@ pub fn test_eq(actual: &str, expected: &str) {
 }
 
 mod book;
+mod epub_import;
 mod parser;
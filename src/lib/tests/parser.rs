@@ -1,11 +1,16 @@
 use super::test_eq;
 use crate::book::Book;
+use crate::markdown::MarkdownRenderer;
 use crate::parser::Parser;
-use crate::token::Token;
+use crate::syntax::CodeInfo;
+use crate::token::{Attributes, Condition, Token};
 
 fn parse_from_str(doc: &str) -> Vec<Token> {
-    let book = Book::new();
-    let mut parser = Parser::from(&book);
+    parse_with(&Book::new(), doc)
+}
+
+fn parse_with(book: &Book, doc: &str) -> Vec<Token> {
+    let mut parser = Parser::from(book);
     parser.parse(doc, None).unwrap()
 }
 
@@ -177,3 +182,416 @@ fn table_simple() {
     let result = format!("{:?}", parse_from_str(doc));
     test_eq(&result, expected);
 }
+
+/// Checks that rendering a document to Markdown and parsing it again gives the same AST,
+/// and that rendering is idempotent
+fn round_trip(doc: &str) {
+    round_trip_with(&Book::new(), doc);
+}
+
+fn round_trip_with(book: &Book, doc: &str) {
+    let expected = parse_with(book, doc);
+    let markdown = MarkdownRenderer::new(book)
+        .render_markdown(&expected)
+        .unwrap();
+    let result = parse_with(book, &markdown);
+    test_eq(&format!("{:?}", result), &format!("{:?}", expected));
+    let again = MarkdownRenderer::new(book)
+        .render_markdown(&result)
+        .unwrap();
+    test_eq(&again, &markdown);
+}
+
+#[test]
+fn round_trip_inline() {
+    round_trip(
+        "# A *title* with `code` #
+
+Some *emphasis*, **strong**, ~~strikethrough~~ and ``code with ` backtick``.
+A [link](http://foo.bar \"with title\"), an autolink <http://foo.bar/baz>
+and an ![image](<some image.png>).  
+After a hard break.
+",
+    );
+}
+
+#[test]
+fn round_trip_escapes() {
+    round_trip(
+        "1986\\. A great year, and \\*not emphasis\\*.
+
+\\# not a header, \\[not a link\\](foo) & a \\| pipe
+
+\\- not a list
+",
+    );
+}
+
+#[test]
+fn round_trip_lists() {
+    round_trip(
+        "* a tight list
+* with a nested one
+    1. first
+    2. second
+
+3. an ordered list
+
+   with two paragraphs
+4. starting at 3
+
+- [x] done
+- [ ] not done
+",
+    );
+}
+
+#[test]
+fn round_trip_blocks() {
+    round_trip(
+        "> A quote
+>
+> > nested, with a list:
+> >
+> > * foo
+> > * bar
+
+````rust
+let s = \"```\";
+````
+
+***
+
+| A | Simple | Table |
+|---|--------|-------|
+| with `code` | *some* | cells |
+
+Term
+
+: Details
+
+Some text with a footnote[^1].
+
+[^1]: The footnote,
+
+    spanning two paragraphs.
+",
+    );
+}
+
+#[test]
+fn conditional_blocks() {
+    let doc = "
+::: {only=\"epub html\" if=!teacher}
+Some *text*
+
+::: {if=teacher}
+nested
+:::
+:::
+
+```
+::: {only=epub}
+:::
+```
+";
+    let expected = vec![
+        Token::Conditional(
+            vec![
+                Condition::Only(vec![String::from("epub"), String::from("html")]),
+                Condition::If(String::from("teacher"), false),
+            ],
+            vec![
+                Token::Paragraph(vec![
+                    Token::Str(String::from("Some ")),
+                    Token::Emphasis(vec![Token::Str(String::from("text"))]),
+                ]),
+                Token::Conditional(
+                    vec![Condition::If(String::from("teacher"), true)],
+                    vec![Token::Paragraph(vec![Token::Str(String::from("nested"))])],
+                ),
+            ],
+        ),
+        Token::CodeBlock(String::new(), String::from("::: {only=epub}\n:::\n")),
+    ];
+    assert_eq!(parse_from_str(doc), expected);
+    // Conditions are evaluated when rendering to Markdown
+    let markdown = MarkdownRenderer::new(&Book::new())
+        .render_markdown(&expected)
+        .unwrap();
+    assert!(!markdown.contains("text"));
+    assert!(markdown.contains("::: {only=epub}\n:::\n"));
+
+    let mut parser = Parser::new();
+    assert!(parser.parse("::: {only=}\nfoo\n:::", None).is_err());
+}
+
+#[test]
+fn include() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/include");
+    let mut parser = Parser::new();
+    parser.set_source_file(&format!("{dir}/chapter.md"));
+    let tokens = parser
+        .parse_file(format!("{dir}/chapter.md"), None)
+        .unwrap();
+    let expected = vec![
+        Token::Paragraph(vec![Token::Str(String::from("Intro"))]),
+        Token::StandaloneImage(
+            String::from("sub/image.png"),
+            String::new(),
+            vec![Token::Str(String::from("An image"))],
+        ),
+        Token::CodeBlock(
+            String::from("rust"),
+            String::from("    println!(\"Hello\");\n}\n{{#include code.rs}}\n"),
+        ),
+        Token::CodeBlock(String::from("rs"), String::from("fn main() {\n")),
+    ];
+    assert_eq!(tokens, expected);
+
+    parser.set_source_file(&format!("{dir}/loop.md"));
+    assert!(parser.parse_file(format!("{dir}/loop.md"), None).is_err());
+
+    // Line ranges past the end of the file are errors
+    parser.set_source_file(&format!("{dir}/chapter.md"));
+    assert!(parser.parse("{{#include code.rs:2:3}}\n", None).is_ok());
+    assert!(parser.parse("{{#include code.rs:2:10}}\n", None).is_err());
+    assert!(parser.parse("{{#include code.rs:5}}\n", None).is_err());
+}
+
+#[test]
+fn page_breaks() {
+    let doc = "It was night. {{#page 12}}Suddenly, {{#pages}} *a shot* {{#page iv}}";
+    let expected = vec![Token::Paragraph(vec![
+        Token::Str(String::from("It was night. ")),
+        Token::PageBreak(String::from("12")),
+        Token::Str(String::from("Suddenly, {{#pages}} ")),
+        Token::Emphasis(vec![Token::Str(String::from("a shot"))]),
+        Token::Str(String::from(" ")),
+        Token::PageBreak(String::from("iv")),
+    ])];
+    assert_eq!(parse_from_str(doc), expected);
+    round_trip(doc);
+}
+
+#[test]
+fn scene_breaks() {
+    let doc = "Before
+
+***
+
+* * *
+
+---
+
+\\*\\*\\*
+
+After
+";
+    let expected = vec![
+        Token::Paragraph(vec![Token::Str(String::from("Before"))]),
+        Token::SceneBreak,
+        Token::SceneBreak,
+        Token::Rule,
+        Token::SceneBreak,
+        Token::Paragraph(vec![Token::Str(String::from("After"))]),
+    ];
+    let mut book = Book::new();
+    book.set_options(&[("input.scene_breaks", "true")]);
+    assert_eq!(parse_with(&book, doc), expected);
+    round_trip_with(&book, doc);
+
+    // Without input.scene_breaks, they are plain rules
+    assert_eq!(parse_from_str("***\n")[0], Token::Rule);
+}
+
+#[test]
+fn scene_breaks_after_front_matter() {
+    let doc = "---
+title: Foo
+author: Bar
+---
+
+Before
+
+***
+
+---
+
+After
+";
+    let mut book = Book::new();
+    book.set_options(&[
+        ("input.scene_breaks", "true"),
+        ("input.yaml_blocks", "true"),
+    ]);
+    let expected = vec![
+        Token::Paragraph(vec![Token::Str(String::from("Before"))]),
+        Token::SceneBreak,
+        Token::Rule,
+        Token::Paragraph(vec![Token::Str(String::from("After"))]),
+    ];
+    assert_eq!(parse_with(&book, doc), expected);
+}
+
+#[test]
+fn attributes() {
+    let doc = "# Title {#intro .centered}
+
+A [span]{.smallcaps lang=fr} and a [link](foo.md){#l}. {.noindent}
+
+::: sidebar
+Some note
+:::
+
+::: {#box .aside only=epub}
+Content
+:::
+
+Not {attributes}
+";
+    let classes = |classes: &[&str]| Attributes {
+        classes: classes.iter().map(|c| c.to_string()).collect(),
+        ..Attributes::default()
+    };
+    let expected = vec![
+        Token::Header(
+            1,
+            vec![
+                Token::Attributes(Attributes {
+                    id: Some(String::from("intro")),
+                    ..classes(&["centered"])
+                }),
+                Token::Str(String::from("Title")),
+            ],
+        ),
+        Token::Paragraph(vec![
+            Token::Attributes(classes(&["noindent"])),
+            Token::Str(String::from("A ")),
+            Token::Span(
+                Attributes {
+                    pairs: vec![(String::from("lang"), String::from("fr"))],
+                    ..classes(&["smallcaps"])
+                },
+                vec![Token::Str(String::from("span"))],
+            ),
+            Token::Str(String::from(" and a ")),
+            Token::Link(
+                String::from("foo.md"),
+                String::new(),
+                vec![
+                    Token::Attributes(Attributes {
+                        id: Some(String::from("l")),
+                        ..Attributes::default()
+                    }),
+                    Token::Str(String::from("link")),
+                ],
+            ),
+            Token::Str(String::from(".")),
+        ]),
+        Token::Div(
+            classes(&["sidebar"]),
+            vec![Token::Paragraph(vec![Token::Str(String::from(
+                "Some note",
+            ))])],
+        ),
+        Token::Conditional(
+            vec![Condition::Only(vec![String::from("epub")])],
+            vec![Token::Div(
+                Attributes {
+                    id: Some(String::from("box")),
+                    ..classes(&["aside"])
+                },
+                vec![Token::Paragraph(vec![Token::Str(String::from("Content"))])],
+            )],
+        ),
+        Token::Paragraph(vec![Token::Str(String::from("Not {attributes}"))]),
+    ];
+    assert_eq!(parse_from_str(doc), expected);
+    // Conditions are evaluated when rendering to Markdown
+    round_trip(&doc.replace(" only=epub", ""));
+}
+
+#[test]
+fn callouts() {
+    let doc = "> [!NOTE]
+> Some *text*
+
+> [!tip] On the same line
+
+> [!FOO]
+> Not a callout
+
+::: {.warning #careful}
+Careful
+:::
+";
+    let expected = vec![
+        Token::Callout(
+            String::from("note"),
+            vec![Token::Paragraph(vec![
+                Token::Str(String::from("Some ")),
+                Token::Emphasis(vec![Token::Str(String::from("text"))]),
+            ])],
+        ),
+        Token::Callout(
+            String::from("tip"),
+            vec![Token::Paragraph(vec![Token::Str(String::from(
+                "On the same line",
+            ))])],
+        ),
+        Token::BlockQuote(vec![Token::Paragraph(vec![Token::Str(String::from(
+            "[!FOO] Not a callout",
+        ))])]),
+        Token::Callout(
+            String::from("warning"),
+            vec![
+                Token::Attributes(Attributes {
+                    id: Some(String::from("careful")),
+                    ..Attributes::default()
+                }),
+                Token::Paragraph(vec![Token::Str(String::from("Careful"))]),
+            ],
+        ),
+    ];
+    assert_eq!(parse_from_str(doc), expected);
+    round_trip(doc);
+}
+
+#[test]
+fn code_info() {
+    let info = CodeInfo::new("rust,linenos,hl_lines=\"1 3-5\",title=\"main file.rs\"");
+    assert_eq!(info.language, "rust");
+    assert!(info.line_numbers);
+    assert_eq!(info.first_line, 1);
+    assert_eq!(info.highlighted, vec![(1, 1), (3, 5)]);
+    assert_eq!(info.title.as_deref(), Some("main file.rs"));
+    assert!(info.is_highlighted(4));
+    assert!(!info.is_highlighted(2));
+
+    let info = CodeInfo::new("python linenostart=10 ignore");
+    assert_eq!(info.language, "python");
+    assert!(info.line_numbers);
+    assert_eq!(info.first_line, 10);
+    assert!(info.title.is_none());
+    assert!(!CodeInfo::new("").has_line_decorations());
+}
+
+#[test]
+fn source_lines() {
+    let doc = "# Title
+
+First paragraph
+on two lines
+
+::: {only=epub}
+Conditional
+:::
+
+Last
+";
+    let mut parser = Parser::new();
+    let tokens = parser.parse(doc, None).unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(parser.lines(), &[1, 3, 6, 10]);
+}
@@ -32,12 +32,14 @@ where
 
         Token::SoftBreak => f(" "),
 
-        Token::Rule | Token::HardBreak => f("\n"),
+        Token::Rule | Token::SceneBreak | Token::HardBreak => f("\n"),
 
         Token::Image(..)
         | Token::StandaloneImage(..)
         | Token::FootnoteDefinition(..)
         | Token::FootnoteReference(..)
+        | Token::PageBreak(..)
+        | Token::Attributes(..)
         | Token::Table(..)
         | Token::TableHead(..)
         | Token::TableRow(..)
@@ -103,7 +105,7 @@ pub fn insert_annotation(
                 false
             }
 
-            Token::Rule | Token::SoftBreak | Token::HardBreak => {
+            Token::Rule | Token::SceneBreak | Token::SoftBreak | Token::HardBreak => {
                 if pos < 1 {
                     if found_left.is_some() {
                         found_right = Some((i, pos));
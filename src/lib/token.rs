@@ -15,6 +15,8 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq)]
 /// The inner type for an annotation.
 ///
@@ -26,6 +28,111 @@ pub enum Data {
     Repetition(String),
 }
 
+/// A condition deciding whether a conditional block is rendered.
+///
+/// This Enum might grow additional variants, so library users should
+/// **not** rely on exhaustive matching.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Condition {
+    /// Only render for these output formats or groups of formats
+    /// (e.g. `epub`, `html` or `print`), set with `only=...`
+    Only(Vec<String>),
+    /// Only render if this flag is set in `rendering.flags` (or, if
+    /// the bool is false, only if it is *not* set), set with `if=...`
+    If(String, bool),
+}
+
+impl Condition {
+    /// Returns true if the condition holds when rendering to `format`
+    /// with the given list of flags.
+    ///
+    /// `format` is the kind of document a renderer produces, that is,
+    /// `html`, `epub`, `tex`, `manuscript`, `typst`, `odt` or `txt`.
+    pub fn holds(&self, format: &str, flags: &[String]) -> bool {
+        match *self {
+            Condition::Only(ref formats) => formats.iter().any(|f| format_matches(f, format)),
+            Condition::If(ref flag, expected) => flags.contains(flag) == expected,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    /// Formats the condition as it is written in a fenced block, e.g. `only=epub`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::Only(ref formats) => write!(f, "only={}", formats.join(",")),
+            Condition::If(ref flag, true) => write!(f, "if={flag}"),
+            Condition::If(ref flag, false) => write!(f, "if=!{flag}"),
+        }
+    }
+}
+
+/// Returns true if a format name, as written in a condition, designates `format`
+fn format_matches(name: &str, format: &str) -> bool {
+    name == format
+        || match name {
+            "print" => matches!(format, "tex" | "manuscript" | "typst" | "odt"),
+            "ebook" => format == "epub",
+            "pdf" => matches!(format, "tex" | "manuscript" | "typst"),
+            "latex" => matches!(format, "tex" | "manuscript"),
+            "text" => format == "txt",
+            _ => false,
+        }
+}
+
+/// Generic attributes of an element, written e.g. `{#intro .centered lang=fr}`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Attributes {
+    /// Identifier, usable as an anchor or in cross-references (`#intro`)
+    pub id: Option<String>,
+    /// Classes (`.centered`)
+    pub classes: Vec<String>,
+    /// Other key/value pairs (`lang=fr`)
+    pub pairs: Vec<(String, String)>,
+}
+
+impl Attributes {
+    /// Returns true if there is no id, no class and no key/value pair
+    pub fn is_empty(&self) -> bool {
+        self.id.is_none() && self.classes.is_empty() && self.pairs.is_empty()
+    }
+
+    /// Returns the value of a key, if it is set
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns true if the element has this class
+    pub fn has_class(&self, class: &str) -> bool {
+        self.classes.iter().any(|c| c == class)
+    }
+}
+
+impl fmt::Display for Attributes {
+    /// Formats the attributes as they are written in Markdown, e.g. `{#intro .centered}`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items = vec![];
+        if let Some(ref id) = self.id {
+            items.push(format!("#{id}"));
+        }
+        for class in &self.classes {
+            items.push(format!(".{class}"));
+        }
+        for (key, value) in &self.pairs {
+            if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '}') {
+                items.push(format!("{key}=\"{value}\""));
+            } else {
+                items.push(format!("{key}={value}"));
+            }
+        }
+        write!(f, "{{{}}}", items.join(" "))
+    }
+}
+
 /// A single token representing a Markdown element.
 ///
 /// A Markdown document is, thus, a Vec of `Token`s.
@@ -94,6 +201,8 @@ pub enum Token {
 
     /// Horizontal rule
     Rule,
+    /// Scene break, a `***` or `* * *` paragraph
+    SceneBreak,
     /// Softbreak, usually rendered by a space
     SoftBreak,
     /// Hardbreak
@@ -108,6 +217,22 @@ pub enum Token {
 
     /// An annotation inserted by crowbook for e.g. grammar checking
     Annotation(Data, Vec<Token>),
+
+    /// A block that is only rendered if all its conditions hold
+    Conditional(Vec<Condition>, Vec<Token>),
+
+    /// A page break of the print edition, with the number of the page starting here
+    PageBreak(String),
+
+    /// Attributes of the element (header, paragraph, link, image or callout) this token is
+    /// the first inner token of
+    Attributes(Attributes),
+    /// A fenced block with attributes (`::: {.note}`)
+    Div(Attributes, Vec<Token>),
+    /// A span of text with attributes (`[some text]{.smallcaps}`)
+    Span(Attributes, Vec<Token>),
+    /// A callout box, with its kind (`note`, `tip`, `important`, `warning` or `caution`)
+    Callout(String, Vec<Token>),
 }
 
 use Token::*;
@@ -117,12 +242,15 @@ impl Token {
     pub fn inner(&self) -> Option<&[Token]> {
         match *self {
             Rule
+            | SceneBreak
             | SoftBreak
             | HardBreak
             | Str(_)
             | CodeBlock(_, _)
             | Code(_)
-            | FootnoteReference(_) => None,
+            | FootnoteReference(_)
+            | PageBreak(_)
+            | Token::Attributes(_) => None,
 
             Paragraph(ref v)
             | Header(_, ref v)
@@ -148,7 +276,11 @@ impl Token {
             | StandaloneImage(_, _, ref v)
             | Strikethrough(ref v)
             | TaskItem(_, ref v)
-            | Annotation(_, ref v) => Some(v),
+            | Annotation(_, ref v)
+            | Conditional(_, ref v)
+            | Div(_, ref v)
+            | Span(_, ref v)
+            | Callout(_, ref v) => Some(v),
         }
     }
 
@@ -156,15 +288,22 @@ impl Token {
     pub fn inner_mut(&mut self) -> Option<&mut Vec<Token>> {
         match *self {
             Rule
+            | SceneBreak
             | SoftBreak
             | HardBreak
             | Str(_)
             | CodeBlock(_, _)
             | Code(_)
-            | FootnoteReference(_) => None,
+            | FootnoteReference(_)
+            | PageBreak(_)
+            | Token::Attributes(_) => None,
 
             Paragraph(ref mut v)
             | Annotation(_, ref mut v)
+            | Conditional(_, ref mut v)
+            | Div(_, ref mut v)
+            | Span(_, ref mut v)
+            | Callout(_, ref mut v)
             | Header(_, ref mut v)
             | Emphasis(ref mut v)
             | Strong(ref mut v)
@@ -232,8 +371,42 @@ impl Token {
                 | Token::FootnoteDefinition(..)
                 | Token::TableCell(..)
                 | Token::Annotation(..)
+                | Token::Conditional(..)
+                | Token::Div(..)
+                | Token::Span(..)
+                | Token::Callout(..)
                 | Token::Item(..)
                 | Token::BlockQuote(..)
         )
     }
+
+    /// Returns the generic attributes of this token, if it has some
+    ///
+    /// Those of headers, paragraphs, links, images and callouts are stored as the first of
+    /// their inner tokens.
+    pub fn attributes(&self) -> Option<&Attributes> {
+        match *self {
+            Div(ref attributes, _) | Span(ref attributes, _) => Some(attributes),
+            Header(..) | Paragraph(..) | Link(..) | Image(..) | StandaloneImage(..)
+            | Callout(..) => match self.inner()?.first()? {
+                Token::Attributes(ref attributes) => Some(attributes),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Returns the ids set in a list of tokens (e.g. `{#intro}`), for cross-references
+pub fn collect_ids(tokens: &[Token]) -> Vec<String> {
+    let mut ids = vec![];
+    for token in tokens {
+        if let Some(id) = token.attributes().and_then(|a| a.id.as_ref()) {
+            ids.push(id.clone());
+        }
+        if let Some(inner) = token.inner() {
+            ids.extend(collect_ids(inner));
+        }
+    }
+    ids
 }
@@ -0,0 +1,464 @@
+// Copyright (C) 2016-2020 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::book::Book;
+use crate::book_renderer::BookRenderer;
+use crate::error::{Error, Result};
+use crate::lang;
+use crate::number::Counter;
+use crate::parser::Parser;
+use crate::renderer::Renderer;
+use crate::token::Token;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+
+/// How footnotes are displayed in plain text
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Footnotes {
+    /// Footnotes are displayed at the end of each chapter
+    End,
+    /// Footnotes are displayed inline, between brackets
+    Inline,
+    /// Footnotes are not displayed
+    None,
+}
+
+/// Plain text renderer
+///
+/// Renders the book as plain text, wrapped to `txt.width` columns.
+pub struct TxtRenderer<'a> {
+    book: &'a Book,
+    width: usize,
+    footnotes: Footnotes,
+    /// Content of footnotes definitions of current chapter, indexed by name
+    footnote_defs: HashMap<String, Vec<Token>>,
+    /// Number of footnotes, in order of references
+    footnote_numbers: HashMap<String, usize>,
+    /// Footnotes that must be displayed at the end of the chapter
+    end_notes: Vec<(usize, String)>,
+    /// Current indentation, e.g. in lists or quotes
+    indent: usize,
+}
+
+impl<'a> TxtRenderer<'a> {
+    /// Creates a new plain text renderer
+    pub fn new(book: &'a Book) -> Result<TxtRenderer<'a>> {
+        let footnotes = match book.options.get_str("txt.footnotes").unwrap() {
+            "end" => Footnotes::End,
+            "inline" => Footnotes::Inline,
+            "none" => Footnotes::None,
+            value => {
+                return Err(Error::book_option(
+                    &book.source,
+                    lformat!(
+                    "txt.footnotes set to '{value}', valid values are 'end', 'inline' or 'none'",
+                    value = value
+                ),
+                ))
+            }
+        };
+        let width = book.options.get_i32("txt.width").unwrap();
+        Ok(TxtRenderer {
+            book,
+            width: if width > 0 { width as usize } else { 0 },
+            footnotes,
+            footnote_defs: HashMap::new(),
+            footnote_numbers: HashMap::new(),
+            end_notes: vec![],
+            indent: 0,
+        })
+    }
+
+    /// Renders the whole book as plain text
+    pub fn render_book(&mut self) -> Result<String> {
+        let mut content = String::new();
+
+        if let Ok(title) = self.book.options.get_str("title") {
+            let title = self.render_metadata(title)?;
+            content.push_str(&self.underline(&title, '='));
+            if let Ok(subtitle) = self.book.options.get_str("subtitle") {
+                writeln!(content, "{}", self.render_metadata(subtitle)?)?;
+            }
+            if let Ok(author) = self.book.options.get_str("author") {
+                writeln!(content, "\n{}", self.render_metadata(author)?)?;
+            }
+            content.push_str("\n\n");
+        }
+
+        let mut counter = Counter::new(
+            self.book
+                .options
+                .get_bool("rendering.part.reset_counter")
+                .unwrap(),
+        );
+        let numbering = self.book.options.get_i32("rendering.num_depth").unwrap() >= 1;
+        for chapter in &self.book.chapters {
+            self.collect_footnotes(&chapter.content);
+
+            let mut offset = 0;
+            if let Some(Token::Header(1, ref title)) = chapter.content.first() {
+                offset = 1;
+                let number = counter.next(chapter.number);
+                if !chapter.number.is_hidden() {
+                    let title = self.render_vec(title)?;
+                    let title = match number {
+                        Some((header_type, n)) if numbering => {
+                            self.book
+                                .get_header(header_type, n, title, |s| Ok(s.to_owned()))?
+                                .text
+                        }
+                        _ => title,
+                    };
+                    let c = if chapter.number.is_part() { '#' } else { '=' };
+                    content.push_str(&self.underline(&title, c));
+                    content.push('\n');
+                }
+            }
+            content.push_str(&self.render_vec(&chapter.content[offset..])?);
+
+            if !self.end_notes.is_empty() {
+                content.push_str(&"-".repeat(self.rule_width() / 3));
+                content.push_str("\n\n");
+                for (number, note) in std::mem::take(&mut self.end_notes) {
+                    let prefix = format!("[{number}] ");
+                    content.push_str(&indent_lines(&note, &prefix));
+                    content.push_str("\n\n");
+                }
+            }
+            content.push('\n');
+        }
+        Ok(format!("{}\n", content.trim_end()))
+    }
+
+    /// Renders a metadata field (which is Markdown) as text
+    fn render_metadata(&mut self, s: &str) -> Result<String> {
+        let tokens = Parser::from(self.book).parse_inline(s)?;
+        self.render_vec(&tokens)
+    }
+
+    /// Stores the footnote definitions of a chapter, so they can be displayed inline
+    /// or numbered in order of appearance
+    fn collect_footnotes(&mut self, tokens: &[Token]) {
+        self.footnote_defs.clear();
+        self.footnote_numbers.clear();
+        for token in tokens {
+            if let Token::FootnoteDefinition(ref name, ref v) = *token {
+                self.footnote_defs.insert(name.clone(), v.clone());
+            }
+        }
+    }
+
+    /// Width used for rules and separators
+    fn rule_width(&self) -> usize {
+        if self.width == 0 {
+            72
+        } else {
+            self.width
+        }
+    }
+
+    /// Renders a title followed by a line of `c`
+    fn underline(&self, title: &str, c: char) -> String {
+        let title = title.trim();
+        let len = title.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+        format!("{title}\n{}\n\n", c.to_string().repeat(len))
+    }
+
+    /// Wraps an inline text to the current width
+    fn wrap(&self, text: &str) -> String {
+        let width = if self.width == 0 {
+            0
+        } else {
+            std::cmp::max(self.width.saturating_sub(self.indent), 20)
+        };
+        text.trim()
+            .lines()
+            .map(|line| wrap_line(line.trim(), width))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Renders a list of blocks with an increased indentation, then prefix them
+    fn render_indented(&mut self, tokens: &[Token], prefix: &str) -> Result<String> {
+        let width = prefix.chars().count();
+        self.indent += width;
+        let content = self.render_blocks(tokens);
+        self.indent -= width;
+        Ok(indent_lines(content?.trim_end(), prefix))
+    }
+
+    /// Renders a list of tokens, grouping inline tokens in paragraphs
+    fn render_blocks(&mut self, tokens: &[Token]) -> Result<String> {
+        if tokens.iter().all(is_inline) {
+            let text = self.render_vec(tokens)?;
+            return Ok(format!("{}\n\n", self.wrap(&text)));
+        }
+        self.render_vec(tokens)
+    }
+
+    /// Renders a (possibly ordered) list
+    fn render_list(&mut self, items: &[Token], start: Option<usize>) -> Result<String> {
+        let mut content = String::new();
+        for (i, item) in items.iter().enumerate() {
+            let marker = match start {
+                Some(n) => format!("{}. ", n + i),
+                None => String::from("- "),
+            };
+            let inner = match *item {
+                Token::Item(ref v) => v.as_slice(),
+                ref token => std::slice::from_ref(token),
+            };
+            content.push_str(&self.render_indented(inner, &marker)?);
+            content.push('\n');
+        }
+        content.push('\n');
+        Ok(content)
+    }
+}
+
+impl<'a> Renderer for TxtRenderer<'a> {
+    fn render_token(&mut self, token: &Token) -> Result<String> {
+        match *token {
+            Token::Str(ref text) => Ok(self.book.clean(text.as_str()).into_owned()),
+            Token::Paragraph(ref vec) => {
+                let text = self.render_vec(vec)?;
+                Ok(format!("{}\n\n", self.wrap(&text)))
+            }
+            Token::Header(n, ref vec) => {
+                let title = self.render_vec(vec)?.replace('\n', " ");
+                Ok(match n {
+                    1 => self.underline(&title, '='),
+                    2 => self.underline(&title, '-'),
+                    _ => format!("{}\n\n", title.trim()),
+                })
+            }
+            Token::Emphasis(ref vec) => Ok(format!("_{}_", self.render_vec(vec)?)),
+            Token::Strong(ref vec) => Ok(format!("*{}*", self.render_vec(vec)?)),
+            Token::Strikethrough(ref vec)
+            | Token::Superscript(ref vec)
+            | Token::Subscript(ref vec)
+            | Token::Annotation(_, ref vec)
+            | Token::TableCell(ref vec) => self.render_vec(vec),
+            Token::Code(ref s) => Ok(s.clone()),
+            Token::CodeBlock(_, ref code) => {
+                Ok(format!("{}\n\n", indent_lines(code.trim_end(), "    ")))
+            }
+            Token::BlockQuote(ref vec) => Ok(format!("{}\n\n", self.render_indented(vec, "    ")?)),
+            Token::Rule => {
+                let width = self.rule_width().saturating_sub(self.indent);
+                Ok(format!("{:^width$}\n\n", "* * *"))
+            }
+            Token::SceneBreak => {
+                let width = self.rule_width().saturating_sub(self.indent);
+                let text = self.book.options.get_str("rendering.scene_break").unwrap();
+                Ok(format!("{:^width$}\n\n", text.trim()))
+            }
+            Token::PageBreak(_) => Ok(String::new()),
+            Token::SoftBreak => Ok(String::from(" ")),
+            Token::HardBreak => Ok(String::from("\n")),
+            Token::List(ref vec) => self.render_list(vec, None),
+            Token::OrderedList(n, ref vec) => self.render_list(vec, Some(n)),
+            Token::Item(_) => self.render_list(std::slice::from_ref(token), None),
+            Token::TaskItem(checked, ref vec) => Ok(format!(
+                "{}{}",
+                if checked { "[x] " } else { "[ ] " },
+                self.render_vec(vec)?
+            )),
+            Token::Conditional(ref conditions, ref vec) => {
+                if self.book.is_rendered(conditions, "txt") {
+                    self.render_blocks(vec)
+                } else {
+                    Ok(String::new())
+                }
+            }
+            Token::Div(_, ref vec) => self.render_blocks(vec),
+            Token::Callout(ref kind, ref vec) => {
+                let title = lang::get_str(self.book.options.get_str("lang").unwrap(), kind);
+                let content = self.render_indented(vec, "    ")?;
+                Ok(format!("{}:\n{content}\n\n", title.to_uppercase()))
+            }
+            Token::Span(_, ref vec) => self.render_vec(vec),
+            Token::Attributes(_) => Ok(String::new()),
+            Token::DescriptionList(ref vec) | Token::DescriptionItem(ref vec) => {
+                self.render_vec(vec)
+            }
+            Token::DescriptionTerm(ref vec) => {
+                let text = self.render_vec(vec)?;
+                Ok(format!("{}\n", self.wrap(&text)))
+            }
+            Token::DescriptionDetails(ref vec) => {
+                Ok(format!("{}\n\n", self.render_indented(vec, "    ")?))
+            }
+            Token::Table(_, ref vec) => Ok(format!("{}\n", self.render_vec(vec)?)),
+            Token::TableHead(ref vec) | Token::TableRow(ref vec) => {
+                let cells = vec
+                    .iter()
+                    .map(|cell| self.render_token(cell))
+                    .collect::<Result<Vec<_>>>()?;
+                let row = cells.join(" | ");
+                if let Token::TableHead(_) = *token {
+                    let len = row.chars().count();
+                    Ok(format!("{row}\n{}\n", "-".repeat(len)))
+                } else {
+                    Ok(format!("{row}\n"))
+                }
+            }
+            Token::FootnoteReference(ref name) => {
+                let new = !self.footnote_numbers.contains_key(name);
+                let n = self.footnote_numbers.len() + 1;
+                let n = *self.footnote_numbers.entry(name.clone()).or_insert(n);
+                let content = match self.footnote_defs.get(name) {
+                    Some(v) => {
+                        let v = v.clone();
+                        let indent = self.indent;
+                        self.indent = 0;
+                        let content = self.render_blocks(&v);
+                        self.indent = indent;
+                        content?
+                    }
+                    None => String::new(),
+                };
+                match self.footnotes {
+                    Footnotes::None => Ok(String::new()),
+                    Footnotes::Inline => Ok(format!(
+                        " [{}]",
+                        content.split_whitespace().collect::<Vec<_>>().join(" ")
+                    )),
+                    Footnotes::End => {
+                        if new {
+                            self.end_notes.push((n, content.trim_end().to_owned()));
+                        }
+                        Ok(format!("[{n}]"))
+                    }
+                }
+            }
+            // Definitions are displayed either inline or at the end of the chapter
+            Token::FootnoteDefinition(..) => Ok(String::new()),
+            Token::Link(ref url, _, ref vec) => {
+                let content = self.render_vec(vec)?;
+                if &content == url || url.starts_with('#') || !url.contains(':') {
+                    Ok(content)
+                } else {
+                    Ok(format!("{content} <{url}>"))
+                }
+            }
+            Token::Image(_, _, ref vec) | Token::StandaloneImage(_, _, ref vec) => {
+                let alt = self.render_vec(vec)?;
+                let name = lang::get_str(self.book.options.get_str("lang").unwrap(), "image");
+                let image = if alt.is_empty() {
+                    format!("[{name}]")
+                } else {
+                    format!("[{name}: {alt}]")
+                };
+                if let Token::StandaloneImage(..) = *token {
+                    Ok(format!("{image}\n\n"))
+                } else {
+                    Ok(image)
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if a token is an inline element
+fn is_inline(token: &Token) -> bool {
+    matches!(
+        *token,
+        Token::Str(..)
+            | Token::Emphasis(..)
+            | Token::Strong(..)
+            | Token::Strikethrough(..)
+            | Token::Code(..)
+            | Token::Superscript(..)
+            | Token::Subscript(..)
+            | Token::SoftBreak
+            | Token::HardBreak
+            | Token::PageBreak(..)
+            | Token::Link(..)
+            | Token::Image(..)
+            | Token::FootnoteReference(..)
+            | Token::Annotation(..)
+            | Token::TaskItem(..)
+            | Token::Attributes(..)
+            | Token::Span(..)
+    )
+}
+
+/// Wraps a single line to `width` columns (no wrapping if `width` is 0)
+fn wrap_line(line: &str, width: usize) -> String {
+    if width == 0 {
+        return line.to_owned();
+    }
+    let mut res = String::with_capacity(line.len());
+    let mut current = 0;
+    // Only split on normal spaces, so non-breaking spaces are kept
+    for word in line.split(' ').filter(|w| !w.is_empty()) {
+        let len = word.chars().count();
+        if current > 0 && current + 1 + len > width {
+            res.push('\n');
+            current = 0;
+        } else if current > 0 {
+            res.push(' ');
+            current += 1;
+        }
+        res.push_str(word);
+        current += len;
+    }
+    res
+}
+
+/// Prefixes the first line of `content` with `prefix`, and indent the other ones
+/// with as many spaces.
+fn indent_lines(content: &str, prefix: &str) -> String {
+    let indent = " ".repeat(prefix.chars().count());
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                format!("{prefix}{line}")
+            } else if line.is_empty() {
+                String::new()
+            } else {
+                format!("{indent}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub struct Txt;
+
+impl BookRenderer for Txt {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.txt"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
+        let mut txt = TxtRenderer::new(book)?;
+        let result = txt.render_book()?;
+        to.write_all(result.as_bytes()).map_err(|e| {
+            Error::render(
+                &book.source,
+                lformat!("problem when writing text: {error}", error = e),
+            )
+        })?;
+        Ok(())
+    }
+}
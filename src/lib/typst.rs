@@ -0,0 +1,599 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rendering of a book to Typst markup, and to PDF with the `typst` compiler
+
+use crate::book::{compile_str, Book, Header};
+use crate::book_renderer::BookRenderer;
+use crate::error::{Error, Result, Source};
+use crate::lang;
+use crate::number::Number;
+use crate::parser::Parser;
+use crate::renderer::Renderer;
+use crate::resource_handler::ResourceHandler;
+use crate::syntax::CodeInfo;
+use crate::text_view::view_as_text;
+use crate::token::Attributes;
+use crate::token::Token;
+use crate::zipper::Zipper;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+/// Typst renderer
+pub struct TypstRenderer<'a> {
+    book: &'a Book,
+    handler: ResourceHandler,
+    source: Source,
+    /// Current number of parts, chapters, sections, ...
+    current_chapter: [i32; 7],
+    /// Depth up to which headers of the current chapter are numbered
+    current_numbering: i32,
+    current_part: bool,
+    current_appendix: bool,
+    current_hide: bool,
+    /// Index of the current chapter, used for labels
+    current_index: usize,
+    /// Rendered footnote definitions of the current chapter
+    footnotes: HashMap<String, String>,
+    /// Footnotes of the current chapter that were already displayed
+    displayed_footnotes: Vec<String>,
+    highlight: bool,
+}
+
+impl<'a> TypstRenderer<'a> {
+    /// Creates a new TypstRenderer
+    pub fn new(book: &'a Book) -> TypstRenderer<'a> {
+        TypstRenderer {
+            book,
+            handler: ResourceHandler::new(),
+            source: Source::empty(),
+            current_chapter: [0, 0, 0, 0, 0, 0, 0],
+            current_numbering: book.options.get_i32("rendering.num_depth").unwrap(),
+            current_part: false,
+            current_appendix: false,
+            current_hide: false,
+            current_index: 0,
+            footnotes: HashMap::new(),
+            displayed_footnotes: vec![],
+            highlight: book.options.get_str("rendering.highlight").unwrap() != "none",
+        }
+    }
+
+    /// Renders the book to PDF, using the `typst` compiler
+    pub fn render_pdf(&mut self, to: &mut dyn io::Write) -> Result<String> {
+        // Images are copied next to the generated file, in the temporary directory
+        self.handler.set_images_mapping(true);
+        let content = self.render_book()?;
+        debug!("{}", lformat!("Attempting to run Typst on generated file"));
+        let mut zipper = Zipper::new(&self.book.options.get_path("crowbook.temp_dir").unwrap())?;
+        zipper.write("result.typ", content.as_bytes(), false)?;
+
+        for (source, dest) in self.handler.images_mapping() {
+            let content = fs::canonicalize(source).and_then(fs::read).map_err(|_| {
+                Error::file_not_found(&self.source, lformat!("image"), source.to_owned())
+            })?;
+            zipper.write(dest, &content, false)?;
+        }
+
+        let command_name = self.book.options.get_str("typst.command").unwrap();
+        let mut command = Command::new(command_name);
+        command
+            .current_dir(zipper.path())
+            .arg("compile")
+            .arg("result.typ")
+            .arg("result.pdf");
+        zipper.run_command(command, command_name, "result.pdf", to)
+    }
+
+    /// Renders the book to Typst markup
+    pub fn render_book(&mut self) -> Result<String> {
+        let mut content = String::new();
+
+        for (i, chapter) in self.book.chapters.iter().enumerate() {
+            self.handler
+                .add_link(chapter.filename.as_str(), format!("chapter-{i}"));
+            self.handler
+                .add_anchors(&chapter.filename, &chapter.content, |id| id.to_owned());
+        }
+
+        for (i, chapter) in self.book.chapters.iter().enumerate() {
+            self.chapter_config(i, chapter.number);
+            let v = &chapter.content;
+            self.source = Source::new(chapter.filename.as_str());
+
+            // Footnotes are displayed where they are referenced, so render their content first
+            self.footnotes.clear();
+            self.displayed_footnotes.clear();
+            for token in v {
+                if let Token::FootnoteDefinition(ref reference, ref vec) = *token {
+                    let footnote = self.render_vec(vec)?;
+                    self.footnotes
+                        .insert(reference.clone(), footnote.trim_end().to_owned());
+                }
+            }
+
+            let mut offset = 0;
+            if !v.is_empty() && v[0].is_header() {
+                content.push_str(&self.render_token(&v[0])?);
+                offset = 1;
+            }
+            writeln!(content, "#metadata(none) <chapter-{i}>")?;
+            for key in &["subtitle", "author", "date", "epigraph"] {
+                if let Some(value) = chapter.metadata.get(*key) {
+                    let value = self.render_vec(&Parser::from(self.book).parse_inline(value)?)?;
+                    writeln!(content, "#mdchapter{key}[{value}]")?;
+                }
+            }
+            for token in v.iter().skip(offset) {
+                content.push_str(&self.render_token(token)?);
+            }
+        }
+        self.source = Source::empty();
+
+        let template = compile_str(
+            self.book.get_template("typst.template")?.as_ref(),
+            &self.book.source,
+            "typst.template",
+        )?;
+
+        // Typst wants a language code and, optionally, a region code
+        let lang = self.book.options.get_str("lang").unwrap();
+        let mut parts = lang.split(['-', '_']);
+        let typst_lang = parts.next().unwrap_or("en").to_lowercase();
+        let typst_region = parts.next().map(|r| r.to_lowercase());
+
+        // Document metadata only accepts strings for authors
+        let author = match self.book.options.get_str("author") {
+            Ok(author) => view_as_text(&Parser::from(self.book).parse_inline(author)?),
+            Err(_) => String::new(),
+        };
+
+        let mut data = self
+            .book
+            .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
+            .insert_str("content", content)
+            .insert_str("author_string", string(&author))
+            .insert_str("typst_lang", typst_lang)
+            .insert_str(
+                "paper",
+                self.book.options.get_str("typst.paper.size").unwrap(),
+            )
+            .insert_bool(
+                "typst_title",
+                self.book.options.get_bool("typst.title").unwrap(),
+            )
+            .insert_bool(
+                "inline_toc",
+                self.book.options.get_bool("rendering.inline_toc").unwrap(),
+            );
+        if let Some(region) = typst_region {
+            data = data
+                .insert_bool("has_typst_region", true)
+                .insert_str("typst_region", region);
+        }
+        if let Ok(font_size) = self.book.options.get_i32("typst.font.size") {
+            data = data
+                .insert_bool("has_font_size", true)
+                .insert_str("font_size", format!("{font_size}"));
+        }
+        if let Ok(additional_code) = self.book.options.get_str("typst.template.add") {
+            data = data.insert_str("additional_code", additional_code);
+        }
+        let data = data.build();
+        let mut res: Vec<u8> = vec![];
+        template.render_data(&mut res, &data)?;
+        match String::from_utf8(res) {
+            Err(_) => panic!("{}", lformat!("generated Typst was not valid utf-8")),
+            Ok(res) => Ok(res),
+        }
+    }
+
+    /// Configures the renderer for chapter `i`
+    fn chapter_config(&mut self, i: usize, n: Number) {
+        let book_numbering = self.book.options.get_i32("rendering.num_depth").unwrap();
+        self.current_hide = false;
+        match n {
+            Number::Unnumbered | Number::UnnumberedPart => self.current_numbering = 0,
+            Number::Default | Number::DefaultPart | Number::DefaultAppendix => {
+                self.current_numbering = book_numbering
+            }
+            Number::Specified(n) | Number::SpecifiedAppendix(n) => {
+                self.current_numbering = book_numbering;
+                self.current_chapter[1] = n - 1;
+            }
+            Number::SpecifiedPart(n) => {
+                self.current_numbering = book_numbering;
+                self.current_chapter[0] = n - 1;
+            }
+            Number::Hidden => {
+                self.current_numbering = 0;
+                self.current_hide = true;
+            }
+        }
+        self.current_part = n.is_part();
+        self.current_appendix = n.is_appendix();
+        self.current_index = i;
+    }
+
+    /// Renders the text of a header, with its number, after increasing the counters
+    fn render_title(&mut self, n: i32, vec: &[Token]) -> Result<String> {
+        if self.current_numbering >= n {
+            let i = n as usize;
+            self.current_chapter[i] += 1;
+            let reset_parts = self
+                .book
+                .options
+                .get_bool("rendering.part.reset_counter")
+                .unwrap();
+            let begin = if i == 0 && !reset_parts { 2 } else { i + 1 };
+            for counter in self.current_chapter.iter_mut().skip(begin) {
+                *counter = 0;
+            }
+        }
+        let title = self.render_vec(vec)?;
+        if self.current_numbering < n {
+            return Ok(title);
+        }
+        if n <= 1 {
+            let header = if n == 0 {
+                Header::Part
+            } else if self.current_appendix {
+                Header::Appendix
+            } else {
+                Header::Chapter
+            };
+            let number = self.current_chapter[n as usize];
+            let book = self.book;
+            let data = book.get_header(header, number, title, |s| {
+                self.render_vec(&Parser::from(book).parse_inline(s)?)
+            })?;
+            Ok(data.text)
+        } else {
+            let header = if self.current_appendix {
+                Header::Appendix
+            } else {
+                Header::Chapter
+            };
+            let chapter = self.current_chapter[1];
+            let mut numbers = if chapter > 0 {
+                self.book.get_header_number(header, chapter)?
+            } else {
+                format!("{chapter}")
+            };
+            for number in &self.current_chapter[2..=n as usize] {
+                write!(numbers, ".{number}")?;
+            }
+            Ok(format!("{numbers} {title}"))
+        }
+    }
+
+    /// Renders a footnote reference, displaying the footnote the first time it is referenced
+    fn render_footnote(&mut self, reference: &str) -> String {
+        let label = format!(
+            "footnote-{}-{}",
+            self.current_index,
+            reference
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                .collect::<String>()
+        );
+        if self.displayed_footnotes.iter().any(|r| r == reference) {
+            format!("#footnote(<{label}>);")
+        } else {
+            self.displayed_footnotes.push(reference.to_owned());
+            let content = self.footnotes.get(reference).cloned().unwrap_or_else(|| {
+                warn!(
+                    "{}",
+                    lformat!(
+                        "Typst ({source}): could not find footnote '{reference}'",
+                        source = self.source,
+                        reference = reference
+                    )
+                );
+                String::new()
+            });
+            format!("#footnote[{content}] <{label}>")
+        }
+    }
+
+    /// Renders an image, or returns `None` if it isn't local
+    fn render_image(&mut self, url: &str, alt: &[Token]) -> Result<Option<String>> {
+        if ResourceHandler::is_local(url) {
+            let alt = string(&view_as_text(alt));
+            let path = string(&self.handler.map_image(&self.source, url)?);
+            Ok(Some(format!("image({path}, alt: {alt})")))
+        } else {
+            debug!(
+                "{}",
+                lformat!(
+                    "Typst ({source}): image '{url}' doesn't seem to be local; ignoring it.",
+                    source = self.source,
+                    url = url
+                )
+            );
+            Ok(None)
+        }
+    }
+
+    /// Renders tokens as the content arguments of a Typst function, e.g. list items
+    fn render_args(&mut self, vec: &[Token]) -> Result<String> {
+        let args = vec
+            .iter()
+            .map(|token| self.render_token(token))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(args
+            .into_iter()
+            .filter(|arg| !arg.is_empty())
+            .collect::<Vec<_>>()
+            .join(",\n"))
+    }
+}
+
+impl<'a> Renderer for TypstRenderer<'a> {
+    fn render_token(&mut self, token: &Token) -> Result<String> {
+        match *token {
+            Token::Str(ref text) => Ok(escape(&self.book.clean(text.as_str()))),
+            Token::Paragraph(ref vec) => Ok(format!(
+                "{}{}\n\n",
+                anchor(token.attributes()),
+                self.render_vec(vec)?
+            )),
+            Token::Header(n, ref vec) => {
+                let n = if self.current_part { n - 1 } else { n };
+                if n <= 1 && self.current_hide {
+                    return Ok(String::from("#pagebreak(weak: true)\n"));
+                }
+                let title = self.render_title(n, vec)?;
+                let anchor = anchor(token.attributes());
+                match n {
+                    0 => Ok(format!("#mdpart[{title}]{anchor}\n")),
+                    1 => Ok(format!("#mdchapter[{title}]{anchor}\n")),
+                    _ => Ok(format!("#heading(level: {n})[{title}]{anchor}\n")),
+                }
+            }
+            Token::TaskItem(checked, ref vec) => Ok(format!(
+                "{} {}",
+                if checked { "☒" } else { "☐" },
+                self.render_vec(vec)?
+            )),
+            Token::Emphasis(ref vec) => Ok(format!("#emph[{}];", self.render_vec(vec)?)),
+            Token::Strong(ref vec) => Ok(format!("#strong[{}];", self.render_vec(vec)?)),
+            Token::Strikethrough(ref vec) => Ok(format!("#strike[{}];", self.render_vec(vec)?)),
+            Token::Code(ref s) => Ok(format!("#raw({});", string(s))),
+            Token::Superscript(ref vec) => Ok(format!("#super[{}];", self.render_vec(vec)?)),
+            Token::Subscript(ref vec) => Ok(format!("#sub[{}];", self.render_vec(vec)?)),
+            Token::BlockQuote(ref vec) => {
+                Ok(format!("#mdblockquote[{}]\n\n", self.render_vec(vec)?))
+            }
+            Token::Callout(ref kind, ref vec) => {
+                let title = lang::get_str(self.book.options.get_str("lang").unwrap(), kind);
+                Ok(format!(
+                    "{}#mdcallout({}, [{}])[{}]\n\n",
+                    anchor(token.attributes()),
+                    string(kind),
+                    escape(&title),
+                    self.render_vec(vec)?
+                ))
+            }
+            Token::CodeBlock(ref info, ref code) => {
+                let info = CodeInfo::new(info);
+                // Typst highlights raw blocks with a language itself
+                let lang = if self.highlight && !info.language.is_empty() {
+                    format!(", lang: {}", string(&info.language))
+                } else {
+                    String::new()
+                };
+                let mut args = String::new();
+                if let Some(ref title) = info.title {
+                    write!(args, ", title: [{}]", escape(title))?;
+                }
+                if let Some(ref caption) = info.caption {
+                    write!(args, ", caption: [{}]", escape(caption))?;
+                }
+                Ok(format!(
+                    "#mdcodeblock(raw(block: true{lang}, {}){args})\n\n",
+                    string(code.trim_end_matches('\n'))
+                ))
+            }
+            Token::Rule => Ok(String::from("#mdrule()\n\n")),
+            Token::SceneBreak => {
+                let options = &self.book.options;
+                let ornament = match options.get_path("rendering.scene_break.image") {
+                    Ok(image) => format!(
+                        "image({}, height: 1.5em)",
+                        string(&self.handler.map_image(&self.source, image)?)
+                    ),
+                    Err(_) => format!(
+                        "[{}]",
+                        escape(options.get_str("rendering.scene_break").unwrap())
+                    ),
+                };
+                Ok(format!("#mdscenebreak({ornament})\n\n"))
+            }
+            Token::PageBreak(_) => Ok(String::new()),
+            Token::SoftBreak => Ok(String::from(" ")),
+            Token::HardBreak => Ok(String::from("\\\n")),
+            Token::DescriptionList(ref v) => Ok(format!("#terms(\n{})\n\n", self.render_args(v)?)),
+            Token::DescriptionItem(ref v) => Ok(format!("terms.item(\n{})", self.render_args(v)?)),
+            Token::DescriptionTerm(ref v) | Token::DescriptionDetails(ref v) => {
+                Ok(format!("[{}]", self.render_vec(v)?))
+            }
+            Token::List(ref vec) => Ok(format!("#list(\n{})\n\n", self.render_args(vec)?)),
+            Token::OrderedList(n, ref vec) => Ok(format!(
+                "#enum(start: {n},\n{})\n\n",
+                self.render_args(vec)?
+            )),
+            Token::Item(ref vec) => Ok(format!("[{}]", self.render_vec(vec)?)),
+            Token::Link(ref url, _, ref vec) => {
+                let content = self.render_vec(vec)?;
+                let anchor = anchor(token.attributes());
+                if self.handler.contains_link(url) {
+                    Ok(format!(
+                        "{anchor}#link(<{}>)[{content}];",
+                        self.handler.get_link(url)
+                    ))
+                } else {
+                    Ok(format!("{anchor}#link({})[{content}];", string(url)))
+                }
+            }
+            Token::StandaloneImage(ref url, _, ref alt) => Ok(self
+                .render_image(url, alt)?
+                .map(|image| format!("#mdstandaloneimage({image})\n\n"))
+                .unwrap_or_default()),
+            Token::Image(ref url, _, ref alt) => Ok(self
+                .render_image(url, alt)?
+                .map(|image| format!("#mdimage({image});"))
+                .unwrap_or_default()),
+            Token::FootnoteReference(ref reference) => Ok(self.render_footnote(reference)),
+            Token::FootnoteDefinition(..) => Ok(String::new()),
+            Token::Table(n, ref vec) => Ok(format!(
+                "#mdtable(columns: {n},\n{})\n\n",
+                self.render_args(vec)?
+            )),
+            Token::TableHead(ref vec) => Ok(format!("table.header({})", self.render_args(vec)?)),
+            Token::TableRow(ref vec) => self.render_args(vec),
+            Token::TableCell(ref vec) => Ok(format!("[{}]", self.render_vec(vec)?)),
+            Token::Annotation(_, ref vec) => self.render_vec(vec),
+            Token::Attributes(_) => Ok(String::new()),
+            Token::Div(ref attributes, ref vec) => Ok(format!(
+                "{}\n{}",
+                anchor(Some(attributes)),
+                self.render_vec(vec)?
+            )),
+            Token::Span(ref attributes, ref vec) => Ok(format!(
+                "{}{}",
+                anchor(Some(attributes)),
+                self.render_vec(vec)?
+            )),
+            Token::Conditional(ref conditions, ref vec) => {
+                if self.book.is_rendered(conditions, "typst") {
+                    self.render_vec(vec)
+                } else {
+                    Ok(String::new())
+                }
+            }
+        }
+    }
+}
+
+/// Returns a label for the id of an element with attributes, the target of cross-references
+fn anchor(attributes: Option<&Attributes>) -> String {
+    match attributes.and_then(|a| a.id.as_ref()) {
+        Some(id) => format!("#metadata(none) <{id}>"),
+        None => String::new(),
+    }
+}
+
+/// Escapes characters that have a meaning in Typst markup
+///
+/// Some characters are always escaped, others only where they are significant: `=`, `+`,
+/// `-` and `/` at the beginning of a line (headings and lists), the dot of a number at the
+/// beginning of a line (numbered lists), `--` and `-?` (dashes and soft hyphens), and `//`
+/// and `/*` (comments).
+pub fn escape(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    // Whether there is only whitespace between the beginning of the line and this character
+    let mut line_start = true;
+    // Whether there is only a number between the beginning of the line and this character
+    let mut number = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        let special = match c {
+            '\\' | '#' | '*' | '_' | '`' | '$' | '<' | '@' | '[' | ']' | '~' => true,
+            '=' | '+' => line_start,
+            '-' => line_start || matches!(next, Some('-' | '?')),
+            '/' => line_start || matches!(next, Some('/' | '*')),
+            '.' => number,
+            _ => false,
+        };
+        if special {
+            res.push('\\');
+        }
+        res.push(c);
+        number = c.is_ascii_digit() && (line_start || number);
+        line_start = c == '\n' || (line_start && (c == ' ' || c == '\t'));
+    }
+    res
+}
+
+/// Returns a Typst string literal
+pub fn string(text: &str) -> String {
+    let mut res = String::with_capacity(text.len() + 2);
+    res.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Returns true if the `typst` command set by `typst.command` can be run
+pub fn is_available(book: &Book) -> bool {
+    Command::new(book.options.get_str("typst.command").unwrap())
+        .arg("--version")
+        .output()
+        .map_or(false, |output| output.status.success())
+}
+
+pub struct Typst;
+
+impl BookRenderer for Typst {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{book_name}.typ"))
+    }
+
+    fn render(&self, book: &Book, to: &mut dyn io::Write) -> Result<()> {
+        let mut typst = TypstRenderer::new(book);
+        let result = typst.render_book()?;
+        to.write_all(result.as_bytes()).map_err(|e| {
+            Error::render(
+                &book.source,
+                lformat!("problem when writing Typst: {error}", error = e),
+            )
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typst_escape() {
+        assert_eq!(escape("#1 - a_b [c]"), "\\#1 - a\\_b \\[c\\]");
+        assert_eq!(escape("it's \"a/b\" = c+d"), "it's \"a/b\" = c+d");
+        assert_eq!(escape("- not a list"), "\\- not a list");
+        assert_eq!(escape("  = not a heading"), "  \\= not a heading");
+        assert_eq!(escape("1986. A great year"), "1986\\. A great year");
+        assert_eq!(escape("a -- b -? c"), "a \\-- b \\-? c");
+        assert_eq!(escape("a // b /* c"), "a \\// b \\/\\* c");
+        assert_eq!(string("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+    }
+}
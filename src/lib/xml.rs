@@ -0,0 +1,329 @@
+// Copyright (C) 2016-2022 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A minimal XML parser, tolerant enough to read the (X)HTML and package
+//! files found in EPUB files.
+
+/// HTML elements that never have content, even when they are not self-closed
+static VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// A node of an XML document
+#[derive(Debug, Clone, PartialEq)]
+pub enum XmlNode {
+    /// An element, with its attributes and children
+    Element(XmlElement),
+    /// Some text, with entities already decoded
+    Text(String),
+}
+
+/// An XML element
+#[derive(Debug, Clone, PartialEq)]
+pub struct XmlElement {
+    /// The (qualified) name of the element, e.g. `dc:title`
+    pub name: String,
+    /// The attributes of the element, in document order
+    pub attributes: Vec<(String, String)>,
+    /// The content of the element
+    pub children: Vec<XmlNode>,
+}
+
+impl XmlElement {
+    fn new(name: &str) -> XmlElement {
+        XmlElement {
+            name: name.to_owned(),
+            attributes: vec![],
+            children: vec![],
+        }
+    }
+
+    /// Returns the name of the element, without namespace prefix
+    pub fn local_name(&self) -> &str {
+        local_name(&self.name)
+    }
+
+    /// Returns the value of an attribute.
+    ///
+    /// `name` can either be a qualified name (e.g. `epub:type`) or a local one.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .or_else(|| {
+                self.attributes
+                    .iter()
+                    .find(|(key, _)| local_name(key) == name)
+            })
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns true if a whitespace-separated attribute (e.g. `class`) contains `value`
+    pub fn has_token(&self, attribute: &str, value: &str) -> bool {
+        self.attribute(attribute)
+            .map_or(false, |s| s.split_whitespace().any(|s| s == value))
+    }
+
+    /// Iterates over the children that are elements
+    pub fn elements(&self) -> impl Iterator<Item = &XmlElement> {
+        self.children.iter().filter_map(|node| match node {
+            XmlNode::Element(element) => Some(element),
+            XmlNode::Text(_) => None,
+        })
+    }
+
+    /// Returns the first descendant element with this local name
+    pub fn find(&self, name: &str) -> Option<&XmlElement> {
+        for element in self.elements() {
+            if element.local_name() == name {
+                return Some(element);
+            }
+            if let Some(found) = element.find(name) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Returns all descendant elements with this local name, in document order
+    pub fn find_all(&self, name: &str) -> Vec<&XmlElement> {
+        let mut result = vec![];
+        for element in self.elements() {
+            if element.local_name() == name {
+                result.push(element);
+            }
+            result.extend(element.find_all(name));
+        }
+        result
+    }
+
+    /// Returns the concatenated text content of this element
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.children {
+            match node {
+                XmlNode::Text(s) => text.push_str(s),
+                XmlNode::Element(element) => text.push_str(&element.text()),
+            }
+        }
+        text
+    }
+}
+
+/// Parses an XML document.
+///
+/// This never fails: ill-formed documents are read as well as possible.
+/// Returns a nameless root element containing the top-level nodes.
+pub fn parse(source: &str) -> XmlElement {
+    let mut stack = vec![XmlElement::new("")];
+    let mut rest = source;
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix("<!--") {
+            rest = skip_past(r, "-->");
+        } else if let Some(r) = rest.strip_prefix("<![CDATA[") {
+            let end = r.find("]]>").unwrap_or(r.len());
+            push_text(&mut stack, r[..end].to_owned());
+            rest = skip_past(&r[end..], "]]>");
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = skip_past(rest, ">");
+        } else if let Some(r) = rest.strip_prefix("</") {
+            let end = r.find('>').unwrap_or(r.len());
+            close(&mut stack, r[..end].trim());
+            rest = skip_past(&r[end..], ">");
+        } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_alphabetic()) {
+            let end = tag_end(rest);
+            let tag = &rest[1..end];
+            rest = skip_past(&rest[end..], ">");
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let element = parse_tag(tag);
+            if self_closing || VOID_ELEMENTS.contains(&element.local_name()) {
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .children
+                    .push(XmlNode::Element(element));
+            } else {
+                stack.push(element);
+            }
+        } else {
+            // Text, up to the next tag
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| c == '<')
+                .map_or(rest.len(), |(i, _)| i);
+            push_text(&mut stack, decode_entities(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    while stack.len() > 1 {
+        let element = stack.pop().unwrap();
+        stack
+            .last_mut()
+            .unwrap()
+            .children
+            .push(XmlNode::Element(element));
+    }
+    stack.pop().unwrap()
+}
+
+/// Replaces XML (and the most common HTML) entities by the corresponding characters
+pub fn decode_entities(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let entity = rest
+            .bytes()
+            .take(12)
+            .position(|b| b == b';')
+            .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end)));
+        if let Some((c, end)) = entity {
+            result.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            result.push('&');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Decodes an entity (without the leading '&' and trailing ';')
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(hex) = entity
+        .strip_prefix("#x")
+        .or_else(|| entity.strip_prefix("#X"))
+    {
+        return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+    }
+    if let Some(dec) = entity.strip_prefix('#') {
+        return dec.parse::<u32>().ok().and_then(char::from_u32);
+    }
+    let c = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "shy" => '\u{ad}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "copy" => '©',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// Returns the part of a qualified name after the namespace prefix
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Returns what follows the first occurrence of `pattern`, or an empty string
+fn skip_past<'a>(s: &'a str, pattern: &str) -> &'a str {
+    s.find(pattern).map_or("", |i| &s[i + pattern.len()..])
+}
+
+/// Returns the position of the '>' ending the tag starting `s`, ignoring quoted values
+fn tag_end(s: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return i,
+            _ => (),
+        }
+    }
+    s.len()
+}
+
+/// Parses the inside of a start tag, e.g. `a href="foo.xhtml"`
+fn parse_tag(tag: &str) -> XmlElement {
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let mut element = XmlElement::new(&tag[..name_end]);
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = rest[key_end..].trim_start();
+        let value = if let Some(r) = rest.strip_prefix('=') {
+            let r = r.trim_start();
+            let (value, r) = match r.chars().next() {
+                Some(q) if q == '"' || q == '\'' => {
+                    let end = r[1..].find(q).map_or(r.len(), |i| i + 1);
+                    (&r[1..end], r.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = r.find(char::is_whitespace).unwrap_or(r.len());
+                    (&r[..end], &r[end..])
+                }
+            };
+            rest = r.trim_start();
+            decode_entities(value)
+        } else {
+            String::new()
+        };
+        if !key.is_empty() {
+            element.attributes.push((key.to_owned(), value));
+        }
+    }
+    element
+}
+
+/// Adds some text to the current element
+fn push_text(stack: &mut [XmlElement], text: String) {
+    let children = &mut stack.last_mut().unwrap().children;
+    if let Some(XmlNode::Text(previous)) = children.last_mut() {
+        previous.push_str(&text);
+    } else {
+        children.push(XmlNode::Text(text));
+    }
+}
+
+/// Closes the last open element with this name, and all the elements opened after it.
+///
+/// End tags that don't match an open element are ignored.
+fn close(stack: &mut Vec<XmlElement>, name: &str) {
+    if let Some(pos) = stack.iter().skip(1).rposition(|e| e.name == name) {
+        while stack.len() > pos + 1 {
+            let element = stack.pop().unwrap();
+            stack
+                .last_mut()
+                .unwrap()
+                .children
+                .push(XmlNode::Element(element));
+        }
+    }
+}
@@ -16,13 +16,17 @@
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::error::{Error, Result};
+use crate::latex_log;
 
 use std::fs::{self, DirBuilder, File};
 use std::io;
 use std::io::Write;
 use std::ops::Drop;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Output};
+
+/// Maximum number of times LaTeX is run to get cross-references right
+const MAX_LATEX_RUNS: u32 = 5;
 
 /// Struct used to create zip (using filesystem and zip command)
 pub struct Zipper {
@@ -56,6 +60,11 @@ impl Zipper {
         })
     }
 
+    /// Returns the path of the temporary directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     /// writes a content to a temporary file
     pub fn write<P: AsRef<Path>>(&mut self, path: P, content: &[u8], add_args: bool) -> Result<()> {
         let path = path.as_ref();
@@ -105,7 +114,9 @@ This is forbidden because we are supposed \
     }
 
     /// Unzip a file and deletes it afterwards
-    #[cfg(feature = "odt")]
+    ///
+    /// This runs the `unzip` command, which must be installed: it is needed to
+    /// render ODT files and to import EPUB files.
     pub fn unzip(&mut self, file: &str) -> Result<()> {
         let output = Command::new("unzip")
             .current_dir(&self.path)
@@ -113,13 +124,19 @@ This is forbidden because we are supposed \
             .output()
             .map_err(|e| {
                 Error::zipper(lformat!(
-                    "failed to execute unzip on {file}: {error}",
+                    "failed to execute unzip on {file} (is the unzip command installed?): {error}",
                     file = file,
                     error = e
                 ))
-            });
-
-        output?;
+            })?;
+        // unzip exits with 1 on mere warnings
+        if !matches!(output.status.code(), Some(0) | Some(1)) {
+            return Err(Error::zipper(lformat!(
+                "failed to unzip {file}: {error}",
+                file = file,
+                error = String::from_utf8_lossy(&output.stderr)
+            )));
+        }
 
         fs::remove_file(self.path.join(file))
             .map_err(|_| Error::zipper(lformat!("failed to remove file {file}", file = file)))
@@ -149,26 +166,7 @@ This is forbidden because we are supposed \
         });
         let output = res_output?;
         if output.status.success() {
-            let mut file = File::open(self.path.join(in_file)).map_err(|_| {
-                debug!(
-                    "{}",
-                    lformat!(
-                        "could not open result of command '{command}'\n\
-                                           Command output:\n\
-                                           {output}'",
-                        command = command_name,
-                        output = String::from_utf8_lossy(&output.stderr)
-                    )
-                );
-                Error::zipper(lformat!(
-                    "could not open result of command '{command}'",
-                    command = command_name
-                ))
-            })?;
-            io::copy(&mut file, out).map_err(|_| {
-                Error::zipper(lformat!("error copying file '{file}'", file = in_file))
-            })?;
-
+            self.copy_result(&output, command_name, in_file, out)?;
             Ok(String::from_utf8_lossy(&output.stdout).into_owned())
         } else {
             debug!(
@@ -186,6 +184,35 @@ This is forbidden because we are supposed \
         }
     }
 
+    /// copy content of file output by a command to `out`
+    fn copy_result(
+        &self,
+        output: &Output,
+        command_name: &str,
+        in_file: &str,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let mut file = File::open(self.path.join(in_file)).map_err(|_| {
+            debug!(
+                "{}",
+                lformat!(
+                    "could not open result of command '{command}'\n\
+                                       Command output:\n\
+                                       {output}'",
+                    command = command_name,
+                    output = String::from_utf8_lossy(&output.stderr)
+                )
+            );
+            Error::zipper(lformat!(
+                "could not open result of command '{command}'",
+                command = command_name
+            ))
+        })?;
+        io::copy(&mut file, out)
+            .map_err(|_| Error::zipper(lformat!("error copying file '{file}'", file = in_file)))?;
+        Ok(())
+    }
+
     /// zip all files in zipper's tmp dir to a given file name and write to odt file
     #[cfg(feature = "odt")]
     pub fn generate_odt(&mut self, command_name: &str, odt_file: &mut dyn Write) -> Result<String> {
@@ -198,24 +225,55 @@ This is forbidden because we are supposed \
     }
 
     /// generate a pdf file into given file name
+    ///
+    /// LaTeX is run again as long as its log asks for it (e.g. to get cross-references
+    /// right), up to `MAX_LATEX_RUNS` times. The log is left in the temporary directory.
     pub fn generate_pdf(
         &mut self,
         command_name: &str,
         tex_file: &str,
         pdf_file: &mut dyn Write,
     ) -> Result<String> {
-        // first pass
         let mut command = Command::new(command_name);
         command.current_dir(&self.path).arg(tex_file);
-        let _ = command.output();
-
-        // second pass
-        let _ = command.output();
-
-        // third pass
-        // let mut command = Command::new(command_name);
-        // command.current_dir(&self.path);
-        // command.arg(tex_file);
+        let log_file = Path::new(tex_file).with_extension("log");
+        for run in 1..MAX_LATEX_RUNS {
+            let output = command.output().map_err(|e| {
+                debug!(
+                    "{}",
+                    lformat!(
+                        "output for command {name}:\n{error}",
+                        name = command_name,
+                        error = e
+                    )
+                );
+                Error::zipper(lformat!(
+                    "failed to run command '{name}'",
+                    name = command_name
+                ))
+            })?;
+            let log = fs::read_to_string(self.path.join(&log_file)).unwrap_or_default();
+            if !output.status.success() || !latex_log::needs_rerun(&log) {
+                debug!(
+                    "{}",
+                    lformat!(
+                        "{command} was run {n} time(s)",
+                        command = command_name,
+                        n = run
+                    )
+                );
+                return if output.status.success() {
+                    self.copy_result(&output, command_name, "result.pdf", pdf_file)?;
+                    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+                } else {
+                    Err(Error::zipper(lformat!(
+                        "{command} didn't return succesfully",
+                        command = command_name
+                    )))
+                };
+            }
+        }
+        // Last run, whatever the log says
         self.run_command(command, command_name, "result.pdf", pdf_file)
     }
 }
@@ -0,0 +1,77 @@
+{{=<< >>=}}// Mustache tags are delimited by << and >>, so braces can be used freely in Typst code
+#set document(title: [<<&title>>], author: <<&author_string>>)
+#set text(lang: "<<&typst_lang>>"<<#has_typst_region>>, region: "<<&typst_region>>"<</has_typst_region>><<#has_font_size>>, size: <<&font_size>>pt<</has_font_size>>)
+#set page(paper: "<<&paper>>", numbering: "1")
+#set par(justify: true)
+// Headers are numbered by Crowbook, according to rendering.chapter.template and co
+#set heading(numbering: none)
+
+// Functions used by the generated content; redefine them to change how it looks
+#let mdpart(title) = {
+  pagebreak(weak: true)
+  v(30%)
+  align(center, heading(level: 1, text(size: 1.3em, title)))
+  pagebreak(weak: true)
+}
+#let mdchapter(title) = {
+  pagebreak(weak: true)
+  v(10%)
+  heading(level: 1, title)
+  v(1em)
+}
+#let mdchaptersubtitle(body) = align(center, text(size: 1.2em, body))
+#let mdchapterauthor(body) = align(center, smallcaps(body))
+#let mdchapterdate(body) = align(center, emph(body))
+#let mdchapterepigraph(body) = align(right, block(width: 60%, align(left, emph(body))))
+#let mdblockquote(body) = quote(block: true, body)
+#let mdcodeblock(code, title: none, caption: none) = {
+  if title != none { block(below: 4pt, strong(title)) }
+  block(fill: luma(245), inset: 8pt, radius: 2pt, width: 100%, code)
+  if caption != none { align(center, emph(caption)) }
+}
+#let mdcalloutcolors = (
+  note: rgb("#0969da"),
+  tip: rgb("#1a7f37"),
+  important: rgb("#8250df"),
+  warning: rgb("#9a6700"),
+  caution: rgb("#cf222e"),
+)
+#let mdcallout(kind, title, body) = {
+  let color = mdcalloutcolors.at(kind)
+  block(fill: color.lighten(95%), stroke: (left: 3pt + color), inset: 8pt, width: 100%, {
+    text(fill: color, weight: "bold", title)
+    parbreak()
+    body
+  })
+}
+#let mdrule() = align(center, line(length: 30%, stroke: 0.5pt))
+#let mdscenebreak(ornament) = block(above: 1.5em, below: 1.5em, breakable: false, width: 100%, align(center, ornament))
+#let mdimage(img) = box(img)
+#let mdstandaloneimage(img) = align(center, block(width: 80%, img))
+#let mdtable(..args) = align(center, table(..args))
+
+<<&additional_code>>
+
+<<#typst_title>>
+#page(numbering: none, align(center + horizon)[
+  #text(size: 2em)[<<&title>>]
+  <<#has_subtitle>>
+
+  #text(size: 1.4em)[<<&subtitle>>]
+  <</has_subtitle>>
+
+  #v(3em)
+  #text(size: 1.4em)[<<&author>>]
+  <<#has_date>>
+
+  #v(1em)
+  <<&date>>
+  <</has_date>>
+])
+<</typst_title>>
+
+<<#inline_toc>>
+#outline()
+<</inline_toc>>
+
+<<&content>>
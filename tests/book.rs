@@ -1,4 +1,4 @@
-use crowbook::Book;
+use crowbook::{Book, Number};
 use std::io;
 
 #[test]
@@ -12,6 +12,10 @@ fn test_book() {
     .unwrap();
     book.render_format_to("html", &mut io::sink()).unwrap();
     book.render_format_to("tex", &mut io::sink()).unwrap();
+    book.render_format_to("md", &mut io::sink()).unwrap();
+    book.render_format_to("txt", &mut io::sink()).unwrap();
+    book.render_format_to("manuscript.tex", &mut io::sink())
+        .unwrap();
 }
 
 #[test]
@@ -22,3 +26,73 @@ fn book_example() {
     book.render_format_to("html", &mut io::sink()).unwrap();
     book.render_format_to("tex", &mut io::sink()).unwrap();
 }
+
+#[test]
+fn volumes() {
+    let mut book = Book::new();
+    book.load_file(format!(
+        "{}/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        "tests/volumes/series.book"
+    ))
+    .unwrap();
+    let files: Vec<&str> = book.chapters.iter().map(|c| c.filename.as_str()).collect();
+    assert_eq!(
+        files,
+        vec!["", "first/one.md", "first/two.md", "", "second/one.md"]
+    );
+    assert!(book.chapters[0].number.is_part());
+    assert!(book.chapters[3].number.is_part());
+    book.render_format_to("html", &mut io::sink()).unwrap();
+    book.render_format_to("tex", &mut io::sink()).unwrap();
+    book.render_format_to("md", &mut io::sink()).unwrap();
+
+    // Images of a volume are relative to its directory, also when the book
+    // is loaded from a relative path
+    let mut book = Book::new();
+    book.load_file("tests/volumes/series.book").unwrap();
+    book.render_format_to("html", &mut io::sink()).unwrap();
+    book.render_format_to("epub", &mut io::sink()).unwrap();
+
+    let mut book = Book::new();
+    assert!(book
+        .load_file(format!(
+            "{}/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            "tests/volumes/loop.book"
+        ))
+        .is_err());
+}
+
+#[test]
+fn txt_and_manuscript() {
+    let content = format!(
+        "# Title\n\n{}with a footnote.[^1]\n\n[^1]: A note.\n",
+        "word ".repeat(260)
+    );
+    let mut book = Book::new();
+    book.set_options(&[("txt.width", "40")]);
+    book.add_chapter_from_source(Number::Default, content.as_bytes(), false)
+        .unwrap();
+    let render = |book: &mut Book, format: &str| {
+        let mut res = vec![];
+        book.render_format_to(format, &mut res).unwrap();
+        String::from_utf8(res).unwrap()
+    };
+
+    let txt = render(&mut book, "txt");
+    assert!(txt.lines().all(|line| line.chars().count() <= 40));
+    assert!(txt.contains("Title\n====="));
+    assert!(txt.contains("footnote.[1]"));
+    assert!(txt.contains("\n[1] A note."));
+
+    book.set_options(&[("txt.footnotes", "inline"), ("txt.width", "0")]);
+    let txt = render(&mut book, "txt");
+    assert!(txt.contains("footnote. [A note.]"));
+    book.set_options(&[("txt.footnotes", "none")]);
+    assert!(!render(&mut book, "txt").contains("A note."));
+
+    // About 265 words, rounded to the nearest hundred
+    let tex = render(&mut book, "manuscript.tex");
+    assert!(tex.contains("300 words"));
+}